@@ -0,0 +1,229 @@
+use std::{
+    io::{Cursor, Read, Write},
+    num::NonZeroUsize,
+};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use silpkg::{sync::Pkg, Compression, EntryCompression, Flags};
+
+#[allow(dead_code)]
+#[path = "../tests/data/mod.rs"]
+mod data;
+
+const SMALL_FILE_COUNT: usize = 10_000;
+
+fn small_files() -> impl Iterator<Item = (String, &'static [u8])> {
+    data::BASIC_EXAMPLE_FILES
+        .iter()
+        .cycle()
+        .take(SMALL_FILE_COUNT)
+        .enumerate()
+        .map(|(i, (name, data))| (format!("{i}/{name}"), *data))
+}
+
+fn insert_all<'a>(
+    pkg: &mut Pkg<Cursor<Vec<u8>>>,
+    flags: Flags,
+    files: impl Iterator<Item = (String, &'a [u8])>,
+) {
+    for (name, data) in files {
+        pkg.insert(name, flags.clone())
+            .unwrap()
+            .write_all(data)
+            .unwrap();
+    }
+}
+
+fn parse(c: &mut Criterion) {
+    let mut pkg = Pkg::create(Cursor::new(vec![])).unwrap();
+    insert_all(&mut pkg, Flags::default(), small_files());
+    let storage = pkg.inner().get_ref().clone();
+
+    c.bench_function("parse 10k entries", |b| {
+        b.iter(|| Pkg::parse(Cursor::new(storage.as_slice())).unwrap())
+    });
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+
+    group.bench_function("10k small files", |b| {
+        b.iter_batched(
+            || Pkg::create(Cursor::new(vec![])).unwrap(),
+            |mut pkg| insert_all(&mut pkg, Flags::default(), small_files()),
+            BatchSize::LargeInput,
+        )
+    });
+
+    let (name, large) = data::generate_big_data().last().unwrap();
+    for (compression_name, compression) in [
+        ("raw", EntryCompression::None),
+        ("deflate", EntryCompression::Deflate(Compression::default())),
+    ] {
+        group.bench_function(format!("1MiB file ({compression_name})"), |b| {
+            b.iter_batched(
+                || Pkg::create(Cursor::new(vec![])).unwrap(),
+                |mut pkg| {
+                    insert_all(
+                        &mut pkg,
+                        Flags {
+                            compression: compression.clone(),
+                        },
+                        std::iter::once((name.clone(), large.as_slice())),
+                    )
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn extract(c: &mut Criterion) {
+    let mut group = c.benchmark_group("extract");
+    group.sample_size(20);
+
+    let files = data::generate_big_data().collect::<Vec<_>>();
+    for (compression_name, compression) in [
+        ("raw", EntryCompression::None),
+        ("deflate", EntryCompression::Deflate(Compression::default())),
+    ] {
+        let mut pkg = Pkg::create(Cursor::new(vec![])).unwrap();
+        insert_all(
+            &mut pkg,
+            Flags { compression },
+            files.iter().map(|(n, d)| (n.clone(), d.as_slice())),
+        );
+        let storage = pkg.inner().get_ref().clone();
+
+        for buffer_size in [8 * 1024, 256 * 1024] {
+            let mut pkg = Pkg::parse(Cursor::new(storage.as_slice())).unwrap();
+            pkg.set_buffer_size(NonZeroUsize::new(buffer_size).unwrap());
+
+            group.bench_function(
+                format!(
+                    "2MiB of files ({compression_name}, {}KiB buffer)",
+                    buffer_size / 1024
+                ),
+                |b| {
+                    let mut out = vec![];
+                    b.iter(|| {
+                        for (name, _) in files.iter() {
+                            out.clear();
+                            pkg.open(name).unwrap().read_to_end(&mut out).unwrap();
+                        }
+                    })
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+fn open(c: &mut Criterion) {
+    let mut group = c.benchmark_group("open 10k entries");
+
+    let mut pkg = Pkg::create(Cursor::new(vec![])).unwrap();
+    insert_all(&mut pkg, Flags::default(), small_files());
+    let paths = pkg.path_list();
+    let indices = paths
+        .iter()
+        .map(|path| pkg.index_of(path).unwrap())
+        .collect::<Vec<_>>();
+
+    let mut buf = [0; 1];
+    group.bench_function("by path", |b| {
+        b.iter(|| {
+            for path in paths.iter() {
+                pkg.open(path).unwrap().read_exact(&mut buf).unwrap();
+            }
+        })
+    });
+    group.bench_function("by index", |b| {
+        b.iter(|| {
+            for &index in indices.iter() {
+                pkg.open_index(index).unwrap().read_exact(&mut buf).unwrap();
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn extract_directory(c: &mut Criterion) {
+    let mut group = c.benchmark_group("extract directory");
+
+    // Spread the files over 10 directories so that every directory is interleaved with all the
+    // others in insertion order
+    let mut pkg = Pkg::create(Cursor::new(vec![])).unwrap();
+    insert_all(
+        &mut pkg,
+        Flags::default(),
+        small_files()
+            .enumerate()
+            .map(|(i, (name, data))| (format!("{}/{name}", i % 10), data)),
+    );
+    let storage = pkg.inner().get_ref().clone();
+    pkg.cluster_by_prefix(1).unwrap();
+    let clustered = pkg.inner().get_ref().clone();
+
+    for (name, storage) in [("insertion order", storage), ("clustered", clustered)] {
+        let mut pkg = Pkg::parse(Cursor::new(storage)).unwrap();
+        let mut paths = pkg
+            .paths()
+            .filter(|path| path.starts_with("3/"))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        paths.sort_by_key(|path| pkg.metadata(path).unwrap().data_offset);
+
+        group.bench_function(name, |b| {
+            let mut out = vec![];
+            b.iter(|| {
+                for path in paths.iter() {
+                    out.clear();
+                    pkg.open(path).unwrap().read_to_end(&mut out).unwrap();
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn repack(c: &mut Criterion) {
+    let files = data::combined_data().collect::<Vec<_>>();
+    let mut pkg = Pkg::create(Cursor::new(vec![])).unwrap();
+    insert_all(
+        &mut pkg,
+        Flags::default(),
+        files.iter().map(|(n, d)| (n.clone(), d.as_slice())),
+    );
+
+    // Remove every other entry to leave holes in the data region
+    for (name, _) in files.iter().step_by(2) {
+        pkg.remove(name).unwrap();
+    }
+    let storage = pkg.inner().get_ref().clone();
+
+    c.bench_function("repack fragmented", |b| {
+        b.iter_batched(
+            || Pkg::parse(Cursor::new(storage.clone())).unwrap(),
+            |mut pkg| pkg.repack().unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    parse,
+    insert,
+    extract,
+    extract_directory,
+    open,
+    repack
+);
+criterion_main!(benches);
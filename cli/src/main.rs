@@ -1,22 +1,23 @@
 use std::{
+    collections::HashMap,
     fs::File,
     io::{Read, Write},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
     process::ExitCode,
 };
 
 use anyhow::{bail, Context};
 use clap::Parser;
-use silpkg::{sync::Pkg, Flags};
+use silpkg::sync::Pkg;
 
 mod progress;
-use progress::{ProgressBar, ProgressBarStyle};
+use progress::{ProgressBar, ProgressBarStyle, ProgressWriter};
 mod spinner;
 use spinner::{Spinner, SpinnerStyle};
 
 const PROGRESS_BAR_STYLE: ProgressBarStyle = ProgressBarStyle { width: 60 };
 const SPINNER_STYLE: SpinnerStyle = SpinnerStyle::const_default();
-const COMPRESS_TMP_PATH: &str = "____silpkg_cli_compress_temporary_4729875987234";
 
 fn pkg_open_ro(path: &Path) -> Result<Pkg<File>, anyhow::Error> {
     Pkg::parse(File::open(path).context("Could not open pkg file")?)
@@ -34,12 +35,85 @@ fn pkg_open_rw(path: &Path) -> Result<Pkg<File>, anyhow::Error> {
     .context("Could not parse pkg file")
 }
 
+/// Returns `path` relative to `base` which must already be canonicalized.
+fn relative_to_base(path: &Path, base: &Path) -> Result<PathBuf, anyhow::Error> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("Could not resolve input path {}", path.display()))?;
+
+    match canonical.strip_prefix(base) {
+        Ok(relative) => Ok(relative.to_path_buf()),
+        Err(_) => bail!(
+            "Input path {} is not inside the base directory {}",
+            path.display(),
+            base.display()
+        ),
+    }
+}
+
 #[derive(clap::Args)]
 /// Lists paths contained in an archive
 struct List {
     pkg: PathBuf,
 }
 
+/// Returns the amount of space available to unprivileged users on the filesystem containing
+/// `path` or [`None`] if it can't be determined.
+#[cfg(unix)]
+fn available_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Returns whether both readers yield exactly the same bytes.
+fn same_contents(mut a: impl Read, mut b: impl Read) -> std::io::Result<bool> {
+    let mut buf_a = vec![0; 64 * 1024];
+    let mut buf_b = vec![0; 64 * 1024];
+
+    loop {
+        let count = a.read(&mut buf_a)?;
+        if count == 0 {
+            return Ok(b.read(&mut buf_b[..1])? == 0);
+        }
+
+        match b.read_exact(&mut buf_b[..count]) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        if buf_a[..count] != buf_b[..count] {
+            return Ok(false);
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+/// What to do when an extracted file already exists.
+enum OnConflict {
+    /// Stop with an error.
+    Error,
+    /// Replace the existing file.
+    Overwrite,
+    /// Leave the existing file as is.
+    Skip,
+    /// Leave the existing file as is if it has the same contents, otherwise replace it.
+    SkipIdentical,
+}
+
 #[derive(clap::Args)]
 /// Extracts paths from an archive
 struct Extract {
@@ -47,8 +121,24 @@ struct Extract {
     output: PathBuf,
 
     /// If an extracted file conflicts with an existing file, overwrite it.
-    #[arg(short, long)]
+    ///
+    /// This is the same as `--on-conflict overwrite`.
+    #[arg(short, long, conflicts_with = "on_conflict")]
     overwrite: bool,
+
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = OnConflict::Error)]
+    /// What to do if an extracted file conflicts with an existing file.
+    on_conflict: OnConflict,
+
+    #[arg(short, long)]
+    /// Restore file modification times if they were stored in the archive.
+    preserve_mtime: bool,
+
+    #[arg(long)]
+    /// Skip empty entries whose path ends with a `/` instead of creating directories for them.
+    ///
+    /// Some tools store these entries to mark directories.
+    skip_directory_markers: bool,
 }
 
 #[derive(clap::Args)]
@@ -75,6 +165,12 @@ struct Add {
     /// If the archive already contains an entry with the same name, overwrite it.
     overwrite: bool,
 
+    #[arg(short, long)]
+    /// Store file modification times in the archive.
+    ///
+    /// PKG archives do not support modification times so they will be stored in a separate entry.
+    preserve_mtime: bool,
+
     #[arg(
         short,
         long = "compress",
@@ -82,6 +178,19 @@ struct Add {
     )]
     /// The compression level to use when inserting files, if not specified files are not compressed.
     compression_level: Option<u32>,
+
+    #[arg(short = 'x', long, value_name = "GLOB")]
+    /// Skip files and directories matching this pattern, can be specified multiple times.
+    ///
+    /// Patterns are matched case-insensitively against both the path that would be stored in the
+    /// archive and the file name alone, so for example both `*.tmp` and `.git` work as expected.
+    exclude: Vec<String>,
+
+    #[arg(short = 'C', long = "base", value_name = "DIR")]
+    /// Store paths relative to this directory instead of as they were specified.
+    ///
+    /// All input files have to be inside this directory.
+    base: Option<PathBuf>,
 }
 
 #[derive(clap::Args)]
@@ -93,6 +202,31 @@ struct Compress {
     )]
     /// The compression level to use when inserting files, if not specified files are not compressed
     compression_level: u32,
+
+    #[arg(short, long)]
+    /// Don't recompress entries that are already compressed.
+    skip_compressed: bool,
+
+    #[arg(short, long, value_name = "BYTES", default_value_t = 0)]
+    /// Don't compress entries smaller than this many bytes.
+    min_size: u32,
+
+    #[arg(short, long, value_name = "N", default_value_t = NonZeroUsize::MIN)]
+    /// Compress entries using this many threads.
+    jobs: NonZeroUsize,
+}
+
+#[derive(clap::Args)]
+/// Stores compressed files in an archive uncompressed
+struct Decompress {
+    pkg: PathBuf,
+    #[arg(required_unless_present = "all", conflicts_with = "all")]
+    /// Paths of the files to decompress
+    paths: Vec<String>,
+
+    #[arg(short, long)]
+    /// Decompress all the files in the archive and then repack it.
+    all: bool,
 }
 
 #[derive(clap::Args)]
@@ -110,6 +244,7 @@ enum Command {
     Cat(Cat),
     Add(Add),
     Compress(Compress),
+    Decompress(Decompress),
     Rename(Rename),
 }
 
@@ -141,43 +276,122 @@ fn real_main() -> Result<ExitCode, anyhow::Error> {
                 bail!("Output path already exists and is not a directory");
             }
 
-            let mut paths = pkg.paths().cloned().collect::<Vec<String>>();
+            let required = pkg.total_uncompressed_size();
+            match available_space(&extract_opts.output) {
+                Some(available) if available < required => log::warn!(
+                    "Extracting needs up to {required} bytes but only {available} are available"
+                ),
+                _ => {}
+            }
+
+            let mtimes = if extract_opts.preserve_mtime {
+                pkg.modification_times()
+                    .context("Could not read modification times")?
+            } else {
+                Default::default()
+            };
+
+            let mut paths = pkg.path_list();
             paths.sort();
-            let mut bar = ProgressBar::new(PROGRESS_BAR_STYLE, paths.len(), "".to_string());
+            let total_size = paths
+                .iter()
+                .map(|path| u64::from(pkg.metadata(path).unwrap().uncompressed_size))
+                .sum();
+            let mut bar = ProgressBar::new(PROGRESS_BAR_STYLE, total_size, "".to_string());
+            let on_conflict = match extract_opts.overwrite {
+                true => OnConflict::Overwrite,
+                false => extract_opts.on_conflict,
+            };
 
             for path in paths {
+                let out = extract_opts.output.join(path.clone());
+                let size = u64::from(pkg.metadata(&path).unwrap().uncompressed_size);
+
+                if pkg.is_directory_marker(&path) {
+                    if !extract_opts.skip_directory_markers {
+                        std::fs::create_dir_all(&out).with_context(|| {
+                            format!("Could not create output directory {}", out.display())
+                        })?;
+                    }
+                    continue;
+                }
+
+                let skip = match on_conflict {
+                    OnConflict::Error | OnConflict::Overwrite => false,
+                    OnConflict::Skip => out.exists(),
+                    OnConflict::SkipIdentical => match std::fs::metadata(&out) {
+                        // Comparing sizes first avoids reading files that obviously differ
+                        Ok(metadata) if metadata.is_file() && metadata.len() == size => {
+                            same_contents(
+                                pkg.open(&path)?,
+                                File::open(&out).with_context(|| {
+                                    format!("Could not read existing file {}", out.display())
+                                })?,
+                            )?
+                        }
+                        _ => false,
+                    },
+                };
+                if skip {
+                    bar.paused(|| eprintln!("\x1b[1mSkipping\x1b[0m {path}"));
+                    bar.advance(size);
+                    continue;
+                }
+
                 bar.paused(|| {
                     eprintln!("\x1b[1mExtracting\x1b[0m {path}...");
                 });
-                let out = extract_opts.output.join(path.clone());
                 std::fs::create_dir_all(out.parent().unwrap())?;
-                std::io::copy(&mut pkg.open(&path)?, &mut {
+                let mut file = {
                     let mut opts = std::fs::OpenOptions::new();
                     opts.write(true);
 
-                    if extract_opts.overwrite {
-                        opts.create(true);
-                    } else {
+                    if on_conflict == OnConflict::Error {
                         opts.create_new(true);
+                    } else {
+                        opts.create(true).truncate(true);
                     }
 
                     opts.open(&out)
                         .with_context(|| format!("Could not write output file {}", out.display()))?
-                })?;
+                };
+                std::io::copy(
+                    &mut pkg.open(&path)?,
+                    &mut ProgressWriter::new(&mut file, &mut bar),
+                )?;
+                if let Some(mtime) = mtimes.get(&path) {
+                    file.set_modified(*mtime).with_context(|| {
+                        format!("Could not set modification time of {}", out.display())
+                    })?;
+                }
                 bar.paused(|| {
                     eprint!("\x1b[1A\x1b[2K");
                     println!("{path}");
                 });
-                bar.inc();
             }
             bar.finish();
         }
         Command::Cat(Cat { pkg, file }) => {
             let mut pkg = pkg_open_ro(&pkg)?;
 
-            std::io::copy(&mut pkg.open(&file)?, &mut std::io::stdout())?;
+            pkg.extract_entry(&file, &mut std::io::stdout())?;
         }
         Command::Add(add_opts) => {
+            let base = match add_opts.base {
+                Some(base) => {
+                    let base = base.canonicalize().with_context(|| {
+                        format!("Could not resolve base directory {}", base.display())
+                    })?;
+
+                    for path in add_opts.files.iter() {
+                        relative_to_base(path, &base)?;
+                    }
+
+                    Some(base)
+                }
+                None => None,
+            };
+
             let mut pkg = {
                 if add_opts.pkg.exists() {
                     pkg_open_rw(&add_opts.pkg)?
@@ -191,11 +405,57 @@ fn real_main() -> Result<ExitCode, anyhow::Error> {
             };
 
             let mut bar = Spinner::new(SPINNER_STYLE.clone(), "Adding files");
+            let mut mtimes = vec![];
+
+            let exclude = add_opts
+                .exclude
+                .iter()
+                .map(|pattern| {
+                    glob::Pattern::new(pattern)
+                        .with_context(|| format!("Invalid exclude pattern {pattern}"))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let stored_path = |path: &Path| -> Result<PathBuf, anyhow::Error> {
+                match &base {
+                    Some(base) => relative_to_base(path, base),
+                    None => Ok(path.to_path_buf()),
+                }
+            };
+
+            let is_excluded = |path: &Path| -> bool {
+                const MATCH_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+                    case_sensitive: false,
+                    require_literal_separator: false,
+                    require_literal_leading_dot: false,
+                };
+
+                let stored = match stored_path(path) {
+                    Ok(stored) => stored,
+                    // This will be reported when trying to add the file
+                    Err(_) => return false,
+                };
+
+                exclude.iter().any(|pattern| {
+                    pattern.matches_path_with(&stored, MATCH_OPTIONS)
+                        || path.file_name().is_some_and(|name| {
+                            pattern.matches_path_with(Path::new(name), MATCH_OPTIONS)
+                        })
+                })
+            };
 
             let mut add_one = |path: &Path| -> Result<(), anyhow::Error> {
-                let path_str = path.to_str().filter(|x| x.is_ascii()).with_context(|| {
-                    format!("Input file path {} is not valid ASCII", path.display())
-                })?;
+                let stored_path = stored_path(path)?;
+                let path_str =
+                    stored_path
+                        .to_str()
+                        .filter(|x| x.is_ascii())
+                        .with_context(|| {
+                            format!(
+                                "Input file path {} is not valid ASCII",
+                                stored_path.display()
+                            )
+                        })?;
 
                 bar.paused(|| {
                     eprint!("\x1b[1mAdding\x1b[0m ",);
@@ -211,25 +471,29 @@ fn real_main() -> Result<ExitCode, anyhow::Error> {
                     pkg.remove(path_str)?;
                 }
 
-                let mut writer = pkg
-                    .insert(
-                        path_str.to_string(),
-                        silpkg::Flags {
-                            compression: add_opts
-                                .compression_level
-                                .map_or(silpkg::EntryCompression::None, |x| {
-                                    silpkg::EntryCompression::Deflate(silpkg::Compression::new(x))
-                                }),
-                        },
-                    )
-                    .with_context(|| format!("Could not add {path_str} to archive"))?;
-
-                std::io::copy(
-                    &mut std::fs::File::open(path)
-                        .with_context(|| "Could not open input file {file}")?,
-                    &mut writer,
+                let mut file = std::fs::File::open(path)
+                    .with_context(|| format!("Could not open input file {}", path.display()))?;
+                pkg.insert_from(
+                    path_str.to_string(),
+                    silpkg::Flags {
+                        compression: add_opts
+                            .compression_level
+                            .map_or(silpkg::EntryCompression::None, |x| {
+                                silpkg::EntryCompression::Deflate(silpkg::Compression::new(x))
+                            }),
+                    },
+                    &mut file,
                 )
-                .with_context(|| format!("Could not write {path_str} to archive"))?;
+                .with_context(|| format!("Could not add {path_str} to archive"))?;
+
+                if add_opts.preserve_mtime {
+                    mtimes.push((
+                        path_str.to_string(),
+                        file.metadata()?.modified().with_context(|| {
+                            format!("Could not read modification time of {path_str}")
+                        })?,
+                    ));
+                }
 
                 log::trace!("done with {path_str}");
 
@@ -243,11 +507,18 @@ fn real_main() -> Result<ExitCode, anyhow::Error> {
             };
 
             for path in add_opts.files.into_iter() {
+                if is_excluded(&path) {
+                    continue;
+                }
+
                 let ft = path.metadata()?.file_type();
                 if ft.is_file() {
                     add_one(&path)?;
                 } else if ft.is_dir() {
-                    for r in walkdir::WalkDir::new(path).into_iter() {
+                    for r in walkdir::WalkDir::new(path)
+                        .into_iter()
+                        .filter_entry(|entry| !is_excluded(entry.path()))
+                    {
                         let entry = r?;
                         if entry.file_type().is_dir() {
                             continue;
@@ -265,6 +536,21 @@ fn real_main() -> Result<ExitCode, anyhow::Error> {
 
             bar.finish_with("done");
 
+            if add_opts.preserve_mtime {
+                let mut all_mtimes = pkg
+                    .modification_times()
+                    .context("Could not read existing modification times")?;
+                all_mtimes.extend(mtimes);
+                pkg.set_modification_times(
+                    all_mtimes
+                        .iter()
+                        .filter(|(path, _)| pkg.contains(path))
+                        .map(|(path, time)| (path.as_str(), *time))
+                        .collect::<Vec<_>>(),
+                )
+                .context("Could not store modification times")?;
+            }
+
             if add_opts.repack {
                 let bar = Spinner::new(SPINNER_STYLE.clone(), "Repacking");
 
@@ -276,39 +562,68 @@ fn real_main() -> Result<ExitCode, anyhow::Error> {
         Command::Compress(compress_opts) => {
             let mut pkg = pkg_open_rw(&compress_opts.pkg)?;
 
-            let mut paths = pkg.paths().cloned().collect::<Vec<_>>();
+            let mut paths = pkg.path_list();
+            if compress_opts.skip_compressed {
+                paths.retain(|path| pkg.is_compressed(path) == Some(false));
+            }
             paths.sort();
-            let mut bar = ProgressBar::new(PROGRESS_BAR_STYLE, paths.len(), "".to_string());
+            let total_size = paths
+                .iter()
+                .map(|path| u64::from(pkg.metadata(path).unwrap().uncompressed_size))
+                .sum();
+            let mut bar = ProgressBar::new(PROGRESS_BAR_STYLE, total_size, "".to_string());
+
+            let mut sizes = HashMap::new();
+            for path in paths.iter() {
+                let info = pkg.metadata(path).unwrap();
+                if info.uncompressed_size < compress_opts.min_size {
+                    bar.advance(info.uncompressed_size.into());
+                } else {
+                    sizes.insert(path.as_str(), u64::from(info.uncompressed_size));
+                }
+            }
 
-            let mut tmp = vec![];
-            for path in paths {
-                bar.paused(|| {
-                    eprintln!("\x1b[1mCompressing\x1b[0m {path}...");
-                });
+            let to_compress = paths
+                .iter()
+                .map(String::as_str)
+                .filter(|path| sizes.contains_key(path))
+                .collect::<Vec<_>>();
+            pkg.compress_entries(
+                &to_compress,
+                silpkg::Compression::new(compress_opts.compression_level),
+                compress_opts.jobs,
+                |path| {
+                    bar.paused(|| println!("{path}"));
+                    bar.advance(sizes[path]);
+                },
+            )
+            .context("Could not compress entries")?;
+            bar.finish();
 
-                pkg.open(&path)?.read_to_end(&mut tmp)?;
-                pkg.insert(
-                    COMPRESS_TMP_PATH.to_string(),
-                    Flags {
-                        compression: silpkg::EntryCompression::Deflate(silpkg::Compression::new(
-                            compress_opts.compression_level,
-                        )),
-                    },
-                )?
-                .write_all(&tmp)
-                .with_context(|| format!("Could not compress {path}"))?;
-                pkg.replace(COMPRESS_TMP_PATH, path.clone())?;
-                tmp.clear();
+            pkg.repack()?;
+        }
+        Command::Decompress(opts) => {
+            let mut pkg = pkg_open_rw(&opts.pkg)?;
 
-                bar.paused(|| {
-                    eprint!("\x1b[1F\x1b[2K");
-                    println!("{path}");
-                });
-                bar.inc();
+            let mut paths = match opts.all {
+                true => pkg.compressed_paths().map(str::to_string).collect(),
+                false => opts.paths,
+            };
+            paths.sort();
+
+            for path in paths {
+                if pkg.is_compressed(&path) == Some(false) {
+                    continue;
+                }
+
+                pkg.decompress(&path)
+                    .with_context(|| format!("Could not decompress {path}"))?;
+                println!("{path}");
             }
-            bar.finish();
 
-            pkg.repack()?;
+            if opts.all {
+                pkg.repack()?;
+            }
         }
         Command::Rename(opts) => {
             let mut pkg = pkg_open_rw(&opts.pkg)?;
@@ -1,3 +1,5 @@
+use std::io::Write;
+
 pub struct ProgressBarStyle {
     pub width: usize,
 }
@@ -5,28 +7,63 @@ pub struct ProgressBarStyle {
 pub struct ProgressBar {
     style: ProgressBarStyle,
     message: String,
-    current_progress: usize,
-    max_progress: usize,
+    current_progress: u64,
+    max_progress: u64,
+    last_drawn: Option<(usize, String)>,
 }
 
 const BLOCKS: &[&str] = &["▏", "▎", "▍", "▌", "▋", "▊", "▉", "█"];
 
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut value = bytes as f64 / 1024.;
+    let mut unit = 0;
+    while value >= 1024. && unit < UNITS.len() - 1 {
+        value /= 1024.;
+        unit += 1;
+    }
+
+    format!("{value:.1} {}", UNITS[unit])
+}
+
 impl ProgressBar {
-    pub fn new(style: ProgressBarStyle, max_progress: usize, message: String) -> Self {
-        let bar = Self {
+    /// Creates a progress bar that tracks `max_progress` bytes.
+    pub fn new(style: ProgressBarStyle, max_progress: u64, message: String) -> Self {
+        let mut bar = Self {
             style,
             message,
             current_progress: 0,
             max_progress,
+            last_drawn: None,
         };
         bar.redraw();
         bar
     }
 
-    fn redraw(&self) {
-        let progress =
-            (self.current_progress * self.style.width * BLOCKS.len()) / self.max_progress;
+    fn progress(&self) -> usize {
+        let total = (self.style.width * BLOCKS.len()) as u64;
+        if self.max_progress == 0 {
+            return total as usize;
+        }
+
+        (self.current_progress.min(self.max_progress) as u128 * total as u128
+            / self.max_progress as u128) as usize
+    }
 
+    fn status(&self) -> String {
+        format!(
+            "{}/{}",
+            format_bytes(self.current_progress),
+            format_bytes(self.max_progress)
+        )
+    }
+
+    fn draw(&self, progress: usize, status: &str) {
         if !self.message.is_empty() {
             eprint!("\x1b[1m{}\x1b[0m▕", self.message);
         }
@@ -42,17 +79,27 @@ impl ProgressBar {
         for _ in (full + (left > 0) as usize)..self.style.width {
             eprint!(" ");
         }
-        eprint!("▏ {}/{}\r", self.current_progress, self.max_progress);
+        eprint!("▏ {status}\r");
+    }
+
+    fn redraw(&mut self) {
+        let current = (self.progress(), self.status());
+        if self.last_drawn.as_ref() != Some(&current) {
+            self.draw(current.0, &current.1);
+            self.last_drawn = Some(current);
+        }
     }
 
     pub fn paused(&self, func: impl FnOnce()) {
         eprint!("\x1b[2K");
         func();
-        self.redraw();
+        if let Some((progress, status)) = &self.last_drawn {
+            self.draw(*progress, status);
+        }
     }
 
-    pub fn inc(&mut self) {
-        self.current_progress += 1;
+    pub fn advance(&mut self, bytes: u64) {
+        self.current_progress += bytes;
         self.redraw()
     }
 
@@ -60,3 +107,27 @@ impl ProgressBar {
         eprintln!();
     }
 }
+
+/// A writer that advances a [`ProgressBar`] by the number of bytes written through it.
+pub struct ProgressWriter<'a, W> {
+    inner: W,
+    bar: &'a mut ProgressBar,
+}
+
+impl<'a, W> ProgressWriter<'a, W> {
+    pub fn new(inner: W, bar: &'a mut ProgressBar) -> Self {
+        Self { inner, bar }
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bar.advance(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
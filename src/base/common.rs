@@ -1,9 +1,59 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+
 pub const MAGIC: &[u8] = b"PKG\n";
 pub const HEADER_SIZE: u64 = 16;
 pub const ENTRY_SIZE: u64 = 20;
 
 pub const BUFFER_SIZE: u64 = 8192;
 
+/// Path of the entry used to store modification times of other entries.
+///
+/// The PKG format has no place to store modification times, so when they are requested via
+/// [`Pkg::set_modification_times`] they are written into this entry instead. It is not listed by
+/// [`Pkg::paths`], [`Pkg::entries`] or anything else that lists entries, like [`Pkg::tree`] or
+/// [`Pkg::check_truncation`], but can still be opened like any other entry.
+///
+/// [`Pkg::set_modification_times`]: crate::sync::Pkg::set_modification_times
+/// [`Pkg::paths`]: crate::sync::Pkg::paths
+/// [`Pkg::entries`]: crate::sync::Pkg::entries
+/// [`Pkg::tree`]: crate::sync::Pkg::tree
+/// [`Pkg::check_truncation`]: crate::sync::Pkg::check_truncation
+pub const MTIME_ENTRY_PATH: &str = ".silpkg-mtimes";
+
+/// Returns whether `path` can be stored in the null terminated ASCII path region.
+pub fn is_valid_path(path: &str) -> bool {
+    !path.is_empty() && path.bytes().all(|b| b.is_ascii() && b != 0)
+}
+
+/// Returns the path of `subpath` inside the directory `prefix`.
+///
+/// Exactly one `/` separates the two, an empty `prefix` refers to the root of the archive.
+pub fn join_path(prefix: &str, subpath: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    let subpath = subpath.trim_start_matches('/');
+    match prefix.is_empty() {
+        true => subpath.to_string(),
+        false => format!("{prefix}/{subpath}"),
+    }
+}
+
+/// Returns the directory containing `path` cut off after its first `depth` components.
+///
+/// Paths without a `/` are at the root of the archive, their directory is empty.
+pub fn directory_prefix(path: &str, depth: usize) -> &str {
+    let directory = path.rsplit_once('/').map_or("", |(directory, _)| directory);
+    match depth {
+        0 => "",
+        _ => directory
+            .match_indices('/')
+            .nth(depth - 1)
+            .map_or(directory, |(i, _)| &directory[..i]),
+    }
+}
+
 pub fn pkg_path_hash(path: &str) -> u32 {
     let mut hash: u32 = 0;
     for mut c in path.chars() {
@@ -1,4 +1,4 @@
-use alloc::{string::String, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
 #[cfg(feature = "std")]
 use std::io::SeekFrom as StdSeekFrom;
 
@@ -94,25 +94,64 @@ impl From<ReadSeekWriteRequest> for ReadSeekWriteTruncateRequest {
 
 #[derive(Default)]
 pub enum Response {
-    // TODO: Transient borrow, maybe this can be worked around using pointers?
-    Read(Vec<u8>),
+    Read(ReadData),
     Seek(u64),
     Written(usize),
     #[default]
     None,
 }
 
+/// The data read by a driver in response to a read request.
+///
+/// Drivers usually reuse a single buffer for every read, so this points into it without
+/// borrowing it and can only be created unsafely with [`ReadData::new`].
+pub struct ReadData {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl ReadData {
+    /// Wraps `data` so that it can be passed back to a coroutine in a [`Response::Read`].
+    ///
+    /// # Safety
+    /// `data` must stay valid and unmodified until the coroutine the response is passed to makes
+    /// its next request, finishes or is dropped.
+    pub unsafe fn new(data: &[u8]) -> Self {
+        Self {
+            ptr: data.as_ptr(),
+            len: data.len(),
+        }
+    }
+}
+
+// The data returned by a read request, see ReadData.
+//
+// This must not be used after the next request was made, which is easy to uphold as long as it's
+// consumed right after being received.
+struct ReadBuf {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl core::ops::Deref for ReadBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
 impl Response {
-    fn assert_into_sized_read(self, size: u64) -> Vec<u8> {
+    fn assert_into_sized_read(self, size: u64) -> ReadBuf {
         let value = self.assert_into_read();
         assert_eq!(value.len() as u64, size);
 
         value
     }
 
-    fn assert_into_read(self) -> Vec<u8> {
+    fn assert_into_read(self) -> ReadBuf {
         match self {
-            Response::Read(value) => value,
+            Response::Read(ReadData { ptr, len }) => ReadBuf { ptr, len },
             _ => panic!("Response::assert_into_read on non Response::Read response"),
         }
     }
@@ -225,6 +264,197 @@ pub struct Flags {
     pub compression: EntryCompression,
 }
 
+/// Options that change how archives are parsed.
+#[derive(Debug, Default, Clone)]
+pub struct ParseOptions {
+    /// Whether to check that the hash stored in each entry matches the hash of its path.
+    ///
+    /// A mismatch means the archive is either corrupted or was created with an incompatible hash
+    /// function, in which case parsing will fail with [`ParseError::HashMismatch`].
+    pub verify_hashes: bool,
+    /// Whether to accept entries with flag bits that silpkg doesn't recognise.
+    ///
+    /// Such entries are normally rejected with [`ParseError::UnrecognisedEntryFlags`]. With this
+    /// option they are kept in the archive and their flags are preserved when the archive is
+    /// modified, but trying to open them for reading fails with
+    /// [`OpenError::UnrecognisedEntryFlags`].
+    pub preserve_unknown_flags: bool,
+    /// Whether to accept archives whose header or entries are larger than expected.
+    ///
+    /// Such archives are normally rejected with [`ParseError::MismatchedHeaderSize`] or
+    /// [`ParseError::MismatchedEntrySize`]. With this option the known fields at the start of the
+    /// header and each entry are read and the rest is skipped, which makes it possible to list
+    /// and read archives using a backwards compatible extension of the format. Archives parsed
+    /// this way cannot be modified if they actually use a larger header or entry size than
+    /// [`format`](Self::format).
+    pub allow_extended_layout: bool,
+    /// The header and entry sizes the archive is expected to use, see [`Format`].
+    ///
+    /// Archives using a different layout are rejected with
+    /// [`ParseError::MismatchedHeaderSize`] or [`ParseError::MismatchedEntrySize`], unless
+    /// [`allow_extended_layout`](Self::allow_extended_layout) is set and they are larger.
+    /// Formats that aren't [supported](Format::is_supported) fail with
+    /// [`ParseError::UnsupportedFormat`].
+    pub format: Format,
+    /// Whether to reject archives that contain data after the end of the entry that ends last.
+    ///
+    /// Such data is left behind by removals and [`Pkg::preallocate`] until the archive is
+    /// repacked, or appended by other tools. With this option parsing fails with
+    /// [`ParseError::TrailingGarbage`] instead, which is useful to make sure an archive was
+    /// cleanly repacked before being distributed.
+    ///
+    /// [`Pkg::preallocate`]: crate::sync::Pkg::preallocate
+    pub reject_trailing_garbage: bool,
+    /// Whether to drop entries whose data extends past the end of the storage.
+    ///
+    /// This happens when an archive was truncated, for example by an interrupted download.
+    /// Such entries are normally kept and silently yield less data when read. With this option
+    /// they are left out of the parsed archive instead, so that the intact entries can be
+    /// recovered, and their paths are reported by [`Pkg::dropped_entries`].
+    ///
+    /// The dropped entries are only left out in memory, they stay in the entry list in the
+    /// storage until the archive is [repacked]. Data inserted before that could end up where a
+    /// dropped entry claims its data to be, so if any entries were dropped the archive can't be
+    /// modified in any other way until it has been repacked.
+    ///
+    /// [`Pkg::dropped_entries`]: crate::sync::Pkg::dropped_entries
+    /// [repacked]: crate::sync::Pkg::repack
+    pub repair_truncated: bool,
+    /// The format of the data of compressed entries, see [`DeflateFormat`].
+    pub deflate_format: DeflateFormat,
+    /// Whether to skip reading the path region.
+    ///
+    /// This avoids decoding every path and building the path lookup table, which makes
+    /// parsing archives with a lot of entries faster when only their sizes and offsets are
+    /// needed. Entries can then only be found through [`EntryInfo::path_hash`], their paths are
+    /// empty, [`verify_hashes`](Self::verify_hashes) is ignored and the archive cannot be
+    /// modified.
+    pub skip_paths: bool,
+}
+
+/// The sizes of the header and of each entry in the entry list of an archive.
+///
+/// SIL archives always use the [`STANDARD`](Self::STANDARD) 16 byte header and 20 byte entries,
+/// this exists for working with related formats that only differ in these sizes. Such formats
+/// are [supported](Self::is_supported) as long as they are at least as large as the standard
+/// ones and store the standard fields at the start of the header and of each entry. The
+/// remaining bytes are ignored when reading and written as zeros whenever silpkg writes the
+/// header or an entry, so any information a format keeps in them is lost on modification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    /// Size of the header in bytes.
+    pub header_size: u16,
+    /// Size of a single entry in the entry list in bytes.
+    pub entry_size: u16,
+}
+
+impl Format {
+    /// The layout used by SIL archives, a 16 byte header followed by 20 byte entries.
+    pub const STANDARD: Format = Format {
+        header_size: HEADER_SIZE as u16,
+        entry_size: ENTRY_SIZE as u16,
+    };
+
+    /// Returns whether silpkg can read and write archives using this format.
+    pub fn is_supported(&self) -> bool {
+        self.header_size as u64 >= HEADER_SIZE && self.entry_size as u64 >= ENTRY_SIZE
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+/// How much extra space is reserved when the path region runs out of space for a new path.
+///
+/// Growing the path region requires moving the whole data region back, so reserving more space
+/// at once makes insertions cheaper at the cost of leaving more unused space in the archive until
+/// it is repacked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathRegionGrowth {
+    /// Reserve this many bytes in addition to the path being inserted.
+    Fixed(u32),
+    /// Reserve this percentage of the current path region size in addition to the path being
+    /// inserted.
+    ///
+    /// This keeps the number of data region moves logarithmic in the number of insertions, which
+    /// is preferable when inserting a lot of entries into a single archive.
+    Proportional(u32),
+}
+
+impl PathRegionGrowth {
+    fn padding(self, current_size: u32) -> u32 {
+        match self {
+            PathRegionGrowth::Fixed(padding) => padding,
+            PathRegionGrowth::Proportional(percent) => {
+                (current_size as u64 * percent as u64 / 100).min(u32::MAX as u64) as u32
+            }
+        }
+    }
+}
+
+impl Default for PathRegionGrowth {
+    /// Reserves enough space for 32 paths of 30 characters.
+    fn default() -> Self {
+        Self::Fixed(30 * 32)
+    }
+}
+
+/// Which regions of an archive have to be grown to insert a new entry, see
+/// [`Pkg::insert_would_grow`](crate::sync::Pkg::insert_would_grow).
+///
+/// Growing either region moves the data of all entries that are in the way to the end of the
+/// archive, which makes the insertion take time proportional to their size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GrowthKind {
+    /// There is a free entry slot and the path fits in the path region, nothing has to be moved.
+    None,
+    /// There is no free entry slot so the entry list has to be grown.
+    ///
+    /// This also grows the path region enough to fit the new path.
+    EntryList,
+    /// The path doesn't fit in the path region so it has to be grown.
+    PathRegion,
+    /// The entry list has to be grown and the path doesn't fit in the path region even after
+    /// the extra space reserved along with the new entry slots.
+    EntryListAndPathRegion,
+}
+
+impl GrowthKind {
+    /// Returns whether any region has to be grown.
+    pub fn grows(self) -> bool {
+        self != GrowthKind::None
+    }
+}
+
+/// The format of the data of deflate compressed entries.
+///
+/// Archives don't record which format their entries use, so it has to be known up front. Reading
+/// entries using the wrong format results in corrupted data, see
+/// [`EntryReader::integrity_ok`](crate::sync::EntryReader::integrity_ok).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeflateFormat {
+    /// Deflate data wrapped in a zlib header and an Adler-32 checksum.
+    ///
+    /// This is what all archives written by silpkg use unless configured otherwise.
+    #[default]
+    Zlib,
+    /// Raw deflate data without any wrapper, which saves 6 bytes per entry.
+    Raw,
+}
+
+impl DeflateFormat {
+    /// Returns whether the data is wrapped in a zlib header, as expected by [`Compress::new`] and
+    /// [`Decompress::new`](flate2::Decompress::new).
+    ///
+    /// [`Compress::new`]: flate2::Compress::new
+    pub fn zlib_header(self) -> bool {
+        self == DeflateFormat::Zlib
+    }
+}
+
 pub use flate2::Compression;
 
 /// An enum that specifies the ways entries can be compressed.
@@ -237,11 +467,59 @@ pub enum EntryCompression {
     None,
 }
 
+/// Decides how entries are compressed when an archive is transcoded into a new one.
+#[derive(Debug, Default, Clone)]
+pub enum CompressionPolicy {
+    /// Entries keep their compression, compressed data is copied without being recompressed.
+    #[default]
+    Keep,
+    /// Every entry is written with the specified compression.
+    All(EntryCompression),
+}
+
 impl EntryCompression {
+    /// Creates [`Deflate`](EntryCompression::Deflate) compression with the specified level.
+    ///
+    /// Unlike [`Compression::new`], which silently clamps levels that are too high, this returns
+    /// an error if `level` is not in the `0..=9` range.
+    pub fn deflate_checked(level: u32) -> Result<Self, InvalidLevel> {
+        match level {
+            0..=9 => Ok(Self::Deflate(Compression::new(level))),
+            _ => Err(InvalidLevel(level)),
+        }
+    }
+
     /// Returns whether `self` is [`None`](EntryCompression::None)
     pub fn is_none(&self) -> bool {
         matches!(self, EntryCompression::None)
     }
+
+    /// Returns the size `data` would take up in an archive when written with this compression,
+    /// without writing it anywhere.
+    ///
+    /// For [`Deflate`](EntryCompression::Deflate) this compresses the data in full and discards
+    /// the output, so it takes as long as actually inserting it would. The size includes the
+    /// zlib wrapper written with [`DeflateFormat::Zlib`], entries written with
+    /// [`DeflateFormat::Raw`] are 6 bytes smaller.
+    pub fn estimate(&self, data: &[u8]) -> u64 {
+        let level = match self {
+            EntryCompression::Deflate(level) => *level,
+            EntryCompression::None => return data.len() as u64,
+        };
+
+        let mut compress = flate2::Compress::new(level, true);
+        let mut out = [0; 4096];
+        loop {
+            let consumed = compress.total_in() as usize;
+            match compress
+                .compress(&data[consumed..], &mut out, flate2::FlushCompress::Finish)
+                .unwrap()
+            {
+                flate2::Status::StreamEnd => break compress.total_out(),
+                flate2::Status::Ok | flate2::Status::BufError => (),
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -254,13 +532,181 @@ pub struct EntryInfo {
     pub compressed_size: u32,
     /// Uncompressed size of the data corresponding to this entry.
     pub uncompressed_size: u32,
+    /// Whether the data corresponding to this entry is deflate compressed.
+    pub compressed: bool,
+    /// Offset of the data corresponding to this entry from the start of the archive.
+    pub data_offset: u32,
+    /// Hash of the path of this entry as stored in the archive.
+    pub path_hash: u32,
+    /// Raw flag bits stored alongside the path offset of this entry.
+    ///
+    /// These are masked to exclude the path offset, so the only bit currently understood by
+    /// silpkg is `1 << 24` which means the entry is deflate compressed.
+    pub raw_flags: u32,
+}
+
+/// The fields of an entry exactly as they are stored in the entry list, see
+/// [`Pkg::entry_records`](crate::sync::Pkg::entry_records).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RawEntryView<'a> {
+    /// Hash of the path of the entry.
+    pub path_hash: u32,
+    /// Offset of the path of the entry from the start of the path region.
+    pub path_offset: u32,
+    /// Flag bits stored in the upper byte of the path offset field.
+    pub flags: u32,
+    /// Offset of the data of the entry from the start of the archive.
+    pub data_offset: u32,
+    /// Size of the data of the entry as stored in the archive.
+    pub data_size: u32,
+    /// Size of the data of the entry after decompression.
+    pub unpacked_size: u32,
+    /// The path read from the path region at `path_offset`.
+    pub path: &'a str,
+}
+
+/// The contents of the header of a PKG archive, see [`peek`](crate::peek).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PeekInfo {
+    /// Whether the file starts with the PKG magic number.
+    pub magic_ok: bool,
+    /// Size of the header in bytes.
+    pub header_size: u16,
+    /// Size of a single entry in the entry list in bytes.
+    pub entry_size: u16,
+    /// Number of slots in the entry list, including empty ones.
+    pub entry_count: u32,
+    /// Size of the path region in bytes.
+    pub path_region_size: u32,
+}
+
+impl PeekInfo {
+    /// Returns whether the header looks like that of an archive silpkg can parse with the default
+    /// [`ParseOptions`].
+    pub fn is_supported(&self) -> bool {
+        self.magic_ok
+            && self.header_size as u64 == HEADER_SIZE
+            && self.entry_size as u64 == ENTRY_SIZE
+    }
+}
+
+/// The result of checking whether an archive can be safely repacked.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct RepackCheck {
+    /// Pairs of paths of entries whose data overlaps, any of these makes repacking fail.
+    pub overlapping: Vec<(String, String)>,
+    /// Paths of entries whose data extends past the end of the storage, repacking will fail with
+    /// an IO error when trying to move them.
+    pub past_end: Vec<String>,
+    /// Paths of entries whose data would overwrite data of another entry that hasn't been moved
+    /// yet when moved into place.
+    pub unsafe_moves: Vec<String>,
+    /// Total size of the data that would be moved.
+    pub bytes_moved: u64,
 }
 
+impl RepackCheck {
+    /// Returns whether repacking would succeed without corrupting any entries.
+    pub fn is_ok(&self) -> bool {
+        self.overlapping.is_empty() && self.past_end.is_empty() && self.unsafe_moves.is_empty()
+    }
+}
+
+/// The expected properties of a single entry in a [`Manifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ManifestEntry {
+    /// Uncompressed size of the entry's data.
+    pub size: u32,
+    /// Whether the entry is deflate compressed.
+    pub compressed: bool,
+    /// 64-bit FNV-1a hash of the entry's uncompressed data, contents are only compared if this is
+    /// present.
+    pub content_hash: Option<u64>,
+}
+
+impl ManifestEntry {
+    /// Creates an entry expecting the given size and compression without a content hash.
+    pub fn new(size: u32, compressed: bool) -> Self {
+        Self {
+            size,
+            compressed,
+            content_hash: None,
+        }
+    }
+}
+
+/// A description of exactly which entries an archive should contain, keyed by path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Manifest {
+    /// The expected entries.
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+/// Something noteworthy that happened while reading or modifying an archive, see
+/// [`Pkg::set_observer`](crate::sync::Pkg::set_observer).
+///
+/// These carry the same information silpkg also logs through the `log` crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PkgEvent {
+    /// Data of entries that started before `offset` was moved to the end of the archive to make
+    /// space for a larger entry list or path region.
+    DataRegionMoved {
+        /// Offset that the data region now starts at or after.
+        offset: u64,
+        /// Number of entries whose data had to be moved.
+        entries: usize,
+    },
+    /// The path region was grown, moving it to `offset` if it was also pushed back.
+    PathRegionResized {
+        /// Offset of the path region.
+        offset: u64,
+        /// New size of the path region.
+        size: u64,
+    },
+    /// Free slots were added to the entry list.
+    EntriesReserved {
+        /// Number of slots that were added.
+        amount: u64,
+        /// Total number of slots in the entry list.
+        total: usize,
+    },
+    /// The data of an entry was written and its slot in the entry list updated.
+    EntryWritten {
+        /// Path of the entry.
+        path: String,
+        /// Offset of the data of the entry.
+        offset: u64,
+        /// Size of the data of the entry as stored in the archive.
+        size: u64,
+    },
+    /// A deflate stream ended before it was complete, the data read from it may be truncated.
+    DeflateStreamTruncated {
+        /// Offset of the data of the entry being read.
+        offset: u64,
+    },
+    /// A deflate stream is corrupted, reading it fails with [`ReadError::Corrupted`].
+    DeflateStreamCorrupted {
+        /// Offset of the data of the entry being read.
+        offset: u64,
+    },
+}
+
+/// A callback that receives [`PkgEvent`]s.
+pub type Observer = Arc<dyn Fn(PkgEvent) + Send + Sync>;
+
 #[derive(Debug, Clone)]
 struct Entry {
     path_hash: u32,
     relative_path_offset: u32,
-    path: String,
+    // Position of the path in PkgState::path_buffer.
+    path_start: u32,
+    path_len: u32,
 
     data_offset: u32,
     data_size: u32,
@@ -269,63 +715,454 @@ struct Entry {
 }
 
 impl Entry {
-    #[generator(static, yield WriteRequest -> Response)]
-    fn write(&self) -> () {
+    fn path<'a>(&self, path_buffer: &'a str) -> &'a str {
+        &path_buffer[self.path_start as usize..][..self.path_len as usize]
+    }
+
+    fn info(&self, index: usize) -> EntryInfo {
+        EntryInfo {
+            index,
+            compressed_size: self.data_size,
+            uncompressed_size: self.unpacked_size,
+            compressed: self.flags.contains(RawFlags::DEFLATED),
+            data_offset: self.data_offset,
+            path_hash: self.path_hash,
+            raw_flags: self.flags.bits(),
+        }
+    }
+
+    // Encodes the standard fields of the entry as they are stored in the entry list.
+    fn to_bytes(&self) -> [u8; ENTRY_SIZE as usize] {
+        // Slots with a zero data offset are parsed as empty, this holds even for entries without
+        // any data since those are still placed somewhere in the data region.
+        debug_assert_ne!(
+            self.data_offset, 0,
+            "entry with path hash {:#010X} has a zero data offset",
+            self.path_hash
+        );
         let path_offset_and_flags: u32 = self.relative_path_offset | self.flags.bits();
 
-        request!(write u32 be self.path_hash);
-        request!(write u32 be path_offset_and_flags);
-        request!(write u32 be self.data_offset);
-        request!(write u32 be self.data_size);
-        request!(write u32 be self.unpacked_size);
+        let mut bytes = [0; ENTRY_SIZE as usize];
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip([
+            self.path_hash,
+            path_offset_and_flags,
+            self.data_offset,
+            self.data_size,
+            self.unpacked_size,
+        ]) {
+            chunk.copy_from_slice(&value.to_be_bytes());
+        }
+        bytes
+    }
+
+    // Writes the entry followed by zeros up to `entry_size`.
+    //
+    // The standard fields are written all at once so that an interrupted write can't leave an
+    // entry with only some of them updated.
+    #[generator(static, yield WriteRequest -> Response)]
+    fn write(&self, entry_size: u64) -> () {
+        request!(write all self.to_bytes());
+        if entry_size > ENTRY_SIZE {
+            request!(write repeated 0, entry_size - ENTRY_SIZE);
+        }
     }
 
     #[generator(static, yield WriteRequest -> Response)]
-    fn write_empty() -> () {
-        request!(write repeated 0, ENTRY_SIZE);
+    fn write_empty(entry_size: u64) -> () {
+        request!(write repeated 0, entry_size);
     }
 }
 
+#[derive(Clone)]
 pub struct PkgState {
     path_region_size: u32,
     path_region_empty_offset: u32,
 
     entries: Vec<Option<Entry>>,
-    path_to_entry_index_map: HashMap<String, usize>,
+    // Only ever points at occupied slots, new entries are added once their data is flushed.
+    path_index: PathIndex,
+    // Maps path hashes to the first entry with that hash, only built for archives parsed with
+    // ParseOptions::skip_paths since those can't be looked up by path and are never modified.
+    hash_to_entry_index_map: Option<HashMap<u32, usize>>,
+    // Paths of all entries stored back to back so that entries don't need an allocation each.
+    // Paths of removed or renamed entries are left behind until the buffer is compacted.
+    path_buffer: String,
+    // Length of path_buffer after it was last compacted.
+    compacted_path_buffer_len: usize,
+    // Gaps between entries in the data region as (offset, size) pairs sorted by offset.
+    // Rebuilt after parsing and repacking, every other operation that moves or removes data
+    // updates it with claim_data and release_data.
+    free_regions: Vec<(u64, u64)>,
+    // Offset right after the data of the entry that ends last or the data region offset if there
+    // are no entries, kept up to date along with free_regions.
+    data_end: u64,
+    path_region_growth: PathRegionGrowth,
+    // Size of the chunks used when compressing and decompressing entry data.
+    buffer_size: u64,
+    // Sizes of the header and of a single entry, see Format.
+    header_size: u64,
+    entry_size: u64,
+    // End of the space reserved with preallocate, data is appended right after the last entry
+    // instead of at the end of the storage while that is still before this offset.
+    reserved_end: Option<u64>,
+    deflate_format: DeflateFormat,
+    observer: Option<Observer>,
+    // Paths of the entries dropped because of ParseOptions::repair_truncated.
+    dropped_entries: Vec<String>,
 }
 
 impl PkgState {
+    pub fn set_observer(&mut self, observer: Option<Observer>) {
+        self.observer = observer;
+    }
+
+    // Passes the event to the observer, `event` is only called if there is one.
+    fn emit(&self, event: impl FnOnce() -> PkgEvent) {
+        if let Some(observer) = &self.observer {
+            observer(event());
+        }
+    }
+
+    pub fn set_path_region_growth(&mut self, growth: PathRegionGrowth) {
+        self.path_region_growth = growth;
+    }
+
+    pub fn set_buffer_size(&mut self, size: u64) {
+        self.buffer_size = size;
+    }
+
+    pub fn set_deflate_format(&mut self, format: DeflateFormat) {
+        self.deflate_format = format;
+    }
+
+    pub fn deflate_format(&self) -> DeflateFormat {
+        self.deflate_format
+    }
+
+    pub fn dropped_entries(&self) -> &[String] {
+        &self.dropped_entries
+    }
+
+    /// Returns the header and entry sizes used by the archive.
+    pub fn format(&self) -> Format {
+        Format {
+            header_size: self.header_size as u16,
+            entry_size: self.entry_size as u16,
+        }
+    }
+
     pub fn contains(&self, path: &str) -> bool {
-        self.path_to_entry_index_map.contains_key(path)
+        self.path_index
+            .contains(&self.entries, &self.path_buffer, path)
     }
 
-    pub fn paths(&self) -> impl Iterator<Item = &String> {
-        self.path_to_entry_index_map.keys()
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.path_index.indices().map(|index| {
+            self.entries[index]
+                .as_ref()
+                .unwrap()
+                .path(&self.path_buffer)
+        })
     }
 
     pub fn index(&self, path: &str) -> Option<usize> {
-        self.path_to_entry_index_map.get(path).copied()
+        self.path_index.get(&self.entries, &self.path_buffer, path)
+    }
+
+    // Adds the entry at `index` to the path index unless its path is already in it.
+    fn index_entry(&mut self, index: usize) {
+        _ = self
+            .path_index
+            .insert(&self.entries, &self.path_buffer, index);
+    }
+
+    // Removes `path` from the path index and returns the index of its entry.
+    fn unindex_path(&mut self, path: &str) -> Option<usize> {
+        self.path_index
+            .remove(&self.entries, &self.path_buffer, path)
+    }
+
+    // Returns the index of the first entry with the given path hash.
+    pub fn index_by_hash(&self, hash: u32) -> Option<usize> {
+        match &self.hash_to_entry_index_map {
+            Some(map) => map.get(&hash).copied(),
+            None => self
+                .entries
+                .iter()
+                .position(|opt| opt.as_ref().is_some_and(|entry| entry.path_hash == hash)),
+        }
     }
 
     pub fn info(&self, index: usize) -> Option<EntryInfo> {
         match self.entries.get(index) {
-            Some(Some(entry)) => Some(EntryInfo {
-                index,
-                compressed_size: entry.data_size,
-                uncompressed_size: entry.data_size,
-            }),
+            Some(Some(entry)) => Some(entry.info(index)),
             _ => None,
         }
     }
 
+    pub fn entries(&self) -> impl Iterator<Item = (&str, EntryInfo)> {
+        self.entries.iter().enumerate().filter_map(|(i, opt)| {
+            opt.as_ref()
+                .map(|entry| (entry.path(&self.path_buffer), entry.info(i)))
+        })
+    }
+
+    // Like `paths` and `entries` but without MTIME_ENTRY_PATH, for everything that lists entries
+    // to users.
+    pub fn listed_paths(&self) -> impl Iterator<Item = &str> {
+        self.paths().filter(|path| *path != MTIME_ENTRY_PATH)
+    }
+
+    pub fn listed_entries(&self) -> impl Iterator<Item = (&str, EntryInfo)> {
+        self.entries().filter(|(path, _)| *path != MTIME_ENTRY_PATH)
+    }
+
+    pub fn entry_slots(&self) -> impl Iterator<Item = (usize, Option<EntryInfo>)> + '_ {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, opt)| (i, opt.as_ref().map(|entry| entry.info(i))))
+    }
+
+    pub fn entry_records(&self) -> impl Iterator<Item = (usize, Option<RawEntryView<'_>>)> {
+        self.entries.iter().enumerate().map(|(i, opt)| {
+            (
+                i,
+                opt.as_ref().map(|entry| RawEntryView {
+                    path_hash: entry.path_hash,
+                    path_offset: entry.relative_path_offset,
+                    flags: entry.flags.bits(),
+                    data_offset: entry.data_offset,
+                    data_size: entry.data_size,
+                    unpacked_size: entry.unpacked_size,
+                    path: entry.path(&self.path_buffer),
+                }),
+            )
+        })
+    }
+
+    pub fn minimum_size(&self) -> u64 {
+        self.entries
+            .iter()
+            .filter_map(|opt| opt.as_ref())
+            .map(|entry| self.entry_size + entry.path_len as u64 + 1 + entry.data_size as u64)
+            .sum::<u64>()
+            + self.header_size
+    }
+
+    // Whether the space a repack would reclaim from free regions and stale paths is more than a
+    // quarter of the archive's minimum size.
+    pub fn should_repack(&self) -> bool {
+        let free = self.free_regions.iter().map(|&(_, size)| size).sum::<u64>();
+        let wasted = free + self.stale_path_bytes() as u64;
+        wasted * 4 > self.minimum_size()
+    }
+
+    pub fn stale_path_bytes(&self) -> u32 {
+        let live = self
+            .entries
+            .iter()
+            .flatten()
+            .map(|entry| entry.path_len + 1)
+            .sum::<u32>();
+        self.path_region_empty_offset.saturating_sub(live)
+    }
+
+    // Offset right after the data of the entry that ends last or the data region offset if there
+    // are no entries.
+    fn data_end(&self) -> u64 {
+        self.data_end
+    }
+
+    // Offset at which data of unknown size should be appended given the current storage length.
+    fn append_offset(&self, storage_len: u64) -> u64 {
+        let data_end = self.data_end();
+        match self.reserved_end {
+            Some(end) if data_end < end => data_end,
+            _ => storage_len,
+        }
+    }
+
+    pub fn free_regions(&self) -> &[(u64, u64)] {
+        &self.free_regions
+    }
+
+    // Appends `path` to the path buffer and returns its start and length for use in an entry.
+    fn push_path(&mut self, path: &str) -> (u32, u32) {
+        // Compacting only once the buffer doubled in size keeps this amortized constant time
+        if self.path_buffer.len() + path.len() > 2 * self.compacted_path_buffer_len.max(4096) {
+            self.compact_path_buffer();
+        }
+
+        let start = self.path_buffer.len() as u32;
+        self.path_buffer.push_str(path);
+        (start, path.len() as u32)
+    }
+
+    // Drops the paths that are no longer referenced by any entry from the path buffer.
+    fn compact_path_buffer(&mut self) {
+        let old = core::mem::take(&mut self.path_buffer);
+        for entry in self.entries.iter_mut().flatten() {
+            let start = self.path_buffer.len() as u32;
+            self.path_buffer.push_str(entry.path(&old));
+            entry.path_start = start;
+        }
+        self.compacted_path_buffer_len = self.path_buffer.len();
+    }
+
+    // Computes free_regions and data_end from scratch, this sorts all entries so it's only done
+    // after parsing and repacking.
+    fn rebuild_free_regions(&mut self) {
+        let mut layout = self
+            .entries
+            .iter()
+            .filter_map(|opt| opt.as_ref())
+            .filter(|entry| entry.data_size > 0)
+            .map(|entry| (entry.data_offset as u64, entry.data_size as u64))
+            .collect::<Vec<_>>();
+        layout.sort_unstable();
+
+        self.free_regions.clear();
+        let mut cursor = self.data_region_offset();
+        for (offset, size) in layout {
+            if offset > cursor {
+                self.free_regions.push((cursor, offset - cursor));
+            }
+            cursor = cursor.max(offset + size);
+        }
+
+        // Empty entries don't use any space but still mark the end of the data
+        self.data_end = self
+            .entries
+            .iter()
+            .filter_map(|opt| opt.as_ref())
+            .map(|entry| entry.data_offset as u64 + entry.data_size as u64)
+            .fold(self.data_region_offset(), u64::max);
+        if self.data_end > cursor {
+            self.free_regions.push((cursor, self.data_end - cursor));
+        }
+    }
+
+    // Marks the `size` bytes at `offset` as free, merging them with adjacent free regions.
+    fn add_free_region(&mut self, offset: u64, size: u64) {
+        if size == 0 {
+            return;
+        }
+
+        let (mut start, mut end) = (offset, offset + size);
+        let first = self
+            .free_regions
+            .partition_point(|&(region_offset, region_size)| region_offset + region_size < start);
+        let last = self
+            .free_regions
+            .partition_point(|&(region_offset, _)| region_offset <= end);
+        if first < last {
+            let (last_offset, last_size) = self.free_regions[last - 1];
+            start = start.min(self.free_regions[first].0);
+            end = end.max(last_offset + last_size);
+        }
+        self.free_regions
+            .splice(first..last, [(start, end - start)]);
+    }
+
+    // Marks the `size` bytes at `offset` as used by the data of an entry.
+    fn claim_data(&mut self, offset: u64, size: u64) {
+        if offset > self.data_end {
+            self.add_free_region(self.data_end, offset - self.data_end);
+        }
+        self.data_end = self.data_end.max(offset + size);
+
+        let end = offset + size;
+        let first = self
+            .free_regions
+            .partition_point(|&(region_offset, region_size)| region_offset + region_size <= offset);
+        let last = self
+            .free_regions
+            .partition_point(|&(region_offset, _)| region_offset < end);
+        if size == 0 || first >= last {
+            return;
+        }
+
+        let first_offset = self.free_regions[first].0;
+        let (last_offset, last_size) = self.free_regions[last - 1];
+        let before = (first_offset < offset).then(|| (first_offset, offset - first_offset));
+        let after = (last_offset + last_size > end).then(|| (end, last_offset + last_size - end));
+        self.free_regions
+            .splice(first..last, before.into_iter().chain(after));
+    }
+
+    // Marks the `size` bytes at `offset` as no longer used by the data of an entry that was
+    // already removed or moved elsewhere. Parts that are still used by another entry's data stay
+    // in use, so unlike claim_data this has to go through all entries, but it doesn't sort them.
+    fn release_data(&mut self, offset: u64, size: u64) {
+        let end = offset + size;
+        let mut data_end = self.data_region_offset();
+        let mut used = Vec::new();
+        for entry in self.entries.iter().flatten() {
+            let entry_start = entry.data_offset as u64;
+            let entry_end = entry_start + entry.data_size as u64;
+            data_end = data_end.max(entry_end);
+            if entry.data_size > 0 && entry_start < end && entry_end > offset {
+                used.push((entry_start.max(offset), entry_end.min(end)));
+            }
+        }
+
+        used.sort_unstable();
+        let mut cursor = offset;
+        for (used_start, used_end) in used {
+            if used_start > cursor {
+                self.add_free_region(cursor, used_start - cursor);
+            }
+            cursor = cursor.max(used_end);
+        }
+        if end > cursor {
+            self.add_free_region(cursor, end - cursor);
+        }
+
+        // Space after the last entry's data isn't a free region
+        self.data_end = data_end;
+        while let Some((region_offset, region_size)) = self.free_regions.last_mut() {
+            if *region_offset < data_end {
+                *region_size = (*region_size).min(data_end - *region_offset);
+                break;
+            }
+            self.free_regions.pop();
+        }
+    }
+
+    // Drops the free space that became part of the entry list or path region after they grew,
+    // all entries must already have been moved out of it.
+    fn trim_free_regions_to_data_region(&mut self) {
+        let data_region_offset = self.data_region_offset();
+        self.data_end = self.data_end.max(data_region_offset);
+
+        let first = self
+            .free_regions
+            .partition_point(|&(region_offset, region_size)| {
+                region_offset + region_size <= data_region_offset
+            });
+        self.free_regions.drain(..first);
+        if let Some((region_offset, region_size)) = self.free_regions.first_mut() {
+            if *region_offset < data_region_offset {
+                *region_size -= data_region_offset - *region_offset;
+                *region_offset = data_region_offset;
+            }
+        }
+    }
+
+    #[inline]
+    fn entry_list_offset(&self) -> u64 {
+        self.header_size
+    }
+
     #[inline]
-    fn entry_list_offset() -> u64 {
-        HEADER_SIZE
+    fn entry_offset(&self, index: usize) -> u64 {
+        self.header_size + index as u64 * self.entry_size
     }
 
     #[inline]
     fn path_region_offset(&self) -> u64 {
-        HEADER_SIZE + self.entries.len() as u64 * ENTRY_SIZE
+        self.header_size + self.entries.len() as u64 * self.entry_size
     }
 
     #[inline]
@@ -340,7 +1177,11 @@ mod common;
 pub use common::*;
 mod parse;
 pub use parse::*;
+mod path_index;
+use path_index::PathIndex;
 mod read;
 pub use read::*;
+mod tree;
+pub use tree::*;
 mod write;
 pub use write::*;
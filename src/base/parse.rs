@@ -1,14 +1,20 @@
-use alloc::{string::String, vec::Vec};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
 
 use hashbrown::HashMap;
 use macros::generator;
 
 use crate::{
-    base::{ENTRY_SIZE, HEADER_SIZE, MAGIC},
+    base::{pkg_path_hash, BUFFER_SIZE, HEADER_SIZE, MAGIC},
     util::ByteSliceExt,
 };
 
-use super::{Entry, ParseError, PkgState, RawFlags, ReadSeekRequest, Response};
+use super::{
+    Entry, EntryInfo, Format, ParseError, ParseOptions, PathIndex, PathRegionGrowth, PeekInfo,
+    PkgState, RawFlags, ReadSeekRequest, Response, SeekFrom,
+};
 
 #[generator(static, yield ReadSeekRequest -> Response)]
 pub fn check_magic() -> bool {
@@ -28,7 +34,39 @@ pub fn check_magic() -> bool {
 }
 
 #[generator(static, yield ReadSeekRequest -> Response)]
-pub fn parse(expect_magic: bool) -> Result<PkgState, ParseError> {
+pub fn peek() -> PeekInfo {
+    request!(rewind);
+    let read = request!(read exact HEADER_SIZE);
+
+    PeekInfo {
+        magic_ok: &read[0..4] == MAGIC,
+        header_size: read[4..6].as_u16_be(),
+        entry_size: read[6..8].as_u16_be(),
+        entry_count: read[8..12].as_u32_be(),
+        path_region_size: read[12..16].as_u32_be(),
+    }
+}
+
+// Sizes read from the header of an archive.
+struct Layout {
+    header_size: u64,
+    entry_size: u64,
+    entry_count: u32,
+    path_region_size: u32,
+    storage_len: u64,
+}
+
+// Reads and validates the header, leaving the storage positioned at the start of the entry list.
+#[generator(static, yield ReadSeekRequest -> Response)]
+fn parse_layout(
+    expect_magic: bool,
+    format: Format,
+    allow_extended_layout: bool,
+) -> Result<Layout, ParseError> {
+    if !format.is_supported() {
+        return Err(ParseError::UnsupportedFormat(format));
+    }
+
     request!(rewind);
 
     if expect_magic && !check_magic().await {
@@ -37,93 +75,269 @@ pub fn parse(expect_magic: bool) -> Result<PkgState, ParseError> {
 
     let read = request!(read exact 4);
 
-    {
+    let header_size = {
         let header_size = read[0..2].as_u16_be();
-        if header_size as u64 != HEADER_SIZE {
-            return Err(ParseError::MismatchedHeaderSize { size: header_size });
+        let accepted = match allow_extended_layout {
+            true => header_size >= format.header_size,
+            false => header_size == format.header_size,
+        };
+        if !accepted {
+            return Err(ParseError::MismatchedHeaderSize {
+                size: header_size,
+                expected: format.header_size,
+            });
         }
-    }
+        header_size as u64
+    };
 
-    {
+    let entry_size = {
         let entry_size = read[2..4].as_u16_be();
-        if entry_size as u64 != ENTRY_SIZE {
-            return Err(ParseError::MismatchedEntrySize { size: entry_size });
+        let accepted = match allow_extended_layout {
+            true => entry_size >= format.entry_size,
+            false => entry_size == format.entry_size,
+        };
+        if !accepted {
+            return Err(ParseError::MismatchedEntrySize {
+                size: entry_size,
+                expected: format.entry_size,
+            });
         }
-    }
+        entry_size as u64
+    };
 
     let storage_len = request!(stream len);
     let read = request!(read exact 8);
     let entry_count = read[0..4].as_u32_be();
-    if (HEADER_SIZE + entry_count as u64 * ENTRY_SIZE) > storage_len {
+    if (header_size + entry_count as u64 * entry_size) > storage_len {
         return Err(ParseError::EntryOverflow);
     }
 
     let path_region_size = read[4..8].as_u32_be();
-    if (HEADER_SIZE + entry_count as u64 * ENTRY_SIZE) + path_region_size as u64 > storage_len {
+    if (header_size + entry_count as u64 * entry_size) + path_region_size as u64 > storage_len {
         return Err(ParseError::PathOverflow);
     }
 
+    // Skip any unknown fields at the end of an extended header
+    if header_size != HEADER_SIZE {
+        request!(seek SeekFrom::Start(header_size));
+    }
+
+    Ok(Layout {
+        header_size,
+        entry_size,
+        entry_count,
+        path_region_size,
+        storage_len,
+    })
+}
+
+// Decodes a single record of the entry list, empty slots are returned as None.
+fn parse_entry(read: &[u8], preserve_unknown_flags: bool) -> Result<Option<Entry>, ParseError> {
+    // Only the first ENTRY_SIZE bytes are understood, the rest of an extended entry is ignored
+    let path_hash = read[0..4].as_u32_be();
+
+    let path_offset_and_flags = read[4..8].as_u32_be();
+    let path_offset = path_offset_and_flags & 0x00FFFFFF;
+    let flag_bits = path_offset_and_flags & 0xFF000000;
+    let flags = if preserve_unknown_flags {
+        RawFlags::from_bits_retain(flag_bits)
+    } else {
+        RawFlags::from_bits(flag_bits).ok_or(ParseError::UnrecognisedEntryFlags(flag_bits))?
+    };
+
+    let data_offset = read[8..12].as_u32_be();
+    let data_size = read[12..16].as_u32_be();
+    let unpacked_size = read[16..20].as_u32_be();
+
+    if data_offset == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(Entry {
+            path_hash,
+            relative_path_offset: path_offset,
+            path_start: 0,
+            path_len: 0,
+            data_offset,
+            data_size,
+            unpacked_size,
+            flags,
+        }))
+    }
+}
+
+/// Decodes the entry list of an archive one entry at a time, see [`scan_entries`].
+pub struct EntryScanner {
+    entry_size: u64,
+    entry_count: u32,
+    next_index: u32,
+    preserve_unknown_flags: bool,
+}
+
+/// Reads the header of an archive and returns a scanner that yields its entries as they're read.
+///
+/// Unlike [`parse`] this never builds a [`PkgState`] and reads neither the path region nor the
+/// data region, so entries can be processed before the rest of the entry list is even read.
+/// Only [`ParseOptions::allow_extended_layout`] and [`ParseOptions::preserve_unknown_flags`]
+/// are taken into account.
+#[generator(static, yield ReadSeekRequest -> Response)]
+pub fn scan_entries(options: ParseOptions) -> Result<EntryScanner, ParseError> {
+    let layout = parse_layout(true, options.format, options.allow_extended_layout).await?;
+
+    Ok(EntryScanner {
+        entry_size: layout.entry_size,
+        entry_count: layout.entry_count,
+        next_index: 0,
+        preserve_unknown_flags: options.preserve_unknown_flags,
+    })
+}
+
+impl EntryScanner {
+    /// Returns the number of slots in the entry list, including empty ones.
+    pub fn entry_count(&self) -> u32 {
+        self.entry_count
+    }
+
+    /// Returns the number of slots that were read so far.
+    pub fn position(&self) -> u32 {
+        self.next_index
+    }
+
+    /// Reads slots until a non-empty one is found and returns its entry or [`None`] if the end
+    /// of the entry list was reached.
+    ///
+    /// The storage has to be left where the previous call left it, the entry list is read
+    /// sequentially.
+    #[generator(static, yield ReadSeekRequest -> Response)]
+    pub fn next_entry(&mut self) -> Option<Result<EntryInfo, ParseError>> {
+        while self.next_index < self.entry_count {
+            let index = self.next_index as usize;
+            let read = request!(read exact self.entry_size);
+            self.next_index += 1;
+
+            match parse_entry(&read, self.preserve_unknown_flags) {
+                Ok(Some(entry)) => return Some(Ok(entry.info(index))),
+                Ok(None) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+
+        None
+    }
+}
+
+#[generator(static, yield ReadSeekRequest -> Response)]
+pub fn parse(expect_magic: bool, options: ParseOptions) -> Result<PkgState, ParseError> {
+    let Layout {
+        header_size,
+        entry_size,
+        entry_count,
+        path_region_size,
+        storage_len,
+    } = parse_layout(expect_magic, options.format, options.allow_extended_layout).await?;
+
     let mut entries = Vec::with_capacity(entry_count as usize);
-    let mut path_to_entry_index_map = HashMap::new();
+    let mut path_index = PathIndex::with_capacity(entry_count as usize);
 
     for _ in 0..entry_count {
-        let read = request!(read exact 20);
-        let path_hash = read[0..4].as_u32_be();
-
-        let path_offset_and_flags = read[4..8].as_u32_be();
-        let path_offset = path_offset_and_flags & 0x00FFFFFF;
-        let flag_bits = path_offset_and_flags & 0xFF000000;
-        let flags =
-            RawFlags::from_bits(flag_bits).ok_or(ParseError::UnrecognisedEntryFlags(flag_bits))?;
-
-        let data_offset = read[8..12].as_u32_be();
-        let data_size = read[12..16].as_u32_be();
-        let unpacked_size = read[16..20].as_u32_be();
-
-        if data_offset == 0 {
-            entries.push(None)
-        } else {
-            entries.push(Some(Entry {
-                path_hash,
-                relative_path_offset: path_offset,
-                path: String::new(),
-                data_offset,
-                data_size,
-                unpacked_size,
-                flags,
-            }))
-        }
+        let read = request!(read exact entry_size);
+        entries.push(parse_entry(&read, options.preserve_unknown_flags)?);
     }
 
-    let read = request!(read exact path_region_size.into());
-    for (i, maybe_entry) in entries.iter_mut().enumerate() {
-        if let Some(entry) = maybe_entry {
-            let path = read[entry.relative_path_offset as usize..]
+    // Paths are left empty when they're skipped, the path region is then treated as full
+    let mut path_region_empty_offset = path_region_size;
+    let mut path_buffer = String::new();
+    if !options.skip_paths {
+        path_buffer.reserve(path_region_size as usize);
+        let read = request!(read exact path_region_size.into());
+        for i in 0..entries.len() {
+            let Some(entry) = &mut entries[i] else {
+                continue;
+            };
+
+            // TODO: Fail if null terminator is not present
+            let bytes = read[entry.relative_path_offset as usize..]
                 .iter()
-                // TODO: Fail if null terminator is not present
-                .take_while(|b| **b != 0)
-                .map(|b| {
-                    if !b.is_ascii() {
-                        Err(ParseError::NonAsciiPath)
-                    } else {
-                        Ok(*b as char)
-                    }
-                })
-                .try_collect::<String>()?;
-
-            entry.path = path.clone();
-            path_to_entry_index_map
-                .try_insert(path, i)
-                .map_err(|e| ParseError::SamePath(e.entry.key().clone()))?;
+                .take_while(|b| **b != 0);
+            let path_start = path_buffer.len();
+            for b in bytes {
+                if !b.is_ascii() {
+                    return Err(ParseError::NonAsciiPath);
+                }
+                path_buffer.push(*b as char);
+            }
+            let path = &path_buffer[path_start..];
+
+            if options.verify_hashes {
+                let computed = pkg_path_hash(path);
+                if computed != entry.path_hash {
+                    return Err(ParseError::HashMismatch {
+                        path: path.to_string(),
+                        stored: entry.path_hash,
+                        computed,
+                    });
+                }
+            }
+
+            entry.path_start = path_start as u32;
+            entry.path_len = path.len() as u32;
+            path_index
+                .insert(&entries, &path_buffer, i)
+                .map_err(|_| ParseError::SamePath(path_buffer[path_start..].to_string()))?;
         }
+
+        path_region_empty_offset = path_region_size
+            .checked_add_signed(-((read.iter().rev().take_while(|b| **b == 0).count() as i32) - 1))
+            .unwrap();
     }
 
-    Ok(PkgState {
+    let mut state = PkgState {
         path_region_size,
-        path_region_empty_offset: path_region_size
-            .checked_add_signed(-((read.iter().rev().take_while(|b| **b == 0).count() as i32) - 1))
-            .unwrap(),
+        path_region_empty_offset,
         entries,
-        path_to_entry_index_map,
-    })
+        path_index,
+        hash_to_entry_index_map: None,
+        compacted_path_buffer_len: path_buffer.len(),
+        path_buffer,
+        free_regions: Vec::new(),
+        data_end: 0,
+        path_region_growth: PathRegionGrowth::default(),
+        buffer_size: BUFFER_SIZE,
+        header_size,
+        entry_size,
+        reserved_end: None,
+        deflate_format: options.deflate_format,
+        observer: None,
+        dropped_entries: Vec::new(),
+    };
+
+    if options.repair_truncated {
+        for (i, slot) in state.entries.iter_mut().enumerate() {
+            if slot.as_ref().is_some_and(|entry| {
+                entry.data_offset as u64 + entry.data_size as u64 > storage_len
+            }) {
+                let path = slot.take().unwrap().path(&state.path_buffer).to_string();
+                state.path_index.remove_index(&path, i);
+                state.dropped_entries.push(path);
+            }
+        }
+    }
+    state.rebuild_free_regions();
+
+    if options.skip_paths {
+        let mut map = HashMap::with_capacity(state.entries.len());
+        for (i, maybe_entry) in state.entries.iter().enumerate() {
+            if let Some(entry) = maybe_entry {
+                map.entry(entry.path_hash).or_insert(i);
+            }
+        }
+        state.hash_to_entry_index_map = Some(map);
+    }
+
+    if options.reject_trailing_garbage && storage_len > state.data_end() {
+        return Err(ParseError::TrailingGarbage {
+            size: storage_len - state.data_end(),
+        });
+    }
+
+    Ok(state)
 }
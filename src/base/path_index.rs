@@ -0,0 +1,103 @@
+use core::hash::BuildHasher;
+
+use hashbrown::{hash_table, DefaultHashBuilder, HashTable};
+
+use super::Entry;
+
+// Looks up entries by path without storing the paths a second time.
+//
+// Only the indices of the entries are stored, their paths are read from PkgState::path_buffer
+// whenever they have to be hashed or compared. Every index in the table must therefore point at
+// an occupied slot whose path doesn't change while it's in the table, paths have to be removed
+// before their entry's path is changed and added back afterwards.
+#[derive(Clone, Default)]
+pub(super) struct PathIndex {
+    table: HashTable<usize>,
+    hasher: DefaultHashBuilder,
+}
+
+fn path_at<'a>(entries: &[Option<Entry>], path_buffer: &'a str, index: usize) -> &'a str {
+    entries[index].as_ref().unwrap().path(path_buffer)
+}
+
+impl PathIndex {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            table: HashTable::with_capacity(capacity),
+            hasher: DefaultHashBuilder::default(),
+        }
+    }
+
+    pub fn get(&self, entries: &[Option<Entry>], path_buffer: &str, path: &str) -> Option<usize> {
+        self.table
+            .find(self.hasher.hash_one(path), |&index| {
+                path_at(entries, path_buffer, index) == path
+            })
+            .copied()
+    }
+
+    pub fn contains(&self, entries: &[Option<Entry>], path_buffer: &str, path: &str) -> bool {
+        self.get(entries, path_buffer, path).is_some()
+    }
+
+    // Adds the entry at `index`, returns the index of the entry that already has the same path
+    // instead if there is one.
+    pub fn insert(
+        &mut self,
+        entries: &[Option<Entry>],
+        path_buffer: &str,
+        index: usize,
+    ) -> Result<(), usize> {
+        let path = path_at(entries, path_buffer, index);
+        let hasher = &self.hasher;
+        match self.table.entry(
+            hasher.hash_one(path),
+            |&other| path_at(entries, path_buffer, other) == path,
+            |&other| hasher.hash_one(path_at(entries, path_buffer, other)),
+        ) {
+            hash_table::Entry::Occupied(occupied) => Err(*occupied.get()),
+            hash_table::Entry::Vacant(vacant) => {
+                vacant.insert(index);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn remove(
+        &mut self,
+        entries: &[Option<Entry>],
+        path_buffer: &str,
+        path: &str,
+    ) -> Option<usize> {
+        self.table
+            .find_entry(self.hasher.hash_one(path), |&index| {
+                path_at(entries, path_buffer, index) == path
+            })
+            .ok()
+            .map(|occupied| occupied.remove().0)
+    }
+
+    // Removes the entry at `index` which had the path `path`, unlike remove this works after the
+    // entry was already taken out of its slot.
+    pub fn remove_index(&mut self, path: &str, index: usize) {
+        if let Ok(occupied) = self
+            .table
+            .find_entry(self.hasher.hash_one(path), |&other| other == index)
+        {
+            occupied.remove();
+        }
+    }
+
+    // Replaces the contents of the index with all the occupied slots in `entries`.
+    pub fn rebuild(&mut self, entries: &[Option<Entry>], path_buffer: &str) {
+        self.table.clear();
+        for (index, _) in entries.iter().enumerate().filter(|(_, opt)| opt.is_some()) {
+            // Occupied slots never share a path
+            _ = self.insert(entries, path_buffer, index);
+        }
+    }
+
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.table.iter().copied()
+    }
+}
@@ -1,13 +1,19 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+
 use flate2::Decompress;
 use macros::generator;
 
-use crate::base::BUFFER_SIZE;
-
-use super::{OpenError, PkgState, RawFlags, ReadSeekRequest, Response, SeekError, SeekFrom};
+use super::{
+    Entry, Observer, OpenError, PkgEvent, PkgState, RawFlags, ReadError, ReadSeekRequest,
+    RepackCheck, Response, SeekError, SeekFrom,
+};
 
 pub trait GeneratorRead {
     #[generator(static, yield ReadSeekRequest -> Response, !use)]
-    fn read(&mut self, buffer: &mut [u8]) -> usize;
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ReadError>;
 }
 
 pub trait GeneratorSeek {
@@ -25,9 +31,17 @@ pub struct DeflateReadHandle {
     offset: u64,
     cursor: u64,
     size: u64,
+    buffer_size: u64,
 
     decompress: Decompress,
     done: bool,
+    // Whether the stream ends with a checksum that can be verified.
+    checksum: bool,
+    // Whether the stream ended with a valid checksum, only known once `done` is set.
+    integrity: Option<bool>,
+    // Set when decompression failed, every following read returns ReadError::Corrupted.
+    corrupted: bool,
+    observer: Option<Observer>,
 }
 
 pub enum ReadHandle {
@@ -35,14 +49,101 @@ pub enum ReadHandle {
     Deflate(DeflateReadHandle),
 }
 
+#[generator(static, yield ReadSeekRequest -> Response)]
+pub fn data_region_size(state: &PkgState) -> u64 {
+    request!(stream len).saturating_sub(state.data_region_offset())
+}
+
+#[generator(static, yield ReadSeekRequest -> Response)]
+pub fn has_trailing_garbage(state: &PkgState) -> bool {
+    request!(stream len) > state.data_end()
+}
+
+// Returns the paths of the entries whose data extends past the end of the storage in path order.
+#[generator(static, yield ReadSeekRequest -> Response)]
+pub fn truncated_entries(state: &PkgState) -> Vec<String> {
+    let storage_len = request!(stream len);
+    let mut paths = state
+        .listed_entries()
+        .filter(|(_, info)| info.data_offset as u64 + info.compressed_size as u64 > storage_len)
+        .map(|(path, _)| path.to_string())
+        .collect::<Vec<_>>();
+    paths.sort_unstable();
+    paths
+}
+
+// Goes through the same steps as PkgState::repack without modifying anything.
+#[generator(static, yield ReadSeekRequest -> Response)]
+pub fn check_repack(state: &PkgState) -> RepackCheck {
+    fn data_end(entry: &Entry) -> u64 {
+        entry.data_offset as u64 + entry.data_size as u64
+    }
+
+    let storage_len = request!(stream len);
+    let mut check = RepackCheck::default();
+
+    let mut entries = state.entries.iter().flatten().collect::<Vec<_>>();
+    entries.sort_by_key(|entry| (entry.data_offset, entry.data_size));
+
+    let path_region_size = entries.iter().map(|e| e.path_len as u64 + 1).sum::<u64>();
+    let path = |entry: &Entry| entry.path(&state.path_buffer).to_string();
+    let mut current_data_offset = state.path_region_offset() + path_region_size;
+    // The entry reaching furthest into the data region so far
+    let mut furthest: Option<&Entry> = None;
+
+    for (i, entry) in entries.iter().enumerate() {
+        let (offset, size) = (entry.data_offset as u64, entry.data_size as u64);
+
+        if let Some(prev) = furthest {
+            if data_end(prev) > offset {
+                check.overlapping.push((path(prev), path(entry)));
+            }
+        }
+        if furthest.is_none_or(|prev| data_end(prev) < offset + size) {
+            furthest = Some(entry);
+        }
+
+        if offset + size > storage_len {
+            check.past_end.push(path(entry));
+        }
+
+        if current_data_offset != offset {
+            check.bytes_moved += size;
+
+            let clobbers = entries[i + 1..]
+                .iter()
+                .take_while(|other| (other.data_offset as u64) < current_data_offset + size)
+                .any(|other| other.data_size > 0 && data_end(other) > current_data_offset);
+            if size > 0 && clobbers {
+                check.unsafe_moves.push(path(entry));
+            }
+        }
+
+        current_data_offset += size;
+    }
+
+    check
+}
+
 #[generator(static, yield ReadSeekRequest -> Response)]
 pub fn open(state: &PkgState, path: &str) -> Result<ReadHandle, OpenError> {
-    let entry = state.entries[match state.path_to_entry_index_map.get(path) {
-        Some(index) => *index,
-        None => return Err(OpenError::NotFound),
-    }]
-    .as_ref()
-    .unwrap();
+    match state.index(path) {
+        Some(index) => open_index(state, index).await,
+        None => Err(OpenError::NotFound),
+    }
+}
+
+#[generator(static, yield ReadSeekRequest -> Response)]
+pub fn open_index(state: &PkgState, index: usize) -> Result<ReadHandle, OpenError> {
+    let entry = match state.entries.get(index) {
+        Some(Some(entry)) => entry,
+        _ => return Err(OpenError::NotFound),
+    };
+
+    let unknown = entry.flags.difference(RawFlags::all());
+    if !unknown.is_empty() {
+        return Err(OpenError::UnrecognisedEntryFlags(unknown.bits()));
+    }
 
     request!(seek SeekFrom::Start(entry.data_offset as u64));
 
@@ -51,8 +152,13 @@ pub fn open(state: &PkgState, path: &str) -> Result<ReadHandle, OpenError> {
             offset: entry.data_offset.into(),
             cursor: 0,
             size: entry.data_size.into(),
-            decompress: Decompress::new(true),
+            buffer_size: state.buffer_size,
+            decompress: Decompress::new(state.deflate_format.zlib_header()),
             done: false,
+            checksum: state.deflate_format.zlib_header(),
+            integrity: None,
+            corrupted: false,
+            observer: state.observer.clone(),
         })
     } else {
         ReadHandle::Raw(RawReadWriteHandle {
@@ -63,51 +169,68 @@ pub fn open(state: &PkgState, path: &str) -> Result<ReadHandle, OpenError> {
     })
 }
 
+// Opens the on-disk data of an entry without decompressing it.
+#[generator(static, yield ReadSeekRequest -> Response)]
+pub fn open_raw(state: &PkgState, path: &str) -> Result<ReadHandle, OpenError> {
+    let entry = match state.index(path) {
+        Some(index) => state.entries[index].as_ref().unwrap(),
+        None => return Err(OpenError::NotFound),
+    };
+
+    request!(seek SeekFrom::Start(entry.data_offset as u64));
+
+    Ok(ReadHandle::Raw(RawReadWriteHandle {
+        offset: entry.data_offset.into(),
+        cursor: 0,
+        size: entry.data_size.into(),
+    }))
+}
+
 impl GeneratorRead for RawReadWriteHandle {
     #[generator(static, yield ReadSeekRequest -> Response, !use)]
-    fn read(&mut self, buffer: &mut [u8]) -> usize {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ReadError> {
         let end = (self.cursor + buffer.len() as u64).min(self.size);
-        let count = end - self.cursor;
+        let count = end.saturating_sub(self.cursor);
         let value = request!(read count);
         buffer[..value.len()].copy_from_slice(&value);
         self.cursor += count;
-        value.len()
+        Ok(value.len())
     }
 }
 
 impl GeneratorSeek for RawReadWriteHandle {
     #[generator(static, yield ReadSeekRequest -> Response, !use)]
     fn seek(&mut self, seekfrom: SeekFrom) -> Result<u64, SeekError> {
-        match seekfrom {
-            SeekFrom::Start(start) => {
-                request!(seek SeekFrom::Start(self.offset + start));
-                Ok(start)
-            }
-            SeekFrom::End(end) => {
-                match self.size.checked_add_signed(end).map(|x| x + self.offset) {
-                    Some(off) => {
-                        request!(seek SeekFrom::Start(off));
-                        Ok(off - self.offset)
-                    }
-                    None => Err(SeekError::SeekOutOfBounds),
-                }
-            }
+        let cursor = match seekfrom {
+            SeekFrom::Start(start) => start,
+            SeekFrom::End(end) => match self.size.checked_add_signed(end) {
+                Some(off) => off,
+                None => return Err(SeekError::SeekOutOfBounds),
+            },
             SeekFrom::Current(off) => match self.cursor.checked_add_signed(off) {
-                Some(off) => {
-                    request!(seek SeekFrom::Start(self.offset + off));
-                    Ok(off)
-                }
-                None => Err(SeekError::SeekOutOfBounds),
+                Some(off) => off,
+                None => return Err(SeekError::SeekOutOfBounds),
             },
+        };
+
+        // Anything past the end belongs to other entries or was never written
+        if cursor > self.size {
+            return Err(SeekError::SeekOutOfBounds);
         }
+
+        request!(seek SeekFrom::Start(self.offset + cursor));
+        self.cursor = cursor;
+        Ok(cursor)
     }
 }
 
 impl GeneratorRead for DeflateReadHandle {
     #[generator(static, yield ReadSeekRequest -> Response, !use)]
-    fn read(&mut self, mut buffer: &mut [u8]) -> usize {
-        if self.done {
-            return 0;
+    fn read(&mut self, mut buffer: &mut [u8]) -> Result<usize, ReadError> {
+        if self.corrupted {
+            return Err(ReadError::Corrupted);
+        } else if self.done {
+            return Ok(0);
         }
 
         log::trace!("Writing compressed entry data to {}", self.offset);
@@ -115,17 +238,16 @@ impl GeneratorRead for DeflateReadHandle {
         let mut read = 0;
 
         while !buffer.is_empty() {
-            let end = (self.cursor + BUFFER_SIZE / 2).min(self.size);
+            let end = (self.cursor + (self.buffer_size / 2).max(1)).min(self.size);
             let count = end - self.cursor;
 
             let prev_in = self.decompress.total_in();
             let prev_out = self.decompress.total_out();
             request!(seek SeekFrom::Start(self.offset + self.cursor));
             let input = request!(read count);
-            let status = self
+            let result = self
                 .decompress
-                .decompress(&input, buffer, flate2::FlushDecompress::None)
-                .unwrap();
+                .decompress(&input, buffer, flate2::FlushDecompress::None);
 
             let read_now = (self.decompress.total_out() - prev_out) as usize;
             let consumed_now = self.decompress.total_in() - prev_in;
@@ -133,14 +255,32 @@ impl GeneratorRead for DeflateReadHandle {
             read += read_now;
             self.cursor += consumed_now;
 
-            match status {
-                flate2::Status::StreamEnd => {
+            match result {
+                Ok(flate2::Status::StreamEnd) => {
                     self.done = true;
+                    self.integrity = self.checksum.then_some(true);
                     break;
                 }
-                flate2::Status::Ok | flate2::Status::BufError => {
+                Ok(flate2::Status::Ok | flate2::Status::BufError) => {
                     buffer = &mut buffer[read_now..];
                 }
+                // This includes a mismatched Adler-32 checksum at the end of the stream
+                Err(err) => {
+                    log::warn!("Deflate stream is corrupted: {err}");
+                    if let Some(observer) = &self.observer {
+                        observer(PkgEvent::DeflateStreamCorrupted {
+                            offset: self.offset,
+                        });
+                    }
+                    self.done = true;
+                    self.integrity = Some(false);
+                    self.corrupted = true;
+                    // Hand out what was decompressed before the error first, the next read fails.
+                    if read == 0 {
+                        return Err(ReadError::Corrupted);
+                    }
+                    break;
+                }
             };
 
             if read_now == 0 && consumed_now == 0 {
@@ -148,19 +288,25 @@ impl GeneratorRead for DeflateReadHandle {
                     log::warn!(
                         "Deflate stream ended unexpectedly, resulting data may be truncated!"
                     );
+                    if let Some(observer) = &self.observer {
+                        observer(PkgEvent::DeflateStreamTruncated {
+                            offset: self.offset,
+                        });
+                    }
+                    self.integrity = Some(false);
                 }
                 self.done = true;
                 break;
             }
         }
 
-        read
+        Ok(read)
     }
 }
 
 impl GeneratorRead for ReadHandle {
     #[generator(static, yield ReadSeekRequest -> Response, !use)]
-    fn read(&mut self, buffer: &mut [u8]) -> usize {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, ReadError> {
         match self {
             ReadHandle::Raw(h) => h.read(buffer).await,
             ReadHandle::Deflate(h) => h.read(buffer).await,
@@ -189,4 +335,11 @@ impl ReadHandle {
     pub fn is_seekable(&self) -> bool {
         !self.is_compressed()
     }
+
+    pub fn integrity_ok(&self) -> Option<bool> {
+        match self {
+            ReadHandle::Raw(_) => None,
+            ReadHandle::Deflate(h) => h.integrity,
+        }
+    }
 }
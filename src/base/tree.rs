@@ -0,0 +1,138 @@
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::String,
+    vec::Vec,
+};
+
+use super::{EntryInfo, PkgState};
+
+#[derive(Debug, Clone, Default)]
+/// A directory in the virtual directory tree formed by the `/` separated paths of an archive.
+///
+/// PKG archives don't store directories, so a directory exists whenever some entry's path goes
+/// through it or there's a directory marker for it, see
+/// [`Pkg::is_directory_marker`](crate::sync::Pkg::is_directory_marker).
+#[non_exhaustive]
+pub struct DirNode {
+    /// Subdirectories of this directory keyed by their name.
+    pub dirs: BTreeMap<String, DirNode>,
+    /// Entries directly inside of this directory sorted by their name.
+    pub files: Vec<FileNode>,
+}
+
+#[derive(Debug, Clone)]
+/// An entry inside of a [`DirNode`].
+#[non_exhaustive]
+pub struct FileNode {
+    /// Last component of the entry's path.
+    pub name: String,
+    /// Full path of the entry.
+    pub path: String,
+    /// Metadata of the entry.
+    pub info: EntryInfo,
+}
+
+impl DirNode {
+    /// Returns the subdirectory at the `/` separated `path` relative to this directory.
+    pub fn get(&self, path: &str) -> Option<&DirNode> {
+        components(path).try_fold(self, |dir, name| dir.dirs.get(name))
+    }
+
+    /// Returns the number of entries in this directory and all of its subdirectories.
+    pub fn file_count(&self) -> usize {
+        self.files.len() + self.dirs.values().map(DirNode::file_count).sum::<usize>()
+    }
+}
+
+// Empty components caused by leading, trailing or repeated slashes are skipped.
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty())
+}
+
+// Some tools store directories as empty entries with a trailing slash.
+fn is_directory_marker(path: &str, info: &EntryInfo) -> bool {
+    path.ends_with('/') && info.uncompressed_size == 0
+}
+
+impl PkgState {
+    pub fn is_directory_marker(&self, path: &str) -> bool {
+        self.index(path)
+            .and_then(|index| self.info(index))
+            .is_some_and(|info| is_directory_marker(path, &info))
+    }
+
+    pub fn tree(&self) -> DirNode {
+        let mut root = DirNode::default();
+
+        for (path, info) in self.listed_entries() {
+            if is_directory_marker(path, &info) {
+                components(path).fold(&mut root, |dir, name| {
+                    dir.dirs.entry(name.into()).or_default()
+                });
+                continue;
+            }
+
+            let mut components = components(path).collect::<Vec<_>>();
+            // Paths without any non-empty components end up in the root with an empty name
+            let name = components.pop().unwrap_or("");
+
+            let dir = components.into_iter().fold(&mut root, |dir, name| {
+                dir.dirs.entry(name.into()).or_default()
+            });
+            dir.files.push(FileNode {
+                name: name.into(),
+                path: path.into(),
+                info,
+            });
+        }
+
+        fn sort(dir: &mut DirNode) {
+            dir.files
+                .sort_unstable_by(|a, b| a.name.cmp(&b.name).then_with(|| a.path.cmp(&b.path)));
+            dir.dirs.values_mut().for_each(sort);
+        }
+        sort(&mut root);
+
+        root
+    }
+
+    pub fn dir_sizes(&self) -> BTreeMap<String, (u64, u64)> {
+        let mut sizes = BTreeMap::from([(String::new(), (0, 0))]);
+
+        for (path, info) in self.listed_entries() {
+            let mut components = components(path).collect::<Vec<_>>();
+            // Directory markers are the directory itself, not an entry inside of it
+            if !is_directory_marker(path, &info) {
+                components.pop();
+            }
+
+            // The root and every ancestor directory, ending with the entry's own directory
+            let directories = (0..=components.len()).map(|depth| components[..depth].join("/"));
+            for directory in directories {
+                let (compressed, uncompressed) = sizes.entry(directory).or_insert((0, 0));
+                *compressed += u64::from(info.compressed_size);
+                *uncompressed += u64::from(info.uncompressed_size);
+            }
+        }
+
+        sizes
+    }
+
+    pub fn children(&self, prefix: &str) -> BTreeSet<&str> {
+        let prefix = match prefix.is_empty() || prefix.ends_with('/') {
+            true => prefix.into(),
+            false => format!("{prefix}/"),
+        };
+
+        self.listed_paths()
+            .filter_map(|path| path.strip_prefix(prefix.as_str()))
+            .filter(|rest| !rest.is_empty())
+            .map(|rest| match rest.find('/') {
+                // Keep the slash so that directories can be told apart from files
+                Some(end) => &rest[..=end],
+                None => rest,
+            })
+            .collect()
+    }
+}
@@ -1,21 +1,27 @@
-use alloc::{string::String, vec, vec::Vec};
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 use core::cmp::Ordering;
 
 use flate2::Compress;
-use hashbrown::HashMap;
+use hashbrown::HashSet;
 use macros::generator;
 
 use crate::{
     base::{
-        pkg_path_hash, PkgState, RawFlags, ReadSeekWriteRequest, Response, SeekFrom, BUFFER_SIZE,
-        ENTRY_SIZE, HEADER_SIZE, MAGIC,
+        directory_prefix, is_valid_path, pkg_path_hash, GrowthKind, PathRegionGrowth, PkgEvent,
+        PkgState, RawFlags, ReadSeekWriteRequest, Response, SeekFrom, BUFFER_SIZE, ENTRY_SIZE,
+        HEADER_SIZE, MAGIC,
     },
-    EntryCompression, Flags,
+    Compression, EntryCompression, Flags,
 };
 
 use super::{
-    CreateError, Entry, InsertError, RawReadWriteHandle, ReadSeekWriteTruncateRequest, RemoveError,
-    RenameError, RepackError, ReplaceError,
+    AppendError, CopyError, CreateError, DeflateFormat, Entry, Format, InsertError, PathIndex,
+    RawReadWriteHandle, ReadSeekWriteTruncateRequest, RemoveError, RenameError, RepackError,
+    ReplaceError,
 };
 
 const PREALLOCATED_PATH_LEN: u64 = 30;
@@ -23,26 +29,70 @@ const PREALLOCATED_ENTRY_COUNT: u64 = 64;
 
 impl PkgState {
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
-    pub fn create() -> Result<PkgState, CreateError> {
-        request!(rewind);
-
-        request!(write all MAGIC);
-        request!(write u16 be HEADER_SIZE as u16);
-        request!(write u16 be ENTRY_SIZE as u16);
+    pub fn create(format: Format) -> Result<PkgState, CreateError> {
+        if !format.is_supported() {
+            return Err(CreateError::UnsupportedFormat(format));
+        }
 
         let initial_entry_count = PREALLOCATED_ENTRY_COUNT;
         let initial_path_region_size = initial_entry_count * PREALLOCATED_PATH_LEN;
-        request!(write u32 be initial_entry_count as u32);
-        request!(write u32 be initial_path_region_size as u32);
-
-        request!(write repeated 0, initial_path_region_size + initial_entry_count * ENTRY_SIZE);
 
-        Ok(PkgState {
+        let mut state = PkgState {
             path_region_size: initial_path_region_size as u32,
             path_region_empty_offset: 0,
             entries: vec![None; initial_entry_count as usize],
-            path_to_entry_index_map: HashMap::default(),
-        })
+            path_index: PathIndex::default(),
+            hash_to_entry_index_map: None,
+            path_buffer: String::new(),
+            compacted_path_buffer_len: 0,
+            free_regions: Vec::new(),
+            data_end: 0,
+            path_region_growth: PathRegionGrowth::default(),
+            buffer_size: BUFFER_SIZE,
+            header_size: format.header_size.into(),
+            entry_size: format.entry_size.into(),
+            reserved_end: None,
+            deflate_format: DeflateFormat::default(),
+            observer: None,
+            dropped_entries: Vec::new(),
+        };
+
+        state.rebuild_free_regions();
+
+        state.write_header().await;
+        request!(write repeated 0, initial_path_region_size + initial_entry_count * state.entry_size);
+
+        Ok(state)
+    }
+
+    // Encodes the standard fields of the header.
+    fn header_bytes(&self) -> [u8; HEADER_SIZE as usize] {
+        let mut bytes = [0; HEADER_SIZE as usize];
+        bytes[0..4].copy_from_slice(MAGIC);
+        bytes[4..6].copy_from_slice(&(self.header_size as u16).to_be_bytes());
+        bytes[6..8].copy_from_slice(&(self.entry_size as u16).to_be_bytes());
+        bytes[8..12].copy_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.path_region_size.to_be_bytes());
+        bytes
+    }
+
+    // Writes the whole header padded with zeros to the header size, leaving the stream positioned
+    // right after it which is where the entry list starts.
+    //
+    // The standard fields are written all at once so that the entry count and path region size
+    // are always updated together.
+    #[generator(static, yield ReadSeekWriteRequest -> Response)]
+    fn write_header(&self) {
+        request!(rewind);
+        request!(write all self.header_bytes());
+        if self.header_size > HEADER_SIZE {
+            request!(write repeated 0, self.header_size - HEADER_SIZE);
+        }
+    }
+
+    #[generator(static, yield ReadSeekWriteRequest -> Response)]
+    pub fn rewrite_header(&self) {
+        self.write_header().await;
     }
 
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
@@ -63,20 +113,30 @@ impl PkgState {
             .collect::<Vec<_>>();
 
         log::trace!("Moving {} entries", entries_to_move.len());
+        let moved = entries_to_move.len();
         for i in entries_to_move {
             let mut entry = self.entries[i].take().unwrap();
-            let new_offset = request!(seek SeekFrom::End(0));
+            // The file may end before `offset` if the regions before it were never written to
+            let new_offset = request!(seek SeekFrom::End(0)).max(offset);
             // TODO: Do not panic on conversion to u32
             let old_offset = core::mem::replace(&mut entry.data_offset, new_offset as u32);
 
             request!(copy old_offset.into(), entry.data_size.into(), new_offset);
             request!(seek SeekFrom::Start(
-                PkgState::entry_list_offset() + i as u64 * ENTRY_SIZE,
+                self.entry_offset(i)
             ));
-            entry.write().await;
+            entry.write(self.entry_size).await;
 
+            let size = entry.data_size.into();
             self.entries[i] = Some(entry);
+            self.release_data(old_offset.into(), size);
+            self.claim_data(new_offset, size);
         }
+
+        self.emit(|| PkgEvent::DataRegionMoved {
+            offset,
+            entries: moved,
+        });
     }
 
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
@@ -86,9 +146,18 @@ impl PkgState {
 
         request!(copy self.path_region_offset(), self.path_region_size as u64, offset);
 
+        // The newly reserved space has to actually exist in the file, otherwise it may end before
+        // the path region does.
+        request!(seek SeekFrom::Start(offset + self.path_region_size as u64));
+        request!(write repeated 0, new_size - self.path_region_size as u64);
+
         self.path_region_size = new_size as u32;
-        request!(seek SeekFrom::Start(MAGIC.len() as u64 + 8));
-        request!(write u32 be self.path_region_size);
+        self.write_header().await;
+
+        self.emit(|| PkgEvent::PathRegionResized {
+            offset,
+            size: new_size,
+        });
     }
 
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
@@ -107,19 +176,22 @@ impl PkgState {
         request!(write repeated 0, (new_path_region_size - self.path_region_empty_offset).into());
 
         self.path_region_size = new_path_region_size;
+        self.write_header().await;
 
-        request!(seek SeekFrom::Start(MAGIC.len() as u64 + 8));
-        request!(write u32 be self.path_region_size);
+        self.trim_free_regions_to_data_region();
+        self.emit(|| PkgEvent::PathRegionResized {
+            offset: new_path_region_start,
+            size: new_path_region_size.into(),
+        });
     }
 
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
     pub fn reserve_entries(&mut self, amount: u64) {
         log::trace!(target: "silpkg", "Resizing entry list");
-        let required_extra_entry_space = (amount * ENTRY_SIZE) as u32;
+        let required_extra_entry_space = (amount * self.entry_size) as u32;
         let required_extra_path_space = (amount * PREALLOCATED_PATH_LEN) as u32;
 
-        let entry_list_grow_start =
-            PkgState::entry_list_offset() + self.entries.len() as u64 * ENTRY_SIZE;
+        let entry_list_grow_start = self.entry_offset(self.entries.len());
 
         let new_path_region_offset = entry_list_grow_start as u32 + required_extra_entry_space;
 
@@ -137,8 +209,13 @@ impl PkgState {
             self.entries.push(None);
         }
 
-        request!(seek SeekFrom::Start(MAGIC.len() as u64 + 4));
-        request!(write u32 be self.entries.len() as u32);
+        self.write_header().await;
+
+        self.trim_free_regions_to_data_region();
+        self.emit(|| PkgEvent::EntriesReserved {
+            amount,
+            total: self.entries.len(),
+        });
     }
 
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
@@ -148,7 +225,8 @@ impl PkgState {
             self.path_region_empty_offset, self.path_region_size
         );
         if self.path_region_empty_offset + path.len() as u32 + 1 >= self.path_region_size {
-            self.reserve_path_space(path.len() as u32 + 1 + PREALLOCATED_PATH_LEN as u32 * 32)
+            let padding = self.path_region_growth.padding(self.path_region_size);
+            self.reserve_path_space(path.len() as u32 + 1 + padding)
                 .await;
         }
         let offset = self.path_region_empty_offset;
@@ -167,13 +245,14 @@ impl PkgState {
 
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
     pub fn remove(&mut self, path: &str) -> Result<(), RemoveError> {
-        if let Some(entry_idx) = self.path_to_entry_index_map.remove(path) {
-            self.entries[entry_idx] = None;
+        if let Some(entry_idx) = self.unindex_path(path) {
+            let entry = self.entries[entry_idx].take().unwrap();
+            self.release_data(entry.data_offset.into(), entry.data_size.into());
 
             request!(seek SeekFrom::Start(
-                Self::entry_list_offset() + entry_idx as u64 * ENTRY_SIZE,
+                self.entry_offset(entry_idx)
             ));
-            request!(write all [0x00; ENTRY_SIZE as usize]);
+            Entry::write_empty(self.entry_size).await;
 
             // TODO: Slipstream does a nice optimisation here and truncates if the data was at the end
             //       but we can't do that until specialisation comes around. (if we want to support non
@@ -185,81 +264,237 @@ impl PkgState {
         }
     }
 
+    /// Removes the entries in `slots`, rewriting the affected part of the entry list at once.
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    pub fn remove_slots<'a>(&'a mut self, slots: &'a [usize]) {
+        let (Some(&first), Some(&last)) = (slots.iter().min(), slots.iter().max()) else {
+            return;
+        };
+
+        let mut released = Vec::with_capacity(slots.len());
+        for &slot in slots {
+            if let Some(entry) = self.entries[slot].take() {
+                self.path_index
+                    .remove_index(entry.path(&self.path_buffer), slot);
+                released.push((entry.data_offset.into(), entry.data_size.into()));
+            }
+        }
+        // Only released once all of them are removed since they may share data
+        for (offset, size) in released {
+            self.release_data(offset, size);
+        }
+
+        request!(seek SeekFrom::Start(
+            self.entry_offset(first)
+        ));
+        for maybe_entry in self.entries[first..=last].iter() {
+            match maybe_entry {
+                Some(entry) => entry.write(self.entry_size).await,
+                None => Entry::write_empty(self.entry_size).await,
+            }
+        }
+    }
+
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
     pub fn rename(&mut self, src: &str, dst: String) -> Result<(), RenameError> {
-        if !self.path_to_entry_index_map.contains_key(src) {
+        if !self.contains(src) {
             return Err(RenameError::NotFound);
         }
 
-        if self.path_to_entry_index_map.contains_key(&dst) {
+        if self.contains(&dst) {
             return Err(RenameError::AlreadyExists);
         }
 
-        let entry_idx = self.path_to_entry_index_map.remove(src).unwrap();
-        let mut entry = self.entries[entry_idx].as_mut().unwrap();
+        if !is_valid_path(&dst) {
+            return Err(RenameError::InvalidPath);
+        }
 
-        debug_assert_eq!(src, entry.path);
-        entry.path = dst.clone();
+        let entry_idx = self.unindex_path(src).unwrap();
+        self.rename_entry(entry_idx, dst).await;
 
-        // If this is true then the previous path was at the end of the path region and we can just
-        // extend the path region and overwrite it.
-        if entry.relative_path_offset + src.len() as u32 == self.path_region_empty_offset {
-            let relative_path_offset = entry.relative_path_offset;
-            self.reserve_path_space((dst.len() - src.len()) as u32)
+        Ok(())
+    }
+
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    pub fn rename_many<'a>(
+        &'a mut self,
+        pairs: &'a [(&'a str, String)],
+    ) -> Result<(), RenameError> {
+        let mut sources = HashSet::with_capacity(pairs.len());
+        for (src, _) in pairs {
+            // Renaming the same entry twice would make the second rename fail
+            if !self.contains(src) || !sources.insert(*src) {
+                return Err(RenameError::NotFound);
+            }
+        }
+
+        let mut destinations = HashSet::with_capacity(pairs.len());
+        for (_, dst) in pairs {
+            if !destinations.insert(dst.as_str())
+                || (self.contains(dst) && !sources.contains(dst.as_str()))
+            {
+                return Err(RenameError::AlreadyExists);
+            }
+
+            if !is_valid_path(dst) {
+                return Err(RenameError::InvalidPath);
+            }
+        }
+
+        let indices = pairs
+            .iter()
+            .map(|(src, _)| self.unindex_path(src).unwrap())
+            .collect::<Vec<_>>();
+
+        for (entry_idx, (_, dst)) in indices.into_iter().zip(pairs) {
+            self.rename_entry(entry_idx, dst.clone()).await;
+        }
+
+        Ok(())
+    }
+
+    #[generator(static, yield ReadSeekWriteRequest -> Response)]
+    pub fn swap(&mut self, a: &str, b: &str) -> Result<(), RenameError> {
+        let (a_idx, b_idx) = match (self.index(a), self.index(b)) {
+            (Some(a_idx), Some(b_idx)) => (a_idx, b_idx),
+            _ => return Err(RenameError::NotFound),
+        };
+
+        if a_idx == b_idx {
+            return Ok(());
+        }
+
+        // Both paths are already in the path region, so the entries only have to exchange which
+        // one they point to.
+        self.unindex_path(a);
+        self.unindex_path(b);
+        let [Some(one), Some(two)] = self.entries.get_disjoint_mut([a_idx, b_idx]).unwrap() else {
+            unreachable!()
+        };
+        core::mem::swap(&mut one.path_hash, &mut two.path_hash);
+        core::mem::swap(&mut one.relative_path_offset, &mut two.relative_path_offset);
+        core::mem::swap(&mut one.path_start, &mut two.path_start);
+        core::mem::swap(&mut one.path_len, &mut two.path_len);
+        self.index_entry(a_idx);
+        self.index_entry(b_idx);
+
+        for idx in [a_idx, b_idx] {
+            request!(seek SeekFrom::Start(self.entry_offset(idx)));
+            self.entries[idx]
+                .as_ref()
+                .unwrap()
+                .write(self.entry_size)
                 .await;
+        }
 
-            request!(seek SeekFrom::Start(relative_path_offset.into()));
-            request!(write all dst.clone().into_bytes());
-            entry = self.entries[entry_idx].as_mut().unwrap();
-        // If the last path is not at the end the new path has to be inserted at the end and the
-        // entry's path offset updated, the previous path will be removed during a repack.
-        } else {
-            let new_relative_path_offset = self.insert_path_into_path_region(&dst).await;
-            entry = self.entries[entry_idx].as_mut().unwrap();
-            entry.relative_path_offset = new_relative_path_offset;
+        Ok(())
+    }
+
+    // NOTE: The previous path must already be removed from path_index
+    #[generator(static, yield ReadSeekWriteRequest -> Response)]
+    fn rename_entry(&mut self, entry_idx: usize, dst: String) {
+        let (path_start, path_len) = self.push_path(&dst);
+        let mut entry = self.entries[entry_idx].as_mut().unwrap();
+        let src_len = core::mem::replace(&mut entry.path_len, path_len);
+        entry.path_start = path_start;
+        // Hashes are case insensitive so this only changes if more than the case of the path does
+        entry.path_hash = pkg_path_hash(&dst);
+
+        // If the previous path is the last one in the path region it is overwritten by the new
+        // one, otherwise the new path is appended and the previous one is left behind until a
+        // repack.
+        let relative_path_offset = entry.relative_path_offset;
+        let in_place = relative_path_offset + src_len + 1 == self.path_region_empty_offset;
+        if in_place {
+            self.path_region_empty_offset = relative_path_offset;
+        }
+
+        let new_relative_path_offset = self.insert_path_into_path_region(&dst).await;
+        if in_place && src_len > path_len {
+            // Whatever remains of a longer previous path would otherwise look like a used part of
+            // the path region when parsing
+            request!(write repeated 0, (src_len - path_len).into());
         }
 
-        self.path_to_entry_index_map.insert(dst, entry_idx);
+        self.index_entry(entry_idx);
 
-        request!(seek SeekFrom::Start(Self::entry_list_offset() + entry_idx as u64 * ENTRY_SIZE));
-        entry.write().await;
+        request!(seek SeekFrom::Start(self.entry_offset(entry_idx)));
+        entry = self.entries[entry_idx].as_mut().unwrap();
+        entry.relative_path_offset = new_relative_path_offset;
+        entry.write(self.entry_size).await;
+    }
+
+    /// Inserts `dst` with a copy of the data and flags stored for `src`, compressed data is copied
+    /// as is.
+    #[generator(static, yield ReadSeekWriteRequest -> Response)]
+    pub fn copy_entry(&mut self, src: &str, dst: String) -> Result<(), CopyError> {
+        let Some(src_idx) = self.index(src) else {
+            return Err(CopyError::NotFound);
+        };
+        let entry = self.entries[src_idx].as_ref().unwrap();
+        let (size, unpacked_size, flags) = (entry.data_size, entry.unpacked_size, entry.flags);
+
+        let mut handle = self
+            .insert_internal(dst, Flags::default(), Some(size.into()))
+            .await
+            .map_err(|err| match err {
+                InsertError::AlreadyExists => CopyError::AlreadyExists,
+                InsertError::InvalidPath => CopyError::InvalidPath,
+                InsertError::InvalidOffset => unreachable!(),
+                InsertError::Io(err) => CopyError::Io(err),
+            })?;
+        handle.unpacked_size = Some(unpacked_size.into());
+        handle.raw_flags = Some(flags);
+
+        // Inserting may have moved the source data so its offset has to be looked up again
+        let src_offset = handle.state().entries[src_idx]
+            .as_ref()
+            .unwrap()
+            .data_offset;
+        let DataWriteHandle::Raw(raw) = handle.inner_mut() else {
+            unreachable!()
+        };
+        request!(copy src_offset.into(), size.into(), raw.offset);
+        raw.size = size.into();
+        raw.cursor = size.into();
+
+        // Copies are written through a raw handle, which never overflows
+        _ = handle.finish().await;
 
         Ok(())
     }
 
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
     pub fn replace(&mut self, src: &str, dst: String) -> Result<(), ReplaceError> {
-        let res = (
-            self.path_to_entry_index_map.get(src).copied(),
-            self.path_to_entry_index_map.get(&dst).copied(),
-        );
+        let res = (self.index(src), self.index(&dst));
         match res {
             (Some(one_idx), Some(two_idx)) => {
                 let one = self.entries[one_idx].take().unwrap();
-                self.path_to_entry_index_map.remove(src);
+                self.path_index.remove_index(src, one_idx);
+
+                request!(seek SeekFrom::Start(self.entry_offset(one_idx)));
+                Entry::write_empty(self.entry_size).await;
 
+                request!(seek SeekFrom::Start(self.entry_offset(two_idx)));
                 let two = self.entries[two_idx].as_mut().unwrap();
+                let (old_offset, old_size) = (two.data_offset, two.data_size);
                 two.data_offset = one.data_offset;
                 two.data_size = one.data_size;
                 two.unpacked_size = one.unpacked_size;
                 two.flags = one.flags;
+                two.write(self.entry_size).await;
 
-                request!(seek SeekFrom::Start(Self::entry_list_offset() + one_idx as u64 * ENTRY_SIZE));
-                Entry::write_empty().await;
-
-                request!(seek SeekFrom::Start(Self::entry_list_offset() + two_idx as u64 * ENTRY_SIZE));
-                two.write().await;
+                self.release_data(old_offset.into(), old_size.into());
 
                 Ok(())
             }
             (Some(_), None) => {
                 self.rename(src, dst).await.map_err(|x| match x {
                     RenameError::NotFound | RenameError::AlreadyExists => unreachable!(),
+                    RenameError::InvalidPath => ReplaceError::InvalidPath,
                     RenameError::Io(err) => ReplaceError::Io(err),
                 })?;
 
-                self.path_to_entry_index_map.remove(src);
-
                 Ok(())
             }
             (None, _) => Err(ReplaceError::NotFound),
@@ -273,17 +508,122 @@ impl PkgState {
         let mut size = 0;
         for entry in self.entries.iter_mut().map(|e| e.as_mut().unwrap()) {
             entry.relative_path_offset = (request!(stream pos) - offset) as u32;
-            // FIXME: borrow path
-            request!(write all entry.path.clone());
+            request!(write all entry.path(&self.path_buffer));
             request!(write u8 0);
-            size += entry.path.len() + 1;
+            size += entry.path_len as usize + 1;
         }
 
         size as u64
     }
 
+    /// Packs the paths of all entries at the start of the path region without moving any data,
+    /// which reclaims the space left behind by renames and removals for future paths.
+    ///
+    /// The entry list and the used part of the path region are replaced with a single write, like
+    /// [`repack`](Self::repack) does.
+    #[generator(static, yield ReadSeekWriteRequest -> Response)]
+    pub fn compact_paths(&mut self) {
+        if self.stale_path_bytes() == 0 {
+            return;
+        }
+
+        log::trace!(target: "silpkg", "Compacting path region");
+        let mut path_region = Vec::with_capacity(self.path_region_empty_offset as usize);
+        for entry in self.entries.iter_mut().flatten() {
+            entry.relative_path_offset = path_region.len() as u32;
+            path_region.extend_from_slice(entry.path(&self.path_buffer).as_bytes());
+            path_region.push(0);
+        }
+        // Whatever is left of the previously used part is cleared
+        let used = path_region.len() as u32;
+        path_region.resize(self.path_region_empty_offset as usize, 0);
+
+        let mut metadata =
+            Vec::with_capacity(self.entries.len() * self.entry_size as usize + path_region.len());
+        for maybe_entry in self.entries.iter() {
+            let start = metadata.len();
+            if let Some(entry) = maybe_entry {
+                metadata.extend_from_slice(&entry.to_bytes());
+            }
+            metadata.resize(start + self.entry_size as usize, 0);
+        }
+        metadata.extend_from_slice(&path_region);
+        request!(seek SeekFrom::Start(self.entry_list_offset()));
+        request!(write all metadata);
+
+        self.path_region_empty_offset = used;
+    }
+
+    /// Extends the storage so that there are at least `extra_bytes` after the last entry's data,
+    /// which inserts of entries will then fill instead of appending to the end of the storage.
+    #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
+    pub fn preallocate(&mut self, extra_bytes: u64) {
+        if extra_bytes == 0 {
+            return;
+        }
+
+        let storage_len = request!(stream len);
+        let end = self.append_offset(storage_len) + extra_bytes;
+        if end > storage_len {
+            log::trace!(target: "silpkg", "Preallocating storage up to {end}");
+            request!(truncate end);
+        }
+
+        self.reserved_end = Some(end);
+    }
+
+    /// Moves the data of every entry that overlaps another entry with a lower data offset or
+    /// extends past the end of the storage to the end of the archive and then repacks it.
+    ///
+    /// Returns the paths of the relocated entries, any part of their data that lies past the end
+    /// of the storage is filled with zeroes.
+    #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
+    pub fn repair(&mut self) -> Result<Vec<String>, RepackError> {
+        let mut by_offset = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entry)| {
+                entry
+                    .as_ref()
+                    .map(|e| (i, e.data_offset as u64, e.data_size as u64))
+            })
+            .collect::<Vec<_>>();
+        by_offset.sort_by_key(|&(_, offset, size)| (offset, size));
+
+        let mut relocated = Vec::new();
+        let mut covered_end = 0;
+        let original_len = request!(stream len);
+        let mut relocation_offset = original_len;
+        for (index, offset, size) in by_offset {
+            // Entries that extend past the end of the storage are relocated too, otherwise they
+            // could end up overlapping the relocated data.
+            if offset >= covered_end && offset + size <= original_len {
+                covered_end = offset + size;
+                continue;
+            }
+
+            let available = size.min(original_len.saturating_sub(offset));
+            log::trace!(target: "silpkg", "Relocating entry {index} to {relocation_offset}");
+            request!(copy offset, available, relocation_offset);
+            request!(seek SeekFrom::Start(relocation_offset + available));
+            request!(write repeated 0, size - available);
+
+            let entry = self.entries[index].as_mut().unwrap();
+            entry.data_offset = relocation_offset as u32;
+            relocated.push(entry.path(&self.path_buffer).to_string());
+            relocation_offset += size;
+        }
+
+        self.repack().await?;
+
+        Ok(relocated)
+    }
+
     #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
     pub fn repack(&mut self) -> Result<(), RepackError> {
+        let old_data_region_start = self.data_region_offset();
+
         // Remove empty entries
         for entry in core::mem::take(&mut self.entries) {
             if entry.is_some() {
@@ -300,11 +640,14 @@ impl PkgState {
                 ord => ord,
             }
         });
+        // Entries moved to other slots, this has to be done before anything can fail
+        self.path_index.rebuild(&self.entries, &self.path_buffer);
 
         // Check for overlapping entries
         for window in self.entries.windows(2) {
             if let [Some(a), Some(b)] = window {
-                if a.data_offset + a.data_size > b.data_offset {
+                // Done in u64 since entries near the end of the addressable range could overflow
+                if a.data_offset as u64 + a.data_size as u64 > b.data_offset as u64 {
                     return Err(RepackError::OverlappingEntries);
                 }
             } else {
@@ -312,77 +655,534 @@ impl PkgState {
             };
         }
 
-        let path_region_size: usize = self
-            .entries
-            .iter()
-            .map(|entry| entry.as_ref().unwrap().path.len() + 1)
-            .sum();
-
-        let path_region_offset = self.path_region_offset();
-        let data_region_start = path_region_offset + path_region_size as u64;
-
-        assert!(data_region_start <= self.data_region_offset());
+        // Pack the path region in data order
+        let mut path_region = Vec::new();
+        for entry in self.entries.iter_mut().map(|e| e.as_mut().unwrap()) {
+            entry.relative_path_offset = path_region.len() as u32;
+            path_region.extend_from_slice(entry.path(&self.path_buffer).as_bytes());
+            path_region.push(0);
+        }
+        self.path_region_size = path_region.len() as u32;
+        self.path_region_empty_offset = self.path_region_size;
 
-        // Update the path region
-        log::trace!(target: "silpkg", "Packing path region");
-        self.path_region_size = self.write_packed_path_region_at(path_region_offset).await as u32;
-        assert_eq!(self.path_region_size, path_region_size as u32);
+        let data_region_start = self.data_region_offset();
+        assert!(data_region_start <= old_data_region_start);
 
-        // Defragment? the data region
+        // The entry list is sorted by path hash, remember which slot each entry ends up in so
+        // that the data can still be moved in the order it is stored in
+        let mut sorted = core::mem::take(&mut self.entries)
+            .into_iter()
+            .enumerate()
+            .collect::<Vec<_>>();
+        sorted.sort_by_key(|(_, entry)| entry.as_ref().unwrap().path_hash);
+        let mut data_order = vec![0; sorted.len()];
+        for (slot, (position, entry)) in sorted.into_iter().enumerate() {
+            data_order[position] = slot;
+            self.entries.push(entry);
+        }
+        self.compact_path_buffer();
+
+        self.path_index.rebuild(&self.entries, &self.path_buffer);
+
+        // The header, entry list and path region are replaced with a single write so that the
+        // archive never contains entries pointing into a path region that was already moved.
+        // The data still lies past the end of the old path region at this point, so none of it
+        // is overwritten.
+        log::trace!(target: "silpkg", "Rewriting entry list and path region");
+        let mut metadata = Vec::with_capacity(data_region_start as usize);
+        metadata.extend_from_slice(&self.header_bytes());
+        metadata.resize(self.header_size as usize, 0);
+        for entry in self.entries.iter().map(|e| e.as_ref().unwrap()) {
+            metadata.extend_from_slice(&entry.to_bytes());
+            metadata.resize(metadata.len() + (self.entry_size - ENTRY_SIZE) as usize, 0);
+        }
+        metadata.extend_from_slice(&path_region);
+        request!(rewind);
+        request!(write all metadata);
 
-        let mut current_data_offset = data_region_start as u32;
-        // TODO: The unwraps are getting really annoying and possibly degrading performance
-        //       Maybe something should be done about this? (I would love to avoid unwrap unchecked too)
+        // Then the data is moved down one entry at a time, each entry is updated right after its
+        // data was moved
         log::trace!(target: "silpkg", "Defragmenting data region");
-        for entry in self.entries.iter_mut().map(|e| e.as_mut().unwrap()) {
+        let mut current_data_offset = data_region_start as u32;
+        for i in data_order {
+            let entry = self.entries[i].as_mut().unwrap();
             if current_data_offset != entry.data_offset {
                 request!(copy entry.data_offset.into(), entry.data_size.into(), current_data_offset.into());
-
                 entry.data_offset = current_data_offset;
+
+                request!(seek SeekFrom::Start(self.entry_offset(i)));
+                self.entries[i]
+                    .as_ref()
+                    .unwrap()
+                    .write(self.entry_size)
+                    .await;
             }
 
+            current_data_offset += self.entries[i].as_ref().unwrap().data_size;
+        }
+
+        request!(truncate current_data_offset.into());
+
+        self.rebuild_free_regions();
+        self.reserved_end = None;
+
+        Ok(())
+    }
+
+    /// Repacks the archive and then reorders it so that its layout only depends on the paths and
+    /// data of its entries.
+    ///
+    /// Both the data and the path region are stored in path order, while the entry list is
+    /// sorted by path hash with ties broken by path.
+    #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
+    pub fn canonicalize(&mut self) -> Result<(), RepackError> {
+        self.repack().await?;
+
+        let path_buffer = &self.path_buffer;
+        self.entries.sort_by(|a, b| {
+            let (a, b) = (a.as_ref().unwrap(), b.as_ref().unwrap());
+            a.path(path_buffer).cmp(b.path(path_buffer))
+        });
+        self.path_index.rebuild(&self.entries, &self.path_buffer);
+
+        let order = (0..self.entries.len()).collect::<Vec<_>>();
+        self.reorder_data(&order).await;
+
+        log::trace!(target: "silpkg", "Reordering path region");
+        let path_region_size = self
+            .write_packed_path_region_at(self.path_region_offset())
+            .await;
+        debug_assert_eq!(path_region_size, self.path_region_size as u64);
+
+        let path_buffer = &self.path_buffer;
+        self.entries.sort_by(|a, b| {
+            let (a, b) = (a.as_ref().unwrap(), b.as_ref().unwrap());
+            a.path_hash
+                .cmp(&b.path_hash)
+                .then_with(|| a.path(path_buffer).cmp(b.path(path_buffer)))
+        });
+        self.compact_path_buffer();
+
+        self.path_index.rebuild(&self.entries, &self.path_buffer);
+
+        log::trace!(target: "silpkg", "Rewriting entry list");
+        self.write_header().await;
+        self.write_entry_list().await;
+
+        Ok(())
+    }
+
+    /// Repacks the archive and then reorders its data so that the data of entries in the same
+    /// directory, up to `depth` directories deep, is stored next to each other.
+    ///
+    /// Directories are ordered by path, the entries inside of each one keep their relative order.
+    #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
+    pub fn cluster_by_prefix(&mut self, depth: usize) -> Result<(), RepackError> {
+        self.repack().await?;
+
+        let path_buffer = &self.path_buffer;
+        let mut order = (0..self.entries.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| {
+            let entry = self.entries[i].as_ref().unwrap();
+            (
+                directory_prefix(entry.path(path_buffer), depth),
+                entry.data_offset,
+            )
+        });
+        self.reorder_data(&order).await;
+
+        Ok(())
+    }
+
+    // Moves the data of the entries in the slots in `order` so that it is stored in that order
+    // right after the path region, the data region must already be packed.
+    //
+    // The data is first copied past its end and the entries are pointed at the copies, then it
+    // is moved back down all at once and the entries are updated again. Every step only
+    // overwrites data no entry points to, so an interrupted reorder leaves all entries intact.
+    #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
+    fn reorder_data(&mut self, order: &[usize]) {
+        let data_start = self.data_region_offset();
+        let data_end = self.data_end();
+        log::trace!(target: "silpkg", "Reordering data region");
+        let mut offset = data_end;
+        for &i in order {
+            let entry = self.entries[i].as_mut().unwrap();
+            if entry.data_size > 0 {
+                request!(copy entry.data_offset.into(), entry.data_size.into(), offset);
+            }
+            entry.data_offset = offset as u32;
+            offset += entry.data_size as u64;
+        }
+
+        // Copies past the addressable range can't be pointed at, they only exist until the data
+        // is moved back down anyway
+        if offset <= u32::MAX as u64 {
+            self.write_entry_list().await;
+        }
+
+        request!(copy data_end, offset - data_end, data_start);
+        let mut current_data_offset = data_start as u32;
+        for &i in order {
+            let entry = self.entries[i].as_mut().unwrap();
+            entry.data_offset = current_data_offset;
             current_data_offset += entry.data_size;
         }
+        self.write_entry_list().await;
+
+        request!(truncate data_end);
+    }
+
+    // Writes every slot of the entry list.
+    #[generator(static, yield ReadSeekWriteRequest -> Response)]
+    fn write_entry_list(&self) {
+        request!(seek SeekFrom::Start(self.entry_list_offset()));
+        for maybe_entry in self.entries.iter() {
+            match maybe_entry {
+                Some(entry) => entry.write(self.entry_size).await,
+                None => Entry::write_empty(self.entry_size).await,
+            }
+        }
+    }
+
+    // Finds the smallest free region that can fit `size` bytes.
+    fn find_free_region(&self, size: u64) -> Option<u64> {
+        self.free_regions
+            .iter()
+            .filter(|(_, region_size)| *region_size >= size)
+            .min_by_key(|(_, region_size)| *region_size)
+            .map(|(offset, _)| *offset)
+    }
 
-        self.entries
-            .sort_by_key(|entry| entry.as_ref().unwrap().path_hash);
+    #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
+    pub fn shrink_entry_table(&mut self) {
+        let new_entry_count = self
+            .entries
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |i| i + 1);
 
-        // Update path_to_entry_index_map
-        for (i, entry) in self.entries.iter().enumerate() {
-            *self
-                .path_to_entry_index_map
-                .get_mut(&entry.as_ref().unwrap().path)
-                .unwrap() = i;
+        if new_entry_count == self.entries.len() {
+            return;
         }
 
-        // And finally, update the header and write the entries!
-        log::trace!(target: "silpkg", "Rewriting entry list");
-        request!(seek SeekFrom::Start(MAGIC.len() as u64 + 4));
-        request!(write u32 be self.entries.len() as u32);
-        request!(write u32 be path_region_size as u32);
+        log::trace!(target: "silpkg",
+            "Shrinking entry list from {} to {new_entry_count} entries",
+            self.entries.len()
+        );
+
+        let old_path_region_offset = self.path_region_offset();
+        let old_data_region_offset = self.data_region_offset();
+        let storage_len = request!(stream len);
+
+        self.entries.truncate(new_entry_count);
+        let shift = old_path_region_offset - self.path_region_offset();
+
+        // Both regions are moved towards the start of the file so a simple forward copy is fine
+        request!(copy old_path_region_offset, storage_len - old_path_region_offset, self.path_region_offset());
+
+        for entry in self.entries.iter_mut().flatten() {
+            debug_assert!(entry.data_offset as u64 >= old_data_region_offset);
+            entry.data_offset -= shift as u32;
+        }
+
+        self.write_header().await;
 
         for maybe_entry in self.entries.iter() {
             match maybe_entry {
-                Some(entry) => entry.write().await,
-                None => request!(write repeated 0, ENTRY_SIZE),
+                Some(entry) => entry.write(self.entry_size).await,
+                None => Entry::write_empty(self.entry_size).await,
             }
         }
 
-        request!(truncate current_data_offset.into());
+        request!(truncate storage_len - shift);
+        self.reserved_end = self.reserved_end.map(|end| end - shift);
+
+        for (region_offset, _) in self.free_regions.iter_mut() {
+            *region_offset -= shift;
+        }
+        self.data_end -= shift;
+    }
+
+    /// Removes an entry and immediately reclaims the space occupied by its data by moving
+    /// everything stored after it down.
+    #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
+    pub fn remove_and_reclaim(&mut self, path: &str) -> Result<(), RemoveError> {
+        let Some(entry_idx) = self.unindex_path(path) else {
+            return Err(RemoveError::NotFound);
+        };
+        let entry = self.entries[entry_idx].take().unwrap();
+
+        request!(seek SeekFrom::Start(
+            self.entry_offset(entry_idx)
+        ));
+        Entry::write_empty(self.entry_size).await;
 
+        self.reclaim(entry.data_offset, entry.data_size).await;
         Ok(())
     }
 
+    // Closes the hole of `freed_size` bytes at `freed_offset` left behind by a removed entry by
+    // moving all data after it down, then truncates the storage.
+    #[generator(static, yield ReadSeekWriteTruncateRequest -> Response)]
+    fn reclaim(&mut self, freed_offset: u32, freed_size: u32) {
+        let start = freed_offset as u64;
+        let end = start + freed_size as u64;
+        // Data shared with another entry has to stay where it is, the space is then only
+        // reclaimed once all of them are removed
+        let shared = self.entries.iter().flatten().any(|entry| {
+            let entry_start = entry.data_offset as u64;
+            entry.data_size > 0 && entry_start < end && entry_start + entry.data_size as u64 > start
+        });
+        self.release_data(start, freed_size.into());
+        if freed_size == 0 || shared {
+            return;
+        }
+
+        log::trace!(target: "silpkg", "Reclaiming {freed_size} bytes at {freed_offset}");
+
+        let storage_len = request!(stream len);
+        if end < storage_len {
+            // Everything is moved towards the start of the file so a simple forward copy is fine
+            request!(copy end, storage_len - end, start);
+        }
+
+        request!(seek SeekFrom::Start(self.entry_list_offset()));
+        for maybe_entry in self.entries.iter_mut() {
+            match maybe_entry {
+                Some(entry) => {
+                    if entry.data_offset as u64 >= end {
+                        entry.data_offset -= freed_size;
+                    } else if entry.data_offset > freed_offset {
+                        // Empty entries pointing into the hole
+                        entry.data_offset = freed_offset;
+                    }
+                    entry.write(self.entry_size).await
+                }
+                None => Entry::write_empty(self.entry_size).await,
+            }
+        }
+
+        request!(truncate storage_len.saturating_sub(freed_size as u64).max(start));
+        self.reserved_end = self.reserved_end.map(|reserved| match reserved {
+            reserved if reserved >= end => reserved - freed_size as u64,
+            reserved => reserved.min(start),
+        });
+
+        // The hole is part of a free region now, which shrinks along with everything after it
+        let collapse = |offset: u64| match offset {
+            offset if offset >= end => offset - freed_size as u64,
+            offset => offset.min(start),
+        };
+        for (region_offset, region_size) in self.free_regions.iter_mut() {
+            let region_end = collapse(*region_offset + *region_size);
+            *region_offset = collapse(*region_offset);
+            *region_size = region_end - *region_offset;
+        }
+        self.free_regions
+            .retain(|&(_, region_size)| region_size > 0);
+        self.data_end = collapse(self.data_end);
+    }
+
     #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
     pub fn insert<'a>(
         &'a mut self,
         path: String,
         flags: Flags,
     ) -> Result<WriteHandle<'a>, InsertError> {
-        if self.path_to_entry_index_map.contains_key(&path) {
+        self.insert_internal(path, flags, None).await
+    }
+
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    pub fn insert_sized<'a>(
+        &'a mut self,
+        path: String,
+        size: u64,
+    ) -> Result<WriteHandle<'a>, InsertError> {
+        self.insert_internal(path, Flags::default(), Some(size))
+            .await
+    }
+
+    /// Opens an existing uncompressed entry whose data is at the very end of the archive for
+    /// appending.
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    pub fn append<'a>(&'a mut self, path: String) -> Result<WriteHandle<'a>, AppendError> {
+        let entry_slot = match self.index(&path) {
+            Some(index) => index,
+            None => return Err(AppendError::NotFound),
+        };
+        let entry = self.entries[entry_slot].as_ref().unwrap();
+
+        let end = entry.data_offset as u64 + entry.data_size as u64;
+        // Entries with unknown flags are rejected too since they would be lost on flush
+        if !entry.flags.is_empty() || end != self.append_offset(request!(stream len)) {
+            return Err(AppendError::NotAppendable);
+        }
+
+        request!(seek SeekFrom::Start(end));
+
+        Ok(WriteHandle {
+            inner: DataWriteHandle::Raw(RawReadWriteHandle {
+                cursor: entry.data_size as u64,
+                offset: entry.data_offset as u64,
+                size: entry.data_size as u64,
+            }),
+            capacity: None,
+            unpacked_size: None,
+            raw_flags: None,
+            path,
+            relative_path_offset: entry.relative_path_offset,
+            entry_slot,
+            flags: Flags::default(),
+            state: self,
+        })
+    }
+
+    /// Inserts an entry whose data will be written already deflate compressed.
+    ///
+    /// The returned handle writes raw bytes, but the entry is marked as compressed and
+    /// `unpacked_size` is recorded as its uncompressed size.
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    pub fn insert_precompressed<'a>(
+        &'a mut self,
+        path: String,
+        unpacked_size: u64,
+    ) -> Result<WriteHandle<'a>, InsertError> {
+        let handle = self.insert_internal(path, Flags::default(), None).await?;
+        Ok(handle.into_precompressed(unpacked_size))
+    }
+
+    /// Writes new data for the existing entry at `path`.
+    ///
+    /// The new data never overwrites the old one, the entry keeps pointing at its old data until
+    /// the returned handle is flushed.
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    pub fn overwrite<'a>(
+        &'a mut self,
+        path: String,
+        flags: Flags,
+    ) -> Result<WriteHandle<'a>, ReplaceError> {
+        self.overwrite_internal(path, flags).await
+    }
+
+    /// Like [`overwrite`](Self::overwrite) but the data will be written already deflate
+    /// compressed, see [`insert_precompressed`](Self::insert_precompressed).
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    pub fn overwrite_precompressed<'a>(
+        &'a mut self,
+        path: String,
+        unpacked_size: u64,
+    ) -> Result<WriteHandle<'a>, ReplaceError> {
+        let handle = self.overwrite_internal(path, Flags::default()).await?;
+        Ok(handle.into_precompressed(unpacked_size))
+    }
+
+    /// Returns which regions inserting `path` would grow, mirroring the checks done by
+    /// `insert_internal` and `insert_path_into_path_region`.
+    pub fn insert_growth(&self, path: &str) -> GrowthKind {
+        if self.contains(path) {
+            return GrowthKind::None;
+        }
+
+        let grows_entry_list = self.entries.iter().all(Option::is_some);
+        let path_region_size = match grows_entry_list {
+            true => self.path_region_size as u64 + PREALLOCATED_ENTRY_COUNT * PREALLOCATED_PATH_LEN,
+            false => self.path_region_size as u64,
+        };
+        let grows_path_region =
+            self.path_region_empty_offset as u64 + path.len() as u64 + 1 >= path_region_size;
+
+        match (grows_entry_list, grows_path_region) {
+            (false, false) => GrowthKind::None,
+            (true, false) => GrowthKind::EntryList,
+            (false, true) => GrowthKind::PathRegion,
+            (true, true) => GrowthKind::EntryListAndPathRegion,
+        }
+    }
+
+    /// Inserts a new entry whose data is written starting at `data_offset` instead of wherever it
+    /// would usually be placed.
+    ///
+    /// `data_offset` has to be inside the data region and must not be inside the data of another
+    /// entry. Data is limited to the space before the data of the next entry, uncompressed writes
+    /// are shortened to fit while compressed data that doesn't fit makes the handle
+    /// [`overflowed`](WriteHandle::overflowed), after which nothing is written and the entry is
+    /// never updated.
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    pub fn insert_at<'a>(
+        &'a mut self,
+        path: String,
+        flags: Flags,
+        data_offset: u64,
+    ) -> Result<WriteHandle<'a>, InsertError> {
+        // Checked up front too so that obviously invalid offsets don't modify the archive
+        if !self.is_free_data_offset(data_offset) {
+            return Err(InsertError::InvalidOffset);
+        }
+
+        let (entry_slot, relative_path_offset) = self.reserve_entry(&path).await?;
+        // Growing the path region moves the data region, which may now cover `data_offset`
+        if !self.is_free_data_offset(data_offset) {
+            return Err(InsertError::InvalidOffset);
+        }
+
+        let capacity = self
+            .entries
+            .iter()
+            .flatten()
+            .filter(|entry| entry.data_size > 0 && entry.data_offset as u64 > data_offset)
+            .map(|entry| entry.data_offset as u64 - data_offset)
+            .min();
+
+        log::trace!(target: "silpkg", "Placing {path} at {data_offset}");
+        request!(seek SeekFrom::Start(data_offset));
+        Ok(self.handle_at(
+            path,
+            flags,
+            data_offset,
+            capacity,
+            entry_slot,
+            relative_path_offset,
+        ))
+    }
+
+    // Returns whether data of a new entry can start at `offset`.
+    fn is_free_data_offset(&self, offset: u64) -> bool {
+        offset >= self.data_region_offset()
+            && offset <= u32::MAX as u64
+            && !self.entries.iter().flatten().any(|entry| {
+                let start = entry.data_offset as u64;
+                (start..start + entry.data_size as u64).contains(&offset)
+            })
+    }
+
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    fn insert_internal<'a>(
+        &'a mut self,
+        path: String,
+        flags: Flags,
+        size: Option<u64>,
+    ) -> Result<WriteHandle<'a>, InsertError> {
+        let (entry_slot, relative_path_offset) = self.reserve_entry(&path).await?;
+        Ok(self
+            .write_handle(path, flags, size, entry_slot, relative_path_offset)
+            .await)
+    }
+
+    // Validates `path` and reserves an entry slot and space in the path region for it, returning
+    // the slot and the offset of the path. The slot stays empty and the path isn't looked up
+    // until a handle for it is flushed, so abandoning the handle leaves nothing behind but the
+    // stale path.
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    fn reserve_entry<'a>(&'a mut self, path: &'a str) -> Result<(usize, u32), InsertError> {
+        if self.contains(path) {
             return Err(InsertError::AlreadyExists);
         }
 
+        if !is_valid_path(path) {
+            return Err(InsertError::InvalidPath);
+        }
+
         let entry_slot = match self.entries.iter().enumerate().find(|(_i, o)| o.is_none()) {
             Some((i, _o)) => i,
             None => {
@@ -392,21 +1192,89 @@ impl PkgState {
             }
         };
 
-        assert!(self
-            .path_to_entry_index_map
-            .insert(path.clone(), entry_slot)
-            .is_none());
+        let relative_path_offset = self.insert_path_into_path_region(path).await;
+        Ok((entry_slot, relative_path_offset))
+    }
 
-        let relative_path_offset = self.insert_path_into_path_region(&path).await;
-        let data_offset = request!(seek SeekFrom::End(0));
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    fn overwrite_internal<'a>(
+        &'a mut self,
+        path: String,
+        flags: Flags,
+    ) -> Result<WriteHandle<'a>, ReplaceError> {
+        let entry_slot = match self.index(&path) {
+            Some(index) => index,
+            None => return Err(ReplaceError::NotFound),
+        };
+        let relative_path_offset = self.entries[entry_slot]
+            .as_ref()
+            .unwrap()
+            .relative_path_offset;
+
+        Ok(self
+            .write_handle(path, flags, None, entry_slot, relative_path_offset)
+            .await)
+    }
 
-        Ok(WriteHandle {
+    // Creates a handle that writes new data for the entry in `entry_slot`, the entry itself is
+    // only updated once the handle is flushed.
+    #[generator(static, yield ReadSeekWriteRequest -> Response, use<'a>)]
+    fn write_handle<'a>(
+        &'a mut self,
+        path: String,
+        flags: Flags,
+        size: Option<u64>,
+        entry_slot: usize,
+        relative_path_offset: u32,
+    ) -> WriteHandle<'a> {
+        // Entries with an unknown size have to be appended since they could grow indefinitely.
+        // Zero sized entries are also appended since they don't take up any space anyway.
+        let data_offset = match size
+            .filter(|size| *size > 0)
+            .and_then(|size| self.find_free_region(size))
+        {
+            Some(offset) => {
+                log::trace!(target: "silpkg", "Reusing free space at {offset} for {path}");
+                request!(seek SeekFrom::Start(offset))
+            }
+            None => {
+                let storage_len = request!(stream len);
+                request!(seek SeekFrom::Start(self.append_offset(storage_len)))
+            }
+        };
+
+        self.handle_at(
+            path,
+            flags,
+            data_offset,
+            size,
+            entry_slot,
+            relative_path_offset,
+        )
+    }
+
+    // Creates a handle that writes the data of the entry in `entry_slot` at `data_offset`, the
+    // storage has to already be positioned there.
+    fn handle_at(
+        &mut self,
+        path: String,
+        flags: Flags,
+        data_offset: u64,
+        capacity: Option<u64>,
+        entry_slot: usize,
+        relative_path_offset: u32,
+    ) -> WriteHandle<'_> {
+        WriteHandle {
             inner: match flags.compression {
                 EntryCompression::Deflate(level) => DataWriteHandle::Deflate(DeflateWriteHandle {
                     offset: data_offset,
                     size: 0,
                     unpacked_size: 0,
-                    compress: Compress::new(level, true),
+                    buffer_size: self.buffer_size,
+                    compress: Compress::new(level, self.deflate_format.zlib_header()),
+                    out: Vec::with_capacity(self.buffer_size as usize),
+                    capacity,
+                    overflowed: false,
                 }),
                 EntryCompression::None => DataWriteHandle::Raw(RawReadWriteHandle {
                     cursor: 0,
@@ -415,12 +1283,15 @@ impl PkgState {
                 }),
             },
 
+            capacity,
+            unpacked_size: None,
+            raw_flags: None,
             state: self,
             path,
             relative_path_offset,
             entry_slot,
             flags,
-        })
+        }
     }
 }
 
@@ -434,7 +1305,16 @@ pub struct DeflateWriteHandle {
     offset: u64,
     size: u64,
     unpacked_size: u64,
+    buffer_size: u64,
     compress: flate2::Compress,
+    // Compressed output waiting to be written. compress_vec only fills the spare capacity, so this
+    // never grows past buffer_size no matter how much data is written at once.
+    out: Vec<u8>,
+    // Maximum amount of compressed bytes that can be written, see WriteHandle::capacity.
+    capacity: Option<u64>,
+    // Set once the compressed output didn't fit into capacity, nothing is written afterwards and
+    // the entry is never updated.
+    overflowed: bool,
 }
 
 pub enum DataWriteHandle {
@@ -444,6 +1324,14 @@ pub enum DataWriteHandle {
 
 pub struct WriteHandle<'a> {
     inner: DataWriteHandle,
+    // Maximum amount of bytes that can be written, used when writing into free space between
+    // other entries.
+    capacity: Option<u64>,
+    // Uncompressed size of data that was compressed before being written into a raw handle.
+    unpacked_size: Option<u64>,
+    // Flags stored verbatim instead of the ones derived from `flags`, used when copying entries
+    // so that unknown flags aren't lost.
+    raw_flags: Option<RawFlags>,
 
     // Used during flush
     state: &'a mut PkgState,
@@ -458,16 +1346,59 @@ impl<'b> WriteHandle<'b> {
         &mut self.inner
     }
 
+    pub fn state(&self) -> &PkgState {
+        self.state
+    }
+
+    // Whether compressed data didn't fit before the data of the next entry, see
+    // PkgState::insert_at.
+    pub fn overflowed(&self) -> bool {
+        matches!(&self.inner, DataWriteHandle::Deflate(deflate) if deflate.overflowed)
+    }
+
+    pub fn written(&self) -> u64 {
+        match &self.inner {
+            DataWriteHandle::Raw(RawReadWriteHandle { size, .. }) => *size,
+            DataWriteHandle::Deflate(DeflateWriteHandle { unpacked_size, .. }) => *unpacked_size,
+        }
+    }
+
+    // Marks the entry as compressed while keeping the raw handle, the data written through it has
+    // to be compressed already.
+    fn into_precompressed(mut self, unpacked_size: u64) -> Self {
+        self.flags = Flags {
+            compression: EntryCompression::Deflate(Compression::default()),
+        };
+        self.unpacked_size = Some(unpacked_size);
+        self
+    }
+
     #[generator(static, yield ReadSeekWriteRequest -> Response, use<'_, 'b>)]
-    fn flush_internal(&mut self) -> () {
+    fn flush_internal(&mut self) -> Result<(), InsertError> {
         match &mut self.inner {
             DataWriteHandle::Deflate(deflate) => deflate.flush().await,
             _ => (),
         }
 
+        if self.overflowed() {
+            log::trace!(
+                "Not updating entry {}, its data didn't fit at its offset",
+                self.entry_slot
+            );
+            return Err(InsertError::InvalidOffset);
+        }
+
         log::trace!("Updating entry {} with written data", self.entry_slot);
 
-        let entry = match self.inner {
+        // Entries that are written to again keep their path where it already is
+        let (path_start, path_len) = match &self.state.entries[self.entry_slot] {
+            Some(entry) if entry.path(&self.state.path_buffer) == self.path => {
+                (entry.path_start, entry.path_len)
+            }
+            _ => self.state.push_path(&self.path),
+        };
+
+        let mut entry = match self.inner {
             DataWriteHandle::Raw(RawReadWriteHandle {
                 offset,
                 size: unpacked_size @ size,
@@ -484,7 +1415,8 @@ impl<'b> WriteHandle<'b> {
                 unpacked_size: unpacked_size as u32,
                 path_hash: pkg_path_hash(&self.path),
                 relative_path_offset: self.relative_path_offset,
-                path: self.path.clone(),
+                path_start,
+                path_len,
                 flags: match self.flags.compression {
                     EntryCompression::Deflate(_) => RawFlags::DEFLATED,
                     EntryCompression::None => RawFlags::empty(),
@@ -492,18 +1424,40 @@ impl<'b> WriteHandle<'b> {
             },
         };
 
-        request!(seek SeekFrom::Start(
-            PkgState::entry_list_offset() + self.entry_slot as u64 * ENTRY_SIZE,
-        ));
+        if let Some(unpacked_size) = self.unpacked_size {
+            entry.unpacked_size = unpacked_size as u32;
+        }
+        if let Some(flags) = self.raw_flags {
+            entry.flags = flags;
+        }
 
-        entry.write().await;
-        self.state.entries[self.entry_slot] = Some(entry);
+        request!(seek SeekFrom::Start(self.state.entry_offset(self.entry_slot)));
+
+        entry.write(self.state.entry_size).await;
+        let (offset, size) = (entry.data_offset.into(), entry.data_size.into());
+        let previous = self.state.entries[self.entry_slot].replace(entry);
+        // Entries that are overwritten keep their slot and are already indexed
+        self.state.index_entry(self.entry_slot);
+        self.state.claim_data(offset, size);
+        // Flushing again only ever extends the data, anything else needs to check whether the
+        // previous data is still used
+        if let Some(previous) = previous {
+            if previous.data_offset as u64 != offset || previous.data_size as u64 > size {
+                self.state
+                    .release_data(previous.data_offset.into(), previous.data_size.into());
+            }
+        }
+        self.state.emit(|| PkgEvent::EntryWritten {
+            path: self.path.clone(),
+            offset,
+            size,
+        });
 
-        Default::default()
+        Ok(())
     }
 
     #[generator(static, yield ReadSeekWriteRequest -> Response, use<'_, 'b>)]
-    pub fn flush(&mut self) -> () {
+    pub fn flush(&mut self) -> Result<(), InsertError> {
         let (offset, cursor) = match self.inner {
             DataWriteHandle::Raw(RawReadWriteHandle { cursor, offset, .. })
             | DataWriteHandle::Deflate(DeflateWriteHandle {
@@ -513,15 +1467,15 @@ impl<'b> WriteHandle<'b> {
             }) => (offset, cursor),
         };
 
-        self.flush_internal().await;
+        self.flush_internal().await?;
         request!(seek SeekFrom::Start(offset + cursor));
 
-        Default::default()
+        Ok(())
     }
 
     #[generator(static, yield ReadSeekWriteRequest -> Response, use<'b>)]
-    pub fn finish(mut self) {
-        self.flush_internal().await;
+    pub fn finish(mut self) -> Result<(), InsertError> {
+        self.flush_internal().await
     }
 }
 
@@ -531,8 +1485,8 @@ impl GeneratorWrite for RawReadWriteHandle {
         log::trace!("Writing entry data to {}", self.offset);
 
         let written = request!(write buf);
-        self.size += written as u64;
         self.cursor += written as u64;
+        self.size = self.size.max(self.cursor);
 
         written
     }
@@ -543,10 +1497,13 @@ impl GeneratorWrite for DeflateWriteHandle {
     fn write(&mut self, mut buf: &[u8]) -> usize {
         log::trace!("Writing compressed entry data at {}", self.offset);
 
+        if self.overflowed {
+            return 0;
+        }
+
         let mut output = 0;
         let mut written = 0;
 
-        let mut out = Vec::with_capacity(BUFFER_SIZE as usize);
         loop {
             let prev_in = self.compress.total_in();
             let prev_out = self.compress.total_out();
@@ -554,7 +1511,7 @@ impl GeneratorWrite for DeflateWriteHandle {
             // log::trace!("compressing buffer of size {}", buf.len());
             let status = self
                 .compress
-                .compress_vec(buf, &mut out, flate2::FlushCompress::None)
+                .compress_vec(buf, &mut self.out, flate2::FlushCompress::None)
                 .unwrap();
 
             // log::trace!(
@@ -564,9 +1521,13 @@ impl GeneratorWrite for DeflateWriteHandle {
             //     self.compress.total_out() - prev_out
             // );
 
-            // FIXME: don't
-            request!(write all out.clone());
-            out.clear();
+            if !self.fits(output + self.out.len() as u64) {
+                self.overflowed = true;
+                self.out.clear();
+                return 0;
+            }
+            request!(write all &self.out);
+            self.out.clear();
 
             let output_now = self.compress.total_out() - prev_out;
             let written_now = (self.compress.total_in() - prev_in) as usize;
@@ -590,22 +1551,28 @@ impl GeneratorWrite for DeflateWriteHandle {
 }
 
 impl DeflateWriteHandle {
+    // Returns whether `extra` more compressed bytes still fit into the capacity.
+    fn fits(&self, extra: u64) -> bool {
+        self.capacity
+            .is_none_or(|capacity| self.size + extra <= capacity)
+    }
+
     #[generator(static, yield ReadSeekWriteRequest -> Response)]
     pub fn flush(&mut self) {
-        let mut out = Vec::with_capacity(BUFFER_SIZE as usize);
-
-        loop {
+        while !self.overflowed {
             self.compress
-                .compress_vec(&[], &mut out, flate2::FlushCompress::Finish)
+                .compress_vec(&[], &mut self.out, flate2::FlushCompress::Finish)
                 .unwrap();
 
-            if out.is_empty() {
+            if self.out.is_empty() {
                 break;
+            } else if !self.fits(self.out.len() as u64) {
+                self.overflowed = true;
+                self.out.clear();
             } else {
-                // FIXME: don't
-                request!(write all out.clone());
-                self.size += out.len() as u64;
-                out.clear();
+                request!(write all &self.out);
+                self.size += self.out.len() as u64;
+                self.out.clear();
             }
         }
     }
@@ -615,7 +1582,20 @@ impl GeneratorWrite for WriteHandle<'_> {
     #[generator(static, yield ReadSeekWriteRequest -> Response, !use)]
     fn write(&mut self, buf: &[u8]) -> usize {
         match &mut self.inner {
-            DataWriteHandle::Raw(h) => h.write(buf).await,
+            DataWriteHandle::Raw(h) => {
+                let buf = match self.capacity {
+                    Some(capacity) => {
+                        &buf[..buf.len().min(capacity.saturating_sub(h.cursor) as usize)]
+                    }
+                    None => buf,
+                };
+
+                if buf.is_empty() {
+                    0
+                } else {
+                    h.write(buf).await
+                }
+            }
             DataWriteHandle::Deflate(h) => h.write(buf).await,
         }
     }
@@ -3,7 +3,49 @@ use core::{convert::Infallible, error::Error};
 use alloc::string::String;
 use thiserror::Error;
 
-use crate::base::{ENTRY_SIZE, HEADER_SIZE};
+use crate::base::Format;
+
+/// A difference between an archive and a [`Manifest`](crate::Manifest) found while validating
+/// the archive against it.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ValidationError {
+    #[error("Entry {0} is missing")]
+    /// An entry in the manifest does not exist in the archive.
+    Missing(String),
+    #[error("Entry {0} is not in the manifest")]
+    /// An entry in the archive does not exist in the manifest.
+    Unexpected(String),
+    #[error("Entry {path} has size {actual} (expected {expected})")]
+    /// An entry's uncompressed size differs from the one in the manifest.
+    SizeMismatch {
+        /// The path of the entry.
+        path: String,
+        /// The size in the manifest.
+        expected: u32,
+        /// The size in the archive.
+        actual: u32,
+    },
+    #[error("Entry {path} is {} (expected it to be {})", compressed_str(*actual), compressed_str(*expected))]
+    /// An entry is compressed while it shouldn't be or the other way around.
+    CompressionMismatch {
+        /// The path of the entry.
+        path: String,
+        /// Whether the manifest expects the entry to be compressed.
+        expected: bool,
+        /// Whether the entry is compressed in the archive.
+        actual: bool,
+    },
+    #[error("Entry {0} has different contents")]
+    /// The hash of an entry's contents differs from the one in the manifest.
+    ContentMismatch(String),
+}
+
+fn compressed_str(compressed: bool) -> &'static str {
+    match compressed {
+        true => "compressed",
+        false => "stored",
+    }
+}
 
 /// An error triggered while parsing an existing archive.
 #[derive(Debug, Error)]
@@ -12,18 +54,26 @@ pub enum ParseError<Io: Error = Infallible> {
     /// The input did not start with the correct magic number.
     MismatchedMagic,
 
-    #[error("File uses unsupported header size {size} (expected {HEADER_SIZE})")]
+    #[error("File uses unsupported header size {size} (expected {expected})")]
     /// The input archive indicated an unsupported header size.
     MismatchedHeaderSize {
         /// The header size provided by the input archive
         size: u16,
+        /// The header size of the expected [`Format`].
+        expected: u16,
     },
-    #[error("File uses unsupported entry size {size} (expected {ENTRY_SIZE})")]
+    #[error("File uses unsupported entry size {size} (expected {expected})")]
     /// The input archive indicated an unsupported entry size.
     MismatchedEntrySize {
         /// The entry size provided by the input archive
         size: u16,
+        /// The entry size of the expected [`Format`].
+        expected: u16,
     },
+    #[error("Format {0:?} is not supported")]
+    /// The [`Format`] passed in [`ParseOptions`](crate::ParseOptions) is not supported, see
+    /// [`Format::is_supported`].
+    UnsupportedFormat(Format),
     #[error("File claims header section extends beyond EOF")]
     /// The input archive indicated its header section extends beyond EOF.
     EntryOverflow,
@@ -41,15 +91,47 @@ pub enum ParseError<Io: Error = Infallible> {
     #[error("Archive contains two entries with the same path {0}")]
     /// The input archive contained two entries with the same path.
     SamePath(String),
+    #[error("Entry {path} has path hash {stored:#010X} but its path hashes to {computed:#010X}")]
+    /// The hash stored in an entry did not match the hash of its path.
+    ///
+    /// This is only checked if [`ParseOptions::verify_hashes`](crate::ParseOptions::verify_hashes)
+    /// is enabled.
+    HashMismatch {
+        /// The path of the entry.
+        path: String,
+        /// The hash stored in the input archive.
+        stored: u32,
+        /// The hash computed from the entry's path.
+        computed: u32,
+    },
+    #[error("File contains {size} bytes of trailing data after the last entry")]
+    /// The input archive contained data past the end of the entry that ends last.
+    ///
+    /// This is only checked if
+    /// [`ParseOptions::reject_trailing_garbage`](crate::ParseOptions::reject_trailing_garbage)
+    /// is enabled.
+    TrailingGarbage {
+        /// The number of bytes after the end of the last entry.
+        size: u64,
+    },
 
     #[error(transparent)]
     /// An IO error occurred.
     Io(#[from] Io),
 }
 
+/// An error returned when a compression level is outside of the supported `0..=9` range.
+#[derive(Debug, Error)]
+#[error("Compression level {0} is out of range (expected 0..=9)")]
+pub struct InvalidLevel(pub u32);
+
 /// An error triggered while creating a new archive.
 #[derive(Debug, Error)]
 pub enum CreateError<Io: Error = Infallible> {
+    #[error("Format {0:?} is not supported")]
+    /// The requested [`Format`] is not supported, see [`Format::is_supported`].
+    UnsupportedFormat(Format),
+
     #[error(transparent)]
     /// An IO error occurred.
     Io(#[from] Io),
@@ -76,6 +158,10 @@ pub enum RenameError<Io: Error = Infallible> {
     #[error("Desination entry already exists")]
     /// An entry with the destination path was already present.
     AlreadyExists,
+    #[error("Destination path is empty, contains a null byte or is not ASCII")]
+    /// The destination path cannot be stored in an archive, see
+    /// [`InsertError::InvalidPath`].
+    InvalidPath,
 
     #[error(transparent)]
     /// An IO error occurred.
@@ -88,6 +174,29 @@ pub enum ReplaceError<Io: Error = Infallible> {
     #[error("Entry does not exist")]
     /// The source entry was not found.
     NotFound,
+    #[error("Destination path is empty, contains a null byte or is not ASCII")]
+    /// The destination path cannot be stored in an archive, see
+    /// [`InsertError::InvalidPath`].
+    InvalidPath,
+
+    #[error(transparent)]
+    /// An IO error occurred.
+    Io(#[from] Io),
+}
+
+/// An error triggered while copying an entry.
+#[derive(Debug, Error)]
+pub enum CopyError<Io: Error = Infallible> {
+    #[error("Source entry does not exist")]
+    /// The source entry was not found.
+    NotFound,
+    #[error("Destination entry already exists")]
+    /// An entry with the destination path was already present.
+    AlreadyExists,
+    #[error("Destination path is empty, contains a null byte or is not ASCII")]
+    /// The destination path cannot be stored in an archive, see
+    /// [`InsertError::InvalidPath`].
+    InvalidPath,
 
     #[error(transparent)]
     /// An IO error occurred.
@@ -100,6 +209,27 @@ pub enum InsertError<Io: Error = Infallible> {
     #[error("An entry with the same path already exists")]
     /// An entry with that name already existed.
     AlreadyExists,
+    #[error("Path is empty, contains a null byte or is not ASCII")]
+    /// The path cannot be stored in an archive, see [`Pkg::insert`](crate::sync::Pkg::insert).
+    InvalidPath,
+    #[error("Data offset is outside of the data region or inside the data of another entry")]
+    /// The data offset requested for the entry is not available, see `PkgState::insert_at`.
+    InvalidOffset,
+
+    #[error(transparent)]
+    /// An IO error occurred.
+    Io(#[from] Io),
+}
+
+/// An error triggered while opening an entry for appending.
+#[derive(Debug, Error)]
+pub enum AppendError<Io: Error = Infallible> {
+    #[error("Entry does not exist")]
+    /// An entry with that name was not found.
+    NotFound,
+    #[error("Entry is not appendable")]
+    /// The entry is compressed or its data is not at the end of the archive.
+    NotAppendable,
 
     #[error(transparent)]
     /// An IO error occurred.
@@ -112,6 +242,12 @@ pub enum OpenError<Io: Error = Infallible> {
     #[error("Entry does not exist")]
     /// An entry with that name was not found.
     NotFound,
+    #[error("Entry contains unrecognised entry flags {0:#04X}")]
+    /// The entry has flags that silpkg doesn't know how to interpret.
+    ///
+    /// This can only happen if the archive was parsed with
+    /// [`ParseOptions::preserve_unknown_flags`](crate::ParseOptions::preserve_unknown_flags).
+    UnrecognisedEntryFlags(u32),
 
     #[error(transparent)]
     /// An IO error occurred.
@@ -129,6 +265,12 @@ pub enum ReadError<Io: Error = Infallible> {
     /// Currently this only occurs when a read is attempted on a deflate compressed entry writer.
     #[error("Not readable")]
     NotReadable,
+    /// The entry's compressed data is corrupted and could not be decompressed.
+    ///
+    /// This includes a mismatched checksum at the end of a zlib stream, the data read up to this
+    /// point may be invalid.
+    #[error("Compressed data is corrupted")]
+    Corrupted,
 
     #[error(transparent)]
     /// An IO error occurred.
@@ -141,7 +283,9 @@ pub enum ReadError<Io: Error = Infallible> {
 /// [`EntryWriter`]: crate::sync::EntryWriter
 #[derive(Debug, Error)]
 pub enum SeekError<Io: Error = Infallible> {
-    /// Seek before zero
+    /// Seek before the start or past the end of the entry.
+    ///
+    /// The position is left unchanged when this is returned.
     #[error("Seek out of bounds")]
     SeekOutOfBounds,
     /// Reader/Writer does not support seeking.
@@ -174,6 +318,9 @@ pub enum RepackError<Io: Error = Infallible> {
 impl<E: Error + Into<std::io::Error>> From<CreateError<E>> for std::io::Error {
     fn from(val: CreateError<E>) -> Self {
         match val {
+            CreateError::UnsupportedFormat(_) => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, val.to_string())
+            }
             CreateError::Io(err) => err.into(),
         }
     }
@@ -201,11 +348,32 @@ impl<E: Error + Into<std::io::Error>> From<RenameError<E>> for std::io::Error {
             RenameError::AlreadyExists => {
                 std::io::Error::new(std::io::ErrorKind::AlreadyExists, val.to_string())
             }
+            RenameError::InvalidPath => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, val.to_string())
+            }
             RenameError::Io(err) => err.into(),
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl<E: Error + Into<std::io::Error>> From<CopyError<E>> for std::io::Error {
+    fn from(val: CopyError<E>) -> Self {
+        match val {
+            CopyError::NotFound => {
+                std::io::Error::new(std::io::ErrorKind::NotFound, val.to_string())
+            }
+            CopyError::AlreadyExists => {
+                std::io::Error::new(std::io::ErrorKind::AlreadyExists, val.to_string())
+            }
+            CopyError::InvalidPath => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, val.to_string())
+            }
+            CopyError::Io(err) => err.into(),
+        }
+    }
+}
+
 #[cfg(feature = "std")]
 impl<E: Error + Into<std::io::Error>> From<ReplaceError<E>> for std::io::Error {
     fn from(val: ReplaceError<E>) -> Self {
@@ -213,6 +381,9 @@ impl<E: Error + Into<std::io::Error>> From<ReplaceError<E>> for std::io::Error {
             ReplaceError::NotFound => {
                 std::io::Error::new(std::io::ErrorKind::NotFound, val.to_string())
             }
+            ReplaceError::InvalidPath => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, val.to_string())
+            }
             ReplaceError::Io(err) => err.into(),
         }
     }
@@ -225,11 +396,29 @@ impl<E: Error + Into<std::io::Error>> From<InsertError<E>> for std::io::Error {
             InsertError::AlreadyExists => {
                 std::io::Error::new(std::io::ErrorKind::AlreadyExists, val.to_string())
             }
+            InsertError::InvalidPath | InsertError::InvalidOffset => {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, val.to_string())
+            }
             InsertError::Io(err) => err.into(),
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl<E: Error + Into<std::io::Error>> From<AppendError<E>> for std::io::Error {
+    fn from(val: AppendError<E>) -> Self {
+        match val {
+            AppendError::NotFound => {
+                std::io::Error::new(std::io::ErrorKind::NotFound, val.to_string())
+            }
+            AppendError::NotAppendable => {
+                std::io::Error::new(std::io::ErrorKind::Unsupported, val.to_string())
+            }
+            AppendError::Io(err) => err.into(),
+        }
+    }
+}
+
 #[cfg(feature = "std")]
 impl<E: Error + Into<std::io::Error>> From<OpenError<E>> for std::io::Error {
     fn from(val: OpenError<E>) -> Self {
@@ -237,6 +426,9 @@ impl<E: Error + Into<std::io::Error>> From<OpenError<E>> for std::io::Error {
             OpenError::NotFound => {
                 std::io::Error::new(std::io::ErrorKind::NotFound, val.to_string())
             }
+            OpenError::UnrecognisedEntryFlags(_) => {
+                std::io::Error::new(std::io::ErrorKind::Unsupported, val.to_string())
+            }
             OpenError::Io(err) => err.into(),
         }
     }
@@ -247,7 +439,10 @@ impl<E: Error + Into<std::io::Error>> From<ReadError<E>> for std::io::Error {
     fn from(val: ReadError<E>) -> Self {
         match val {
             ReadError::NotReadable => {
-                std::io::Error::new(std::io::ErrorKind::Other, "Not readable")
+                std::io::Error::new(std::io::ErrorKind::Unsupported, val.to_string())
+            }
+            ReadError::Corrupted => {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, val.to_string())
             }
             ReadError::Io(err) => err.into(),
         }
@@ -259,12 +454,11 @@ impl<E: Error + Into<std::io::Error>> From<SeekError<E>> for std::io::Error {
     fn from(val: SeekError<E>) -> std::io::Error {
         match val {
             SeekError::SeekOutOfBounds => {
-                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek out of bounds")
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, val.to_string())
+            }
+            SeekError::NotSeekable => {
+                std::io::Error::new(std::io::ErrorKind::NotSeekable, val.to_string())
             }
-            SeekError::NotSeekable => std::io::Error::new(
-                std::io::ErrorKind::NotSeekable,
-                "Cannot read on compressed entry writer",
-            ),
             SeekError::Io(err) => err.into(),
         }
     }
@@ -282,12 +476,13 @@ impl<T, E: Error> FlattenResult<T, ParseError<E>> for Result<Result<T, ParseErro
                 Ok(o) => Ok(o),
                 Err(e) => Err(match e {
                     ParseError::MismatchedMagic => ParseError::MismatchedMagic,
-                    ParseError::MismatchedHeaderSize { size } => {
-                        ParseError::MismatchedHeaderSize { size }
+                    ParseError::MismatchedHeaderSize { size, expected } => {
+                        ParseError::MismatchedHeaderSize { size, expected }
                     }
-                    ParseError::MismatchedEntrySize { size } => {
-                        ParseError::MismatchedEntrySize { size }
+                    ParseError::MismatchedEntrySize { size, expected } => {
+                        ParseError::MismatchedEntrySize { size, expected }
                     }
+                    ParseError::UnsupportedFormat(format) => ParseError::UnsupportedFormat(format),
                     ParseError::EntryOverflow => ParseError::EntryOverflow,
                     ParseError::PathOverflow => ParseError::PathOverflow,
                     ParseError::UnrecognisedEntryFlags(flags) => {
@@ -295,6 +490,16 @@ impl<T, E: Error> FlattenResult<T, ParseError<E>> for Result<Result<T, ParseErro
                     }
                     ParseError::NonAsciiPath => ParseError::NonAsciiPath,
                     ParseError::SamePath(path) => ParseError::SamePath(path),
+                    ParseError::HashMismatch {
+                        path,
+                        stored,
+                        computed,
+                    } => ParseError::HashMismatch {
+                        path,
+                        stored,
+                        computed,
+                    },
+                    ParseError::TrailingGarbage { size } => ParseError::TrailingGarbage { size },
                     ParseError::Io(_) => unreachable!(),
                 }),
             },
@@ -310,9 +515,10 @@ impl<T, E: Error> FlattenResult<T, CreateError<E>>
         match self {
             Ok(o) => match o {
                 Ok(o) => Ok(o),
-                // This is a lot cleaner as a match
-                #[allow(unreachable_code)]
                 Err(e) => Err(match e {
+                    CreateError::UnsupportedFormat(format) => {
+                        CreateError::UnsupportedFormat(format)
+                    }
                     CreateError::Io(_) => unreachable!(),
                 }),
             },
@@ -348,6 +554,7 @@ impl<T, E: Error> FlattenResult<T, RenameError<E>>
                 Err(e) => Err(match e {
                     RenameError::NotFound => RenameError::NotFound,
                     RenameError::AlreadyExists => RenameError::AlreadyExists,
+                    RenameError::InvalidPath => RenameError::InvalidPath,
                     RenameError::Io(_) => unreachable!(),
                 }),
             },
@@ -356,6 +563,23 @@ impl<T, E: Error> FlattenResult<T, RenameError<E>>
     }
 }
 
+impl<T, E: Error> FlattenResult<T, CopyError<E>> for Result<Result<T, CopyError<Infallible>>, E> {
+    fn flatten(self) -> Result<T, CopyError<E>> {
+        match self {
+            Ok(o) => match o {
+                Ok(o) => Ok(o),
+                Err(e) => Err(match e {
+                    CopyError::NotFound => CopyError::NotFound,
+                    CopyError::AlreadyExists => CopyError::AlreadyExists,
+                    CopyError::InvalidPath => CopyError::InvalidPath,
+                    CopyError::Io(_) => unreachable!(),
+                }),
+            },
+            Err(e) => Err(CopyError::Io(e)),
+        }
+    }
+}
+
 impl<T, E: Error> FlattenResult<T, ReplaceError<E>>
     for Result<Result<T, ReplaceError<Infallible>>, E>
 {
@@ -365,6 +589,7 @@ impl<T, E: Error> FlattenResult<T, ReplaceError<E>>
                 Ok(o) => Ok(o),
                 Err(e) => Err(match e {
                     ReplaceError::NotFound => ReplaceError::NotFound,
+                    ReplaceError::InvalidPath => ReplaceError::InvalidPath,
                     ReplaceError::Io(_) => unreachable!(),
                 }),
             },
@@ -382,6 +607,8 @@ impl<T, E: Error> FlattenResult<T, InsertError<E>>
                 Ok(o) => Ok(o),
                 Err(e) => Err(match e {
                     InsertError::AlreadyExists => InsertError::AlreadyExists,
+                    InsertError::InvalidPath => InsertError::InvalidPath,
+                    InsertError::InvalidOffset => InsertError::InvalidOffset,
                     InsertError::Io(_) => unreachable!(),
                 }),
             },
@@ -390,6 +617,24 @@ impl<T, E: Error> FlattenResult<T, InsertError<E>>
     }
 }
 
+impl<T, E: Error> FlattenResult<T, AppendError<E>>
+    for Result<Result<T, AppendError<Infallible>>, E>
+{
+    fn flatten(self) -> Result<T, AppendError<E>> {
+        match self {
+            Ok(o) => match o {
+                Ok(o) => Ok(o),
+                Err(e) => Err(match e {
+                    AppendError::NotFound => AppendError::NotFound,
+                    AppendError::NotAppendable => AppendError::NotAppendable,
+                    AppendError::Io(_) => unreachable!(),
+                }),
+            },
+            Err(e) => Err(AppendError::Io(e)),
+        }
+    }
+}
+
 impl<T, E: Error> FlattenResult<T, OpenError<E>> for Result<Result<T, OpenError<Infallible>>, E> {
     fn flatten(self) -> Result<T, OpenError<E>> {
         match self {
@@ -397,6 +642,9 @@ impl<T, E: Error> FlattenResult<T, OpenError<E>> for Result<Result<T, OpenError<
                 Ok(o) => Ok(o),
                 Err(e) => Err(match e {
                     OpenError::NotFound => OpenError::NotFound,
+                    OpenError::UnrecognisedEntryFlags(flags) => {
+                        OpenError::UnrecognisedEntryFlags(flags)
+                    }
                     OpenError::Io(_) => unreachable!(),
                 }),
             },
@@ -412,6 +660,7 @@ impl<T, E: Error> FlattenResult<T, ReadError<E>> for Result<Result<T, ReadError<
                 Ok(o) => Ok(o),
                 Err(e) => Err(match e {
                     ReadError::NotReadable => ReadError::NotReadable,
+                    ReadError::Corrupted => ReadError::Corrupted,
                     ReadError::Io(_) => unreachable!(),
                 }),
             },
@@ -22,16 +22,7 @@
 
 #![warn(missing_docs)]
 #![feature(doc_cfg)]
-#![feature(seek_stream_len)]
-#![feature(iterator_try_collect)]
-#![feature(map_try_insert)]
 #![feature(coroutines, coroutine_trait)]
-#![feature(read_buf)]
-#![feature(map_many_mut)]
-// this could be removed if necessary as it's only used in errors.rs to implement from conversion
-// between errors, but we already use many unstable features and this is the cleaner option
-#![feature(auto_traits)]
-#![feature(negative_impls)]
 #![allow(dead_code)] // TODO: remove
 #![cfg_attr(not(feature = "std"), no_std)]
 
@@ -53,8 +44,12 @@ mod util;
 #[doc(cfg(feature = "std"))]
 pub mod sync;
 
-pub use base::{Compression, EntryCompression, EntryInfo, Flags};
+pub use base::{
+    Compression, CompressionPolicy, DeflateFormat, DirNode, EntryCompression, EntryInfo, FileNode,
+    Flags, Format, GrowthKind, Manifest, ManifestEntry, ParseOptions, PathRegionGrowth, PeekInfo,
+    PkgEvent, RawEntryView, RepackCheck,
+};
 
 #[cfg(feature = "std")]
 #[doc(cfg(feature = "std"))]
-pub use sync::Truncate;
+pub use sync::{peek, OffsetStorage, Truncate};
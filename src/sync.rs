@@ -1,8 +1,13 @@
 use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
     io::{Read, Seek, Write},
     mem::ManuallyDrop,
-    ops::Coroutine,
+    num::NonZeroUsize,
+    ops::{Coroutine, Range},
     pin::Pin,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, SystemTime},
 };
 
 use base::FlattenResult;
@@ -10,14 +15,21 @@ use base::FlattenResult;
 use crate::{
     base::{
         self, DataWriteHandle, Flags, GeneratorRead, GeneratorSeek, GeneratorWrite, PkgState,
-        ReadSeekRequest, ReadSeekWriteRequest, ReadSeekWriteTruncateRequest, Response,
+        ReadData, ReadSeekRequest, ReadSeekWriteRequest, ReadSeekWriteTruncateRequest, Response,
         WriteRequest,
     },
     errors,
-    util::{ReadSeekWriteExt, WriteExt},
-    EntryInfo,
+    util::{ReadExt, ReadSeekWriteExt, WriteExt},
+    Compression, CompressionPolicy, DeflateFormat, DirNode, EntryCompression, EntryInfo, Format,
+    GrowthKind, Manifest, ManifestEntry, ParseOptions, PathRegionGrowth, PeekInfo, PkgEvent,
+    RawEntryView, RepackCheck,
 };
 
+/// [`CopyError`] returned by [`sync::Pkg`].
+///
+/// [`CopyError`]: crate::errors::CopyError
+/// [`sync::Pkg`]: crate::sync::Pkg
+pub type CopyError = errors::CopyError<std::io::Error>;
 /// [`CreateError`] returned by [`sync::Pkg`].
 ///
 /// [`CreateError`]: crate::errors::CreateError
@@ -33,6 +45,11 @@ pub type ParseError = errors::ParseError<std::io::Error>;
 /// [`InsertError`]: errors::InsertError
 /// [`sync::Pkg`]: crate::sync::Pkg
 pub type InsertError = errors::InsertError<std::io::Error>;
+/// [`AppendError`] returned by [`sync::Pkg`].
+///
+/// [`AppendError`]: crate::errors::AppendError
+/// [`sync::Pkg`]: crate::sync::Pkg
+pub type AppendError = errors::AppendError<std::io::Error>;
 /// [`OpenError`] returned by [`sync::Pkg`].
 ///
 /// [`OpenError`]: crate::errors::OpenError
@@ -58,6 +75,98 @@ pub type RepackError = errors::RepackError<std::io::Error>;
 /// [`ReplaceError`]: crate::errors::ReplaceError
 /// [`sync::Pkg`]: crate::sync::Pkg
 pub type ReplaceError = errors::ReplaceError<std::io::Error>;
+/// [`ReadError`] returned by [`sync::EntryWriter`].
+///
+/// [`ReadError`]: crate::errors::ReadError
+/// [`sync::EntryWriter`]: crate::sync::EntryWriter
+pub type ReadError = errors::ReadError<std::io::Error>;
+/// [`SeekError`] returned by [`sync::EntryReader`] and [`sync::EntryWriter`].
+///
+/// [`SeekError`]: crate::errors::SeekError
+/// [`sync::EntryReader`]: crate::sync::EntryReader
+/// [`sync::EntryWriter`]: crate::sync::EntryWriter
+pub type SeekError = errors::SeekError<std::io::Error>;
+
+pub use crate::base::MTIME_ENTRY_PATH;
+
+/// Hashes an entry's contents in chunks for [`Pkg::find_duplicates`] and [`Manifest`]s.
+///
+/// This is 64-bit FNV-1a, which unlike [`std::hash::DefaultHasher`] is guaranteed not to depend
+/// on how the input is split into chunks.
+struct ContentHasher(u64);
+
+impl ContentHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Write for ContentHasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for byte in buf {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn decode_mtimes(mut data: &[u8]) -> std::io::Result<HashMap<String, SystemTime>> {
+    let invalid = |msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+
+    let mut times = HashMap::new();
+    while !data.is_empty() {
+        let len = data.read_u32_be()? as usize;
+        if len > data.len() {
+            return Err(invalid(
+                "Modification time entry path extends beyond end of entry",
+            ));
+        }
+
+        let (path, rest) = data.split_at(len);
+        data = rest;
+        let path = std::str::from_utf8(path)
+            .ok()
+            .filter(|p| p.is_ascii())
+            .ok_or_else(|| invalid("Modification time entry contains a non-ascii path"))?;
+
+        let secs = data.read_u64_be()?;
+        let nanos = data.read_u32_be()?;
+        if nanos >= 1_000_000_000 {
+            return Err(invalid(
+                "Modification time entry contains an invalid timestamp",
+            ));
+        }
+
+        times.insert(
+            path.to_string(),
+            SystemTime::UNIX_EPOCH + Duration::new(secs, nanos),
+        );
+    }
+
+    Ok(times)
+}
+
+fn encode_mtimes<'a>(times: impl IntoIterator<Item = (&'a str, SystemTime)>) -> Vec<u8> {
+    let mut data = vec![];
+    for (path, time) in times {
+        // Times before the epoch can't be represented, just skip them.
+        let Ok(since_epoch) = time.duration_since(SystemTime::UNIX_EPOCH) else {
+            continue;
+        };
+
+        // Writing into a Vec can't fail
+        data.write_u32_be(path.len() as u32).unwrap();
+        data.write_all(path.as_bytes()).unwrap();
+        data.write_u64_be(since_epoch.as_secs()).unwrap();
+        data.write_u32_be(since_epoch.subsec_nanos()).unwrap();
+    }
+
+    data
+}
 
 /// A trait for objects that can be truncated.
 pub trait Truncate {
@@ -96,13 +205,280 @@ impl<T: Truncate> Truncate for Box<T> {
     }
 }
 
+/// A storage that starts at a fixed offset into another storage.
+///
+/// All offsets are relative to `base`, the data before it is not accessible through this
+/// storage and seeking before it fails with
+/// [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput). The length of the stream as
+/// reported by seeking to its end is the length remaining after `base`, truncating also only
+/// affects the data after it. Used by [`Pkg::parse_at`] for archives embedded inside of other
+/// files, but works with anything that needs a view of a storage starting at some offset.
+///
+/// The position of the underlying storage is left as is when this is created, it should be
+/// seeked to a position after `base` before reading or writing anything without seeking first.
+///
+/// # Example
+/// ```
+/// # use std::io::{Cursor, Seek, SeekFrom, Write};
+/// # use silpkg::{sync::Pkg, OffsetStorage};
+/// let mut storage = OffsetStorage::new(Cursor::new(b"header".to_vec()), 6);
+/// let mut pkg = Pkg::create(&mut storage)?;
+/// pkg.insert("hello.txt".into(), Default::default())?.write_all(b"Hello!")?;
+/// pkg.repack()?;
+/// drop(pkg);
+///
+/// assert!(storage.get_ref().get_ref().starts_with(b"header"));
+/// assert_eq!(
+///     storage.seek(SeekFrom::End(0))? + 6,
+///     storage.get_ref().get_ref().len() as u64
+/// );
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct OffsetStorage<S> {
+    inner: S,
+    base: u64,
+}
+
+impl<S> OffsetStorage<S> {
+    /// Creates a storage whose offset zero is at `base` in `inner`.
+    pub fn new(inner: S, base: u64) -> Self {
+        Self { inner, base }
+    }
+
+    /// Returns the offset in the underlying storage at which this storage starts.
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    /// Returns a reference to the underlying storage.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying storage.
+    ///
+    /// Data before `base` can be modified through it without affecting this storage.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Returns the underlying storage.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read> Read for OffsetStorage<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Seek> Seek for OffsetStorage<S> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            std::io::SeekFrom::Start(offset) => self.base.checked_add(offset),
+            std::io::SeekFrom::End(offset) => {
+                let position = self.inner.stream_position()?;
+                match self
+                    .inner
+                    .seek(std::io::SeekFrom::End(0))?
+                    .checked_add_signed(offset)
+                {
+                    Some(target) if target >= self.base => Some(target),
+                    // Finding the end moved the inner storage, a failed seek must not
+                    _ => {
+                        self.inner.seek(std::io::SeekFrom::Start(position))?;
+                        None
+                    }
+                }
+            }
+            std::io::SeekFrom::Current(offset) => {
+                self.inner.stream_position()?.checked_add_signed(offset)
+            }
+        };
+
+        match target {
+            Some(target) if target >= self.base => {
+                Ok(self.inner.seek(std::io::SeekFrom::Start(target))? - self.base)
+            }
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek before the start of the storage",
+            )),
+        }
+    }
+}
+
+impl<S: Write> Write for OffsetStorage<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Truncate> Truncate for OffsetStorage<S> {
+    fn truncate(&mut self, len: u64) -> std::io::Result<()> {
+        match self.base.checked_add(len) {
+            Some(len) => self.inner.truncate(len),
+            None => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "truncated length does not fit in the storage",
+            )),
+        }
+    }
+}
+
+/// Counts of IO requests issued to the underlying storage of a [`Pkg`].
+///
+/// See [`Pkg::io_stats`].
+#[cfg(feature = "io_stats")]
+#[doc(cfg(feature = "io_stats"))]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IoStats {
+    /// Number of reads that could return less data than requested.
+    pub reads: u64,
+    /// Number of reads that had to return exactly the requested amount of data.
+    pub exact_reads: u64,
+    /// Number of seeks, this includes seeks used to query the stream length or position.
+    pub seeks: u64,
+    /// Number of writes that could write less data than requested.
+    pub writes: u64,
+    /// Number of writes that had to write the whole buffer.
+    pub full_writes: u64,
+    /// Number of copies of data from one place in the storage to another.
+    pub copies: u64,
+    /// Number of writes of a repeated byte value.
+    pub repeated_writes: u64,
+    /// Number of truncations.
+    pub truncates: u64,
+
+    /// Total number of bytes requested to be read.
+    pub bytes_read: u64,
+    /// Total number of bytes requested to be written, including repeated writes.
+    pub bytes_written: u64,
+    /// Total number of bytes requested to be copied.
+    pub bytes_copied: u64,
+}
+
+#[cfg(feature = "io_stats")]
+impl IoStats {
+    fn record_readseek(&mut self, request: &ReadSeekRequest) {
+        match request {
+            ReadSeekRequest::Read(count) => {
+                self.reads += 1;
+                self.bytes_read += count;
+            }
+            ReadSeekRequest::ReadExact(count) => {
+                self.exact_reads += 1;
+                self.bytes_read += count;
+            }
+            ReadSeekRequest::Seek(_) => self.seeks += 1,
+        }
+    }
+
+    fn record_write(&mut self, request: &WriteRequest) {
+        match request {
+            WriteRequest::WriteAll(_, count) => {
+                self.full_writes += 1;
+                self.bytes_written += *count as u64;
+            }
+            WriteRequest::Write(_, count) => {
+                self.writes += 1;
+                self.bytes_written += *count as u64;
+            }
+            WriteRequest::Copy { count, .. } => {
+                self.copies += 1;
+                self.bytes_copied += count;
+            }
+            WriteRequest::WriteRepeated { count, .. } => {
+                self.repeated_writes += 1;
+                self.bytes_written += count;
+            }
+        }
+    }
+}
+
+// Returns the first `len` bytes of `buffer`, growing it if necessary.
+fn scratch(buffer: &mut Vec<u8>, len: usize) -> &mut [u8] {
+    if buffer.len() < len {
+        buffer.resize(len, 0);
+    }
+    &mut buffer[..len]
+}
+
+/// The error wrapped in a [`PermissionDenied`] error when writing fails because the storage
+/// can't be written to at all.
+///
+/// [`PermissionDenied`]: std::io::ErrorKind::PermissionDenied
+#[derive(Debug, thiserror::Error)]
+#[error("The storage is not writable")]
+struct NotWritable(#[source] std::io::Error);
+
+// Replaces errors meaning that the storage can't be written to at all with a PermissionDenied
+// error, leaving every other error as it is. Unix reports writes to files opened without write
+// access as EBADF, which otherwise doesn't hint at the cause.
+fn map_not_writable(err: std::io::Error) -> std::io::Error {
+    const EBADF: i32 = 9;
+
+    let not_writable = err.kind() == std::io::ErrorKind::PermissionDenied
+        || (cfg!(unix) && err.raw_os_error() == Some(EBADF));
+    match not_writable {
+        true => std::io::Error::new(std::io::ErrorKind::PermissionDenied, NotWritable(err)),
+        false => err,
+    }
+}
+
+// Why silpkg refuses to modify an archive.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ReadOnly {
+    SkippedPaths,
+    ExtendedLayout,
+    // Lifted once the archive is repacked.
+    DroppedEntries,
+}
+
+impl ReadOnly {
+    fn reason(self) -> &'static str {
+        match self {
+            ReadOnly::SkippedPaths => "Archives parsed without paths cannot be modified",
+            ReadOnly::ExtendedLayout => "Archives with an extended layout cannot be modified",
+            ReadOnly::DroppedEntries => {
+                "Archives with dropped truncated entries have to be repacked before they can be \
+                 modified"
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 struct SyncDriver<S> {
     storage: S,
+    // Size of the buffer used when copying data around inside the storage.
+    buffer_size: usize,
+    // Set for archives that silpkg can read but not modify, to the reason why.
+    read_only: Option<ReadOnly>,
+    // Reused for the data of every read request to avoid allocating a buffer for each one.
+    scratch: Vec<u8>,
+    #[cfg(feature = "io_stats")]
+    stats: IoStats,
 }
 
 impl<S: Read + Seek> SyncDriver<S> {
     pub fn new(storage: S) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            buffer_size: base::BUFFER_SIZE as usize,
+            read_only: None,
+            scratch: Vec::new(),
+            #[cfg(feature = "io_stats")]
+            stats: IoStats::default(),
+        }
     }
 
     pub fn get_mut(&mut self) -> &mut S {
@@ -110,17 +486,21 @@ impl<S: Read + Seek> SyncDriver<S> {
     }
 
     fn handle_readseek(&mut self, request: ReadSeekRequest) -> std::io::Result<Response> {
+        #[cfg(feature = "io_stats")]
+        self.stats.record_readseek(&request);
+
         Ok(match request {
+            // The scratch buffer is only touched again once the coroutine makes its next request,
+            // which is all ReadData::new requires.
             ReadSeekRequest::Read(count) => {
-                let mut buf = vec![0; count as usize];
-                let read = self.storage.read(&mut buf)?;
-                buf.truncate(read);
-                Response::Read(buf)
+                let buf = scratch(&mut self.scratch, count as usize);
+                let read = self.storage.read(buf)?;
+                Response::Read(unsafe { ReadData::new(&buf[..read]) })
             }
             ReadSeekRequest::ReadExact(count) => {
-                let mut buf = vec![0; count as usize];
-                self.storage.read_exact(&mut buf)?;
-                Response::Read(buf)
+                let buf = scratch(&mut self.scratch, count as usize);
+                self.storage.read_exact(buf)?;
+                Response::Read(unsafe { ReadData::new(buf) })
             }
             ReadSeekRequest::Seek(offset) => Response::Seek(self.storage.seek(offset.into())?),
         })
@@ -144,7 +524,21 @@ impl<S: Read + Seek> SyncDriver<S> {
 }
 
 impl<S: Read + Seek + Write> SyncDriver<S> {
+    // Checked before the coroutine is first resumed so that the state is left untouched.
+    fn check_writable(&self) -> std::io::Result<()> {
+        match self.read_only {
+            Some(read_only) => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                read_only.reason(),
+            )),
+            None => Ok(()),
+        }
+    }
+
     fn handle_write(&mut self, request: WriteRequest) -> std::io::Result<Response> {
+        #[cfg(feature = "io_stats")]
+        self.stats.record_write(&request);
+
         Ok(match request {
             WriteRequest::WriteAll(ptr, count) => {
                 self.storage
@@ -156,7 +550,8 @@ impl<S: Read + Seek + Write> SyncDriver<S> {
                     .write(unsafe { core::slice::from_raw_parts(ptr, count) })?,
             ),
             WriteRequest::Copy { from, count, to } => {
-                self.storage.copy_within(from, count, to)?;
+                self.storage
+                    .copy_within(from, count, to, self.buffer_size)?;
                 Response::None
             }
             WriteRequest::WriteRepeated { value, count } => {
@@ -170,6 +565,7 @@ impl<S: Read + Seek + Write> SyncDriver<S> {
         &mut self,
         mut coroutine: impl Coroutine<Response, Return = R, Yield = ReadSeekWriteRequest>,
     ) -> std::io::Result<R> {
+        self.check_writable()?;
         let mut response = Response::None;
 
         loop {
@@ -180,7 +576,7 @@ impl<S: Read + Seek + Write> SyncDriver<S> {
                     response = self.handle_readseek(request)?
                 }
                 CoroutineState::Yielded(ReadSeekWriteRequest::Write(request)) => {
-                    response = self.handle_write(request)?
+                    response = self.handle_write(request).map_err(map_not_writable)?
                 }
                 CoroutineState::Complete(result) => break Ok(result),
             }
@@ -193,6 +589,7 @@ impl<S: Read + Seek + Write + Truncate> SyncDriver<S> {
         &mut self,
         mut coroutine: impl Coroutine<Response, Return = R, Yield = ReadSeekWriteTruncateRequest>,
     ) -> std::io::Result<R> {
+        self.check_writable()?;
         let mut response = Response::None;
 
         loop {
@@ -203,10 +600,15 @@ impl<S: Read + Seek + Write + Truncate> SyncDriver<S> {
                     response = self.handle_readseek(request)?
                 }
                 CoroutineState::Yielded(ReadSeekWriteTruncateRequest::Write(request)) => {
-                    response = self.handle_write(request)?
+                    response = self.handle_write(request).map_err(map_not_writable)?
                 }
                 CoroutineState::Yielded(ReadSeekWriteTruncateRequest::Truncate(size)) => {
-                    self.storage.truncate(size)?;
+                    #[cfg(feature = "io_stats")]
+                    {
+                        self.stats.truncates += 1;
+                    }
+
+                    self.storage.truncate(size).map_err(map_not_writable)?;
                     response = Response::None;
                 }
                 CoroutineState::Complete(result) => break Ok(result),
@@ -216,20 +618,67 @@ impl<S: Read + Seek + Write + Truncate> SyncDriver<S> {
 }
 
 /// A synchronous PKG archive reader/writer.
+///
+/// # Consistency
+/// The metadata parsed from the archive is kept in memory and every method that modifies the
+/// archive updates it together with the storage, so anything written through a [`Pkg`] can be
+/// read back from the same [`Pkg`] right away. There is no need to [`parse`](Self::parse) the
+/// storage again, which would only re-read the same metadata. The exceptions are:
+/// - An [`EntryWriter`] only updates its entry once it is flushed, [`finish`]ed or dropped,
+///   until then the entry keeps its previous size (or contents, see [`overwrite`]).
+/// - If a method fails with an IO error the storage may be left partially modified, in which
+///   case the in-memory metadata is not guaranteed to match it anymore and the archive should be
+///   parsed again.
+/// - Modifications made to the storage by anything other than this [`Pkg`] are not noticed.
+///
+/// [`finish`]: EntryWriter::finish
+/// [`overwrite`]: Self::overwrite
+///
+/// # Notes
+/// If the underlying storage is [`Clone`] then so is the [`Pkg`], cloning it will clone both the
+/// storage and all the parsed archive metadata. This is mostly useful for cheaply sharing read-only
+/// archives backed by something like a [`Cursor`](std::io::Cursor) over a shared buffer.
+#[derive(Clone)]
 pub struct Pkg<S: Read + Seek> {
     driver: SyncDriver<S>,
     state: PkgState,
 }
 
 /// A reader that allows reading a single entry from a [`Pkg`]
+///
+/// Readers of uncompressed entries can seek anywhere between the start and the end of the entry,
+/// seeking outside of it fails with [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput).
 pub struct EntryReader<'a, S: Read + Seek> {
     driver: &'a mut SyncDriver<S>,
     handle: base::ReadHandle,
 }
 
+impl<S: Read + Seek> EntryReader<'_, S> {
+    /// Closes this reader, releasing the borrow of the [`Pkg`] it was opened from.
+    ///
+    /// This is equivalent to dropping the reader, but makes the intent explicit. Readers don't
+    /// leave any state behind so the [`Pkg`] can be used normally afterwards, even if the entry
+    /// was only partially read.
+    pub fn close(self) {}
+
+    /// Returns whether the Adler-32 checksum at the end of a compressed entry's zlib stream
+    /// matched the decompressed data.
+    ///
+    /// This is [`None`] until the entry has been read to its end, as well as for uncompressed
+    /// entries, empty entries and entries in the [`DeflateFormat::Raw`] format which don't have
+    /// a checksum. A corrupted or truncated stream yields `Some(false)`, for a corrupted one the
+    /// read that detects it also fails with [`InvalidData`]. Use
+    /// [`Pkg::open_raw_lenient`] to salvage data from such entries.
+    ///
+    /// [`InvalidData`]: std::io::ErrorKind::InvalidData
+    pub fn integrity_ok(&self) -> Option<bool> {
+        self.handle.integrity_ok()
+    }
+}
+
 impl<S: Read + Seek> Read for EntryReader<'_, S> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.driver.drive_read(self.handle.read(buf))
+        Ok(self.driver.drive_read(self.handle.read(buf)).flatten()?)
     }
 }
 
@@ -241,161 +690,1351 @@ impl<S: Read + Seek> Read for EntryReader<'_, S> {
 /// [`NotSeekable`]: std::io::ErrorKind::NotSeekable
 impl<S: Read + Seek> Seek for EntryReader<'_, S> {
     fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
-        match &mut self.handle {
-            base::ReadHandle::Raw(handle) => {
-                Ok(self.driver.drive_read(handle.seek(pos.into())).flatten()?)
-            }
-            // FIXME: Should this really work like this?
-            base::ReadHandle::Deflate(_) => Err(std::io::Error::new(
-                std::io::ErrorKind::NotSeekable,
-                "Cannot seek on compressed entry reader",
-            )),
-        }
+        Ok(self
+            .driver
+            .drive_read(self.handle.seek(pos.into()))
+            .flatten()?)
     }
 }
 
-impl<S: Read + Seek> Pkg<S> {
-    /// Returns a reference to the underlying reader
-    pub fn inner(&self) -> &S {
-        &self.driver.storage
-    }
+/// The contents of an entry read into memory by [`Pkg::read_entry_owned`].
+///
+/// Unlike [`EntryReader`] this doesn't borrow the [`Pkg`] it was read from, so it can be sent to
+/// other threads. It implements [`Read`] and [`Seek`] over the already decompressed data.
+#[derive(Debug, Clone)]
+pub struct OwnedEntryBytes(std::io::Cursor<Vec<u8>>);
 
-    /// Checks whether the archive contains `path`.
-    pub fn contains(&self, path: &str) -> bool {
-        self.state.contains(path)
+impl OwnedEntryBytes {
+    /// Returns the whole contents of the entry, regardless of how much has been read already.
+    pub fn as_slice(&self) -> &[u8] {
+        self.0.get_ref()
     }
 
-    /// Returns an iterator over all the paths in the archive.
-    pub fn paths(&self) -> impl Iterator<Item = &String> {
-        self.state.paths()
+    /// Returns the contents of the entry as a [`Vec`].
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0.into_inner()
     }
+}
 
-    /// Parses a [`Pkg`] from the supplied reader.
-    pub fn parse(storage: S) -> Result<Self, ParseError> {
-        let mut driver = SyncDriver::new(storage);
-        let state = driver.drive_read(base::parse(true)).flatten()?;
-
-        Ok(Self { driver, state })
+impl Read for OwnedEntryBytes {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
     }
+}
 
-    /// Opens an entry for reading.
-    pub fn open(&mut self, path: &str) -> Result<EntryReader<S>, OpenError> {
-        let handle = self
-            .driver
-            .drive_read(base::open(&self.state, path))
-            .flatten()?;
-
-        Ok(EntryReader {
-            driver: &mut self.driver,
-            handle,
-        })
+impl Seek for OwnedEntryBytes {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
     }
+}
 
-    /// Retrieve entry metadata, like size or index.
-    ///
-    /// # Notes
-    /// Returns [`None`] if an entry with that name does not exist.
-    pub fn metadata(&self, path: &str) -> Option<EntryInfo> {
-        self.state.index(path).and_then(|idx| self.state.info(idx))
-    }
+/// Reads only the header of the archive in `reader` without parsing anything else.
+///
+/// This is much cheaper than [`Pkg::parse`] and can be used to quickly tell whether a file looks
+/// like a PKG archive. The header is read from the start of `reader` and the position of `reader`
+/// is restored to where it was afterwards, even if an error occurs.
+///
+/// # Errors
+/// - [`ParseError::Io`] if an IO error occurs, including if `reader` is too short to contain a
+///   header.
+///
+/// # Example
+/// ```
+/// # use std::io::Cursor;
+/// # use silpkg::sync::Pkg;
+/// let mut storage = Cursor::new(vec![]);
+/// Pkg::create(&mut storage)?;
+///
+/// let info = silpkg::peek(&mut storage)?;
+/// assert!(info.is_supported());
+/// assert!(!silpkg::peek(&mut Cursor::new(b"not a pkg file!!"))?.magic_ok);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn peek<R: Read + Seek>(reader: &mut R) -> Result<PeekInfo, ParseError> {
+    let start = reader.stream_position()?;
+    let result = SyncDriver::new(&mut *reader).drive_read(base::peek());
+    reader.seek(std::io::SeekFrom::Start(start))?;
 
-    // TODO: Add a way to access this metadata
-    // pub fn fixme_remove_this_print_size_info(&mut self) {
-    //     {
-    //         let occupied = self.entries.iter().filter(|x| x.is_some()).count();
-    //         println!(
-    //             "Entries: {} ({} occupied, {} empty)",
-    //             self.entries.len(),
-    //             occupied,
-    //             self.entries.len() - occupied
-    //         );
-    //     }
-    //     println!(
-    //         "Path region size: {}B ({}B occupied, {}B unused)",
-    //         self.path_region_size,
-    //         self.path_region_empty_offset,
-    //         self.path_region_size - self.path_region_empty_offset
-    //     );
-    //
-    //     println!("Data region:");
-    //     let data_region_size = self.storage.stream_len().unwrap() - self.data_region_offset();
-    //     let occupied_data_region = self
-    //         .entries
-    //         .iter()
-    //         .filter_map(|opt| opt.as_ref().map(|entry| entry.data_size as u64))
-    //         .sum::<u64>();
-    //     println!(
-    //         "  Size: {data_region_size}B ({}B occupied, {}B unused)",
-    //         occupied_data_region,
-    //         data_region_size - occupied_data_region
-    //     );
-    //     println!(
-    //         "  Fragmentation: {:.4}%",
-    //         ((data_region_size - occupied_data_region) as f64) / data_region_size as f64
-    //     )
-    // }
-} // Read + Seek
+    Ok(result?)
+}
 
-/// A writer that allows writing a single entry into a [`Pkg`].
-pub struct EntryWriter<'a, S: Read + Seek + Write> {
-    driver: &'a mut SyncDriver<S>,
-    handle: ManuallyDrop<base::WriteHandle<'a>>,
+/// An iterator over the entries of an archive that decodes them as they're read, see
+/// [`Pkg::scan_entries`].
+pub struct EntryScan<S: Read + Seek> {
+    driver: SyncDriver<S>,
+    scanner: base::EntryScanner,
+    done: bool,
 }
 
-impl<S: Read + Seek + Write> EntryWriter<'_, S> {
-    /// Writes entry metadata to the underlying writer.
-    pub fn finish(mut self) -> std::io::Result<()> {
-        let handle = unsafe { ManuallyDrop::take(&mut self.handle) };
-        self.driver.drive_write(handle.finish())?;
-        self.driver.get_mut().flush()?;
-        std::mem::forget(self);
+impl<S: Read + Seek> EntryScan<S> {
+    /// Returns the number of slots in the entry list, including empty ones.
+    pub fn entry_count(&self) -> u32 {
+        self.scanner.entry_count()
+    }
 
-        Ok(())
+    /// Returns the number of slots that were read so far, useful for reporting progress together
+    /// with [`entry_count`](Self::entry_count).
+    pub fn position(&self) -> u32 {
+        self.scanner.position()
+    }
+
+    /// Returns the underlying storage.
+    pub fn into_inner(self) -> S {
+        self.driver.storage
     }
 }
 
-impl<S: Read + Seek + Write> Write for EntryWriter<'_, S> {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self.handle.inner_mut() {
-            DataWriteHandle::Raw(handle) => Ok(self.driver.drive_write(handle.write(buf))?),
-            DataWriteHandle::Deflate(handle) => Ok(self.driver.drive_write(handle.write(buf))?),
+impl<S: Read + Seek> Iterator for EntryScan<S> {
+    type Item = Result<EntryInfo, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
-    }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.driver.drive_write(self.handle.flush())?;
-        self.driver.get_mut().flush()
+        let item = match self.driver.drive_read(self.scanner.next_entry()) {
+            Ok(None) => None,
+            Ok(Some(result)) => Some(Ok::<_, std::io::Error>(result).flatten()),
+            Err(err) => Some(Err(ParseError::Io(err))),
+        };
+        self.done = !matches!(item, Some(Ok(_)));
+        item
     }
-}
 
-impl<S: Read + Seek + Write> Read for EntryWriter<'_, S> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        match self.handle.inner_mut() {
-            DataWriteHandle::Raw(handle) => self.driver.drive_read(handle.read(buf)),
-            DataWriteHandle::Deflate(_) => Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "Cannot read on compressed entry writer",
-            )),
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.done {
+            true => (0, Some(0)),
+            false => (0, Some((self.entry_count() - self.position()) as usize)),
         }
     }
 }
 
-/// # Notes
-/// Even though this type implements [`Seek`] [`seek`]ing will not always succeed, for example if the entry
-/// happens to be compressed then [`seek`]ing will fail with [`NotSeekable`].
-///
-/// [`seek`]: EntryReader::seek
-/// [`NotSeekable`]: std::io::ErrorKind::NotSeekable
-impl<S: Read + Seek + Write> Seek for EntryWriter<'_, S> {
-    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
-        match self.handle.inner_mut() {
-            DataWriteHandle::Raw(handle) => {
+impl<S: Read + Seek> std::iter::FusedIterator for EntryScan<S> {}
+
+impl<S: Read + Seek> Pkg<S> {
+    /// Returns a reference to the underlying reader
+    pub fn inner(&self) -> &S {
+        &self.driver.storage
+    }
+
+    /// Returns whether silpkg allows modifying this archive.
+    ///
+    /// This is `false` for archives parsed with [`ParseOptions::skip_paths`], ones using a
+    /// larger layout than [`ParseOptions::format`] and ones that had entries dropped by
+    /// [`ParseOptions::repair_truncated`] until they are [`repack`](Self::repack)ed. Trying to
+    /// modify them fails with [`ErrorKind::Unsupported`](std::io::ErrorKind::Unsupported).
+    /// Whether the storage itself accepts writes is not known until something is written, see
+    /// [`check_writable`](Self::check_writable).
+    pub fn is_writable(&self) -> bool {
+        self.driver.read_only.is_none()
+    }
+
+    /// Returns the header and entry sizes used by the archive.
+    ///
+    /// This is [`Format::STANDARD`] unless the archive was created with
+    /// [`create_with_format`](Self::create_with_format) or parsed with a different
+    /// [`ParseOptions::format`] or [`ParseOptions::allow_extended_layout`].
+    pub fn format(&self) -> Format {
+        self.state.format()
+    }
+
+    /// Sets the size of the buffers used when compressing, decompressing and moving data.
+    ///
+    /// The default is 8KiB, larger buffers mean fewer but bigger IO requests which can speed up
+    /// extraction and insertion of big entries. Like
+    /// [`set_path_region_growth`](Self::set_path_region_growth) this is not stored in the archive
+    /// and only affects readers and writers opened after it's changed.
+    pub fn set_buffer_size(&mut self, size: NonZeroUsize) {
+        self.driver.buffer_size = size.get();
+        self.state.set_buffer_size(size.get() as u64);
+    }
+
+    /// Sets the format used for compressed entries opened or inserted afterwards.
+    ///
+    /// Parsed archives use [`ParseOptions::deflate_format`] and created ones default to
+    /// [`DeflateFormat::Zlib`]. Since the format is not stored in the archive, anyone reading an
+    /// archive written with [`DeflateFormat::Raw`] has to know to use that format as well, other
+    /// PKG tools will most likely expect zlib.
+    pub fn set_deflate_format(&mut self, format: DeflateFormat) {
+        self.state.set_deflate_format(format);
+    }
+
+    /// Sets a callback that is called with a [`PkgEvent`] whenever regions are moved, space is
+    /// reserved, entries are written or a corrupted compressed entry is read.
+    ///
+    /// The same information is also logged through the `log` crate regardless of whether an
+    /// observer is set. Readers opened before this is called keep reporting to the previous
+    /// observer.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::{io::Cursor, sync::{Arc, Mutex}};
+    /// # use silpkg::{sync::Pkg, Flags, PkgEvent};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![])).unwrap();
+    /// let events = Arc::new(Mutex::new(vec![]));
+    /// let sink = events.clone();
+    /// pkg.set_observer(move |event| sink.lock().unwrap().push(event));
+    ///
+    /// pkg.insert("hello.txt".into(), Flags::default()).unwrap().finish().unwrap();
+    /// assert!(matches!(
+    ///     events.lock().unwrap().last(),
+    ///     Some(PkgEvent::EntryWritten { path, .. }) if path == "hello.txt"
+    /// ));
+    /// ```
+    pub fn set_observer(&mut self, observer: impl Fn(PkgEvent) + Send + Sync + 'static) {
+        self.state.set_observer(Some(Arc::new(observer)));
+    }
+
+    /// Removes the callback set with [`set_observer`](Self::set_observer).
+    pub fn clear_observer(&mut self) {
+        self.state.set_observer(None);
+    }
+
+    /// Returns counts of all the IO requests issued to the underlying storage so far.
+    ///
+    /// This includes requests issued by [`EntryReader`]s and [`EntryWriter`]s created by this
+    /// [`Pkg`]. The counts can be reset with [`reset_io_stats`](Self::reset_io_stats).
+    #[cfg(feature = "io_stats")]
+    #[doc(cfg(feature = "io_stats"))]
+    pub fn io_stats(&self) -> &IoStats {
+        &self.driver.stats
+    }
+
+    /// Resets the counts returned by [`io_stats`](Self::io_stats) to zero.
+    #[cfg(feature = "io_stats")]
+    #[doc(cfg(feature = "io_stats"))]
+    pub fn reset_io_stats(&mut self) {
+        self.driver.stats = IoStats::default();
+    }
+
+    /// Checks whether the archive contains `path`.
+    pub fn contains(&self, path: &str) -> bool {
+        self.state.contains(path)
+    }
+
+    /// Returns the index of the entry at `path` if the archive contains it.
+    ///
+    /// The index can be passed to [`open_index`](Self::open_index) to open the entry without
+    /// looking up its path again.
+    pub fn index_of(&self, path: &str) -> Option<usize> {
+        self.state.index(path)
+    }
+
+    /// Returns which regions of the archive inserting `path` would have to grow.
+    ///
+    /// Growing a region moves the data of the entries that are in its way, which makes an
+    /// insertion that has to do so much slower than one that doesn't. Paths that are already in
+    /// the archive can't be inserted and return [`GrowthKind::None`].
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::Cursor;
+    /// # use silpkg::{sync::Pkg, GrowthKind};
+    /// let pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// assert_eq!(pkg.insert_would_grow("hello.txt"), GrowthKind::None);
+    /// assert_eq!(pkg.insert_would_grow(&"a".repeat(4096)), GrowthKind::PathRegion);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn insert_would_grow(&self, path: &str) -> GrowthKind {
+        self.state.insert_growth(path)
+    }
+
+    /// Returns an iterator over all the paths in the archive, except for [`MTIME_ENTRY_PATH`].
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.state.listed_paths()
+    }
+
+    /// Returns a list of all the paths in the archive.
+    ///
+    /// Unlike [`Self::paths`] the returned paths are owned, so the archive can be modified while
+    /// iterating over them.
+    pub fn path_list(&self) -> Vec<String> {
+        self.paths().map(str::to_string).collect()
+    }
+
+    /// Returns an iterator over all the entries in the archive along with their metadata, except
+    /// for [`MTIME_ENTRY_PATH`].
+    pub fn entries(&self) -> impl Iterator<Item = (&str, EntryInfo)> {
+        self.state.listed_entries()
+    }
+
+    /// Returns the paths of all entries whose index is not in `known_indices`, ordered by index.
+    ///
+    /// Passing the indices of the entries from an earlier snapshot of the archive, for example
+    /// collected from [`entries`](Self::entries), lists the entries inserted since then without
+    /// having to compare their contents.
+    ///
+    /// # Notes
+    /// Inserted entries may reuse the slots of removed ones, an entry inserted after its slot
+    /// was emptied will therefore not be listed if that index is known. [`repack`](Self::repack)
+    /// renumbers all entries, so snapshots taken before it can't be compared against.
+    pub fn entries_after(&self, known_indices: &HashSet<usize>) -> Vec<&str> {
+        let mut entries = self
+            .entries()
+            .filter(|(_, info)| !known_indices.contains(&info.index))
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by_key(|(_, info)| info.index);
+        entries.into_iter().map(|(path, _)| path).collect()
+    }
+
+    /// Returns the sum of the uncompressed sizes of all entries, which is how much space
+    /// extracting the whole archive takes up.
+    pub fn total_uncompressed_size(&self) -> u64 {
+        self.entries()
+            .map(|(_, info)| u64::from(info.uncompressed_size))
+            .sum()
+    }
+
+    /// Returns the sum of the sizes of the data of all entries as stored in the archive.
+    pub fn total_compressed_size(&self) -> u64 {
+        self.entries()
+            .map(|(_, info)| u64::from(info.compressed_size))
+            .sum()
+    }
+
+    /// Returns whether the entry at `path` is deflate compressed or [`None`] if it doesn't exist.
+    pub fn is_compressed(&self, path: &str) -> Option<bool> {
+        self.metadata(path).map(|info| info.compressed)
+    }
+
+    /// Returns an iterator over the paths of all deflate compressed entries.
+    pub fn compressed_paths(&self) -> impl Iterator<Item = &str> {
+        self.entries()
+            .filter(|(_, info)| info.compressed)
+            .map(|(path, _)| path)
+    }
+
+    /// Returns an iterator over the paths of all entries that are stored uncompressed.
+    pub fn stored_paths(&self) -> impl Iterator<Item = &str> {
+        self.entries()
+            .filter(|(_, info)| !info.compressed)
+            .map(|(path, _)| path)
+    }
+
+    /// Groups the entries of the archive into a tree of directories by splitting their paths on
+    /// `/`.
+    ///
+    /// Empty path components, like the ones caused by a leading slash, are ignored so
+    /// `/lorem//ipsum.txt` ends up in the same directory as `lorem/ipsum.txt`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"hello")?;
+    /// pkg.insert("lorem/ipsum.txt".into(), Flags::default())?.write_all(b"dolor")?;
+    ///
+    /// let tree = pkg.tree();
+    /// assert_eq!(tree.files[0].name, "hello.txt");
+    /// assert_eq!(tree.get("lorem").unwrap().files[0].path, "lorem/ipsum.txt");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn tree(&self) -> DirNode {
+        self.state.tree()
+    }
+
+    /// Returns the `(compressed, uncompressed)` sizes of every directory, which are the sums of
+    /// the sizes of all entries inside of it and its subdirectories.
+    ///
+    /// Directories are keyed by their path without a trailing `/`, the root of the archive is
+    /// the empty string and always present. Like [`tree`](Self::tree) this ignores empty path
+    /// components and counts directory markers as the directory they mark.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"hello")?;
+    /// pkg.insert("lorem/ipsum.txt".into(), Flags::default())?.write_all(b"dolor")?;
+    /// pkg.insert("lorem/sit/amet.txt".into(), Flags::default())?.write_all(b"consectetur")?;
+    ///
+    /// let sizes = pkg.dir_sizes();
+    /// assert_eq!(sizes[""], (21, 21));
+    /// assert_eq!(sizes["lorem"], (16, 16));
+    /// assert_eq!(sizes["lorem/sit"], (11, 11));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn dir_sizes(&self) -> BTreeMap<String, (u64, u64)> {
+        self.state.dir_sizes()
+    }
+
+    /// Returns the names of the entries and directories directly inside of the directory `prefix`
+    /// in sorted order.
+    ///
+    /// Directory names keep their trailing `/` to tell them apart from entries. A `/` is appended
+    /// to `prefix` if it doesn't already end with one, an empty `prefix` lists the root. Unlike
+    /// [`tree`](Self::tree) paths are matched as is, without ignoring empty components.
+    /// Directory markers show up as the directory they mark, see
+    /// [`is_directory_marker`](Self::is_directory_marker).
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"hello")?;
+    /// pkg.insert("lorem/ipsum.txt".into(), Flags::default())?.write_all(b"dolor")?;
+    ///
+    /// assert!(pkg.children("").eq(["hello.txt", "lorem/"]));
+    /// assert!(pkg.children("lorem").eq(["ipsum.txt"]));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn children(&self, prefix: &str) -> impl Iterator<Item = &str> {
+        self.state.children(prefix).into_iter()
+    }
+
+    /// Returns whether the entry at `path` is a directory marker.
+    ///
+    /// Some tools store directories as empty entries whose path ends with a `/`. These are
+    /// treated as directories by [`tree`](Self::tree) and [`children`](Self::children) and
+    /// usually have to be skipped when extracting, since no file can be created at such a path.
+    /// Returns `false` if the entry doesn't exist.
+    pub fn is_directory_marker(&self, path: &str) -> bool {
+        self.state.is_directory_marker(path)
+    }
+
+    /// Returns an iterator over every slot in the entry table, including empty ones.
+    ///
+    /// Empty slots are left behind by removed entries and preallocated for future insertions,
+    /// they're yielded as [`None`] along with their index. This is mostly useful for debugging,
+    /// use [`entries`](Self::entries) if you only care about entries that actually exist.
+    pub fn entry_slots(&self) -> impl Iterator<Item = (usize, Option<EntryInfo>)> + '_ {
+        self.state.entry_slots()
+    }
+
+    /// Returns an iterator over the raw records of every slot in the entry table in the order
+    /// they're stored in, including empty ones.
+    ///
+    /// Unlike [`entry_slots`](Self::entry_slots) this exposes the fields exactly as they appear
+    /// on disk, which is useful for comparing archives byte by byte or seeing how
+    /// [`repack`](Self::repack) rearranged them. Empty slots are yielded as [`None`].
+    pub fn entry_records(&self) -> impl Iterator<Item = (usize, Option<RawEntryView<'_>>)> {
+        self.state.entry_records()
+    }
+
+    /// Returns the paths of all entries for which `pred` returns `true`.
+    ///
+    /// # Examples
+    /// ```
+    /// # use silpkg::{Flags, sync::*};
+    /// # let mut pkg = Pkg::create(std::io::Cursor::new(Vec::new()))?;
+    /// // Find all entries that are stored uncompressed and are larger than 1MiB
+    /// let big = pkg.find(|_path, info| {
+    ///     info.compressed_size == info.uncompressed_size && info.uncompressed_size > 1024 * 1024
+    /// });
+    /// # assert!(big.is_empty());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn find(&self, pred: impl Fn(&str, &EntryInfo) -> bool) -> Vec<&str> {
+        self.entries()
+            .filter(|(path, info)| pred(path, info))
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// Parses a [`Pkg`] from the supplied reader.
+    pub fn parse(storage: S) -> Result<Self, ParseError> {
+        Self::parse_with_options(storage, ParseOptions::default())
+    }
+
+    /// Reads the header of the archive in `storage` and returns an iterator over its entries that
+    /// decodes each of them as it's read.
+    ///
+    /// This allows processing the entries of huge archives, or stopping early, before the whole
+    /// entry list was read. Neither paths nor data are read, entries can be told apart by their
+    /// [`index`](EntryInfo::index) and [`path_hash`](EntryInfo::path_hash). Only
+    /// [`ParseOptions::allow_extended_layout`] and [`ParseOptions::preserve_unknown_flags`] are
+    /// taken into account. The iterator stops after yielding the first error.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::Cursor;
+    /// # use silpkg::{sync::Pkg, ParseOptions};
+    /// # let mut storage = Cursor::new(vec![]);
+    /// # Pkg::create(&mut storage)?.insert("hello.txt".into(), Default::default())?;
+    /// let mut scan = Pkg::scan_entries(storage, ParseOptions::default())?;
+    /// while let Some(info) = scan.next().transpose()? {
+    ///     println!("{}/{}: {:#010X}", scan.position(), scan.entry_count(), info.path_hash);
+    /// }
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn scan_entries(storage: S, options: ParseOptions) -> Result<EntryScan<S>, ParseError> {
+        let mut driver = SyncDriver::new(storage);
+        let scanner = driver.drive_read(base::scan_entries(options)).flatten()?;
+
+        Ok(EntryScan {
+            driver,
+            scanner,
+            done: false,
+        })
+    }
+
+    /// Parses a [`Pkg`] from the supplied reader using the specified [`ParseOptions`].
+    pub fn parse_with_options(storage: S, options: ParseOptions) -> Result<Self, ParseError> {
+        let mut driver = SyncDriver::new(storage);
+        let skip_paths = options.skip_paths;
+        let format = options.format;
+        let state = driver.drive_read(base::parse(true, options)).flatten()?;
+        if skip_paths {
+            driver.read_only = Some(ReadOnly::SkippedPaths);
+        } else if state.format() != format {
+            driver.read_only = Some(ReadOnly::ExtendedLayout);
+        } else if !state.dropped_entries().is_empty() {
+            driver.read_only = Some(ReadOnly::DroppedEntries);
+        }
+
+        Ok(Self { driver, state })
+    }
+
+    /// Parses a [`Pkg`] from the supplied reader without reading any paths.
+    ///
+    /// This is a shorthand for [`parse_with_options`](Self::parse_with_options) with
+    /// [`ParseOptions::skip_paths`] set, see its documentation for the limitations of archives
+    /// parsed this way. Entries can be found through [`entry_slots`](Self::entry_slots) or
+    /// opened with [`open_by_hash`](Self::open_by_hash).
+    pub fn parse_headers_only(storage: S) -> Result<Self, ParseError> {
+        Self::parse_with_options(
+            storage,
+            ParseOptions {
+                skip_paths: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Opens an entry for reading.
+    ///
+    /// Every call hashes `path` to look up its entry. Code that opens the same entries over and
+    /// over can look up their indices once with [`index_of`](Self::index_of) and use
+    /// [`open_index`](Self::open_index) instead.
+    ///
+    /// # Errors
+    /// - [`OpenError::NotFound`] if the entry does not exist.
+    /// - [`OpenError::UnrecognisedEntryFlags`] if the entry has flags that can't be interpreted.
+    /// - [`OpenError::Io`] if an IO error occurs.
+    pub fn open(&mut self, path: &str) -> Result<EntryReader<'_, S>, OpenError> {
+        let handle = self
+            .driver
+            .drive_read(base::open(&self.state, path))
+            .flatten()?;
+
+        Ok(EntryReader {
+            driver: &mut self.driver,
+            handle,
+        })
+    }
+
+    /// Opens an entry for reading and returns it along with its metadata.
+    ///
+    /// This only looks up `path` once, which makes it a cheaper way of getting the size of the
+    /// entry before reading it than calling [`metadata`](Self::metadata) and
+    /// [`open`](Self::open) separately.
+    ///
+    /// # Errors
+    /// Same as [`open`](Self::open).
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Read, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"Hello!")?;
+    ///
+    /// let (mut reader, info) = pkg.open_with_info("hello.txt")?;
+    /// let mut data = Vec::with_capacity(info.uncompressed_size as usize);
+    /// reader.read_to_end(&mut data)?;
+    /// assert_eq!(data, b"Hello!");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn open_with_info(
+        &mut self,
+        path: &str,
+    ) -> Result<(EntryReader<'_, S>, EntryInfo), OpenError> {
+        let index = self.state.index(path).ok_or(OpenError::NotFound)?;
+        let info = self.state.info(index).unwrap();
+
+        Ok((self.open_index(index)?, info))
+    }
+
+    /// Opens the entry at `subpath` inside the directory `prefix` for reading.
+    ///
+    /// This is meant for archives mounted under a virtual path, `open_under("assets/", "foo.png")`
+    /// opens `assets/foo.png`. Exactly one `/` is placed between `prefix` and `subpath` no matter
+    /// if either of them already has one, an empty `prefix` opens `subpath` itself.
+    ///
+    /// # Errors
+    /// Same as [`open`](Self::open).
+    pub fn open_under(
+        &mut self,
+        prefix: &str,
+        subpath: &str,
+    ) -> Result<EntryReader<'_, S>, OpenError> {
+        self.open(&base::join_path(prefix, subpath))
+    }
+
+    /// Opens the raw on-disk data of an entry for reading.
+    ///
+    /// The returned reader will yield exactly the bytes stored in the archive for this entry,
+    /// compressed entries are not decompressed and no validation of the data is performed.
+    /// This is intended as a way to salvage data from corrupted entries that fail to be read
+    /// through [`open`](Self::open).
+    ///
+    /// # Errors
+    /// - [`OpenError::NotFound`] if the entry does not exist.
+    /// - [`OpenError::Io`] if an IO error occurs.
+    pub fn open_raw_lenient(&mut self, path: &str) -> Result<EntryReader<'_, S>, OpenError> {
+        let handle = self
+            .driver
+            .drive_read(base::open_raw(&self.state, path))
+            .flatten()?;
+
+        Ok(EntryReader {
+            driver: &mut self.driver,
+            handle,
+        })
+    }
+
+    /// Opens the entry at `index` for reading.
+    ///
+    /// Indices can be obtained from [`EntryInfo::index`] or [`index_of`](Self::index_of), this
+    /// avoids looking up the entry by its path again. An index keeps referring to the same entry
+    /// until that entry is removed or the archive is [`repack`](Pkg::repack)ed.
+    ///
+    /// # Errors
+    /// - [`OpenError::NotFound`] if there is no entry at `index`.
+    /// - [`OpenError::UnrecognisedEntryFlags`] if the entry has flags that can't be interpreted.
+    /// - [`OpenError::Io`] if an IO error occurs.
+    pub fn open_index(&mut self, index: usize) -> Result<EntryReader<'_, S>, OpenError> {
+        let handle = self
+            .driver
+            .drive_read(base::open_index(&self.state, index))
+            .flatten()?;
+
+        Ok(EntryReader {
+            driver: &mut self.driver,
+            handle,
+        })
+    }
+
+    /// Writes the contents of the entry at `path` to `out`, returning the number of bytes written.
+    ///
+    /// This is a shorthand for [`open`](Self::open) followed by [`std::io::copy`].
+    ///
+    /// # Errors
+    /// - [`OpenError::NotFound`] if the entry does not exist.
+    /// - [`OpenError::UnrecognisedEntryFlags`] if the entry has flags that can't be interpreted.
+    /// - [`OpenError::Io`] if an IO error occurs while reading the entry or writing to `out`.
+    pub fn extract_entry(&mut self, path: &str, out: &mut impl Write) -> Result<u64, OpenError> {
+        Ok(std::io::copy(&mut self.open(path)?, out)?)
+    }
+
+    /// Writes the bytes in `range` of the entry at `path` to `out`, returning the number of bytes
+    /// written.
+    ///
+    /// The range is clamped to the size of the entry, so a range extending past its end writes
+    /// fewer bytes and one starting past it writes none. Uncompressed entries are read starting
+    /// at `range.start` directly, compressed entries have to be decompressed from their start
+    /// with everything before `range.start` being discarded.
+    ///
+    /// # Errors
+    /// - [`OpenError::NotFound`] if the entry does not exist.
+    /// - [`OpenError::UnrecognisedEntryFlags`] if the entry has flags that can't be interpreted.
+    /// - [`OpenError::Io`] if an IO error occurs while reading the entry or writing to `out`.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"Hello, world!")?;
+    ///
+    /// let mut out = vec![];
+    /// assert_eq!(pkg.read_range("hello.txt", 7..12, &mut out)?, 5);
+    /// assert_eq!(out, b"world");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn read_range(
+        &mut self,
+        path: &str,
+        range: Range<u64>,
+        out: &mut impl Write,
+    ) -> Result<u64, OpenError> {
+        let (mut reader, info) = self.open_with_info(path)?;
+        let size = info.uncompressed_size as u64;
+        let start = range.start.min(size);
+        let end = range.end.clamp(start, size);
+
+        if info.compressed {
+            std::io::copy(&mut (&mut reader).take(start), &mut std::io::sink())?;
+        } else {
+            reader.seek(std::io::SeekFrom::Start(start))?;
+        }
+
+        Ok(std::io::copy(&mut reader.take(end - start), out)?)
+    }
+
+    /// Reads the whole entry at `path` into memory, decompressing it if needed.
+    ///
+    /// The returned [`OwnedEntryBytes`] is independent of the [`Pkg`], which makes it possible
+    /// to hand entries off to worker threads at the cost of buffering each of them in full.
+    ///
+    /// # Errors
+    /// - [`NotFound`] if the entry does not exist.
+    /// - Any other error [`open`](Self::open) or reading the entry returns.
+    ///
+    /// [`NotFound`]: std::io::ErrorKind::NotFound
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Read, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"Hello!")?;
+    ///
+    /// let mut entry = pkg.read_entry_owned("hello.txt")?;
+    /// let worker = std::thread::spawn(move || {
+    ///     let mut contents = String::new();
+    ///     entry.read_to_string(&mut contents).map(|_| contents)
+    /// });
+    /// assert_eq!(worker.join().unwrap()?, "Hello!");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn read_entry_owned(&mut self, path: &str) -> std::io::Result<OwnedEntryBytes> {
+        let (mut reader, info) = self.open_with_info(path)?;
+        let mut data = Vec::with_capacity(info.uncompressed_size as usize);
+        reader.read_to_end(&mut data)?;
+
+        Ok(OwnedEntryBytes(std::io::Cursor::new(data)))
+    }
+
+    /// Opens the first entry whose path has the hash `hash` for reading.
+    ///
+    /// This is mostly useful for archives parsed with [`parse_headers_only`], which can't look
+    /// up entries by path. Hashes are compared against [`EntryInfo::path_hash`].
+    ///
+    /// Archives parsed with [`ParseOptions::skip_paths`] build a lookup table of the hashes while
+    /// parsing, for other archives every call scans the entry list instead.
+    ///
+    /// # Errors
+    /// - [`OpenError::NotFound`] if no entry has the hash `hash`.
+    /// - [`OpenError::UnrecognisedEntryFlags`] if the entry has flags that can't be interpreted.
+    /// - [`OpenError::Io`] if an IO error occurs.
+    ///
+    /// [`parse_headers_only`]: Self::parse_headers_only
+    pub fn open_by_hash(&mut self, hash: u32) -> Result<EntryReader<'_, S>, OpenError> {
+        let index = self.state.index_by_hash(hash).ok_or(OpenError::NotFound)?;
+        self.open_index(index)
+    }
+
+    /// Returns the free regions in the archive's data region as `(offset, size)` pairs.
+    ///
+    /// Free regions are gaps between entries' data left behind after entries are removed or
+    /// replaced, they are sorted by offset and are reused by [`insert_sized`].
+    /// [`repack`]ing an archive removes all free regions.
+    ///
+    /// [`insert_sized`]: Pkg::insert_sized
+    /// [`repack`]: Pkg::repack
+    pub fn free_regions(&self) -> &[(u64, u64)] {
+        self.state.free_regions()
+    }
+
+    /// Returns the size the archive would have after being [`repack`](Pkg::repack)ed.
+    ///
+    /// This is the size of the header, an entry table without empty slots, a path region without
+    /// any padding and the data of every entry. It is computed from the parsed metadata alone so
+    /// no IO is performed, comparing it with the current size of the storage tells you how much
+    /// space a repack would reclaim.
+    pub fn minimum_size(&self) -> u64 {
+        self.state.minimum_size()
+    }
+
+    /// Returns the number of bytes in the path region that hold paths of no entry.
+    ///
+    /// These are left behind by [`rename`](Pkg::rename)s and removals and are reclaimed by a
+    /// [`repack`](Pkg::repack) or the cheaper [`compact_paths`](Pkg::compact_paths). Padding
+    /// reserved for future paths isn't counted. Like [`minimum_size`](Pkg::minimum_size) this is
+    /// computed from the parsed metadata alone.
+    pub fn stale_path_bytes(&self) -> u32 {
+        self.state.stale_path_bytes()
+    }
+
+    /// Returns whether enough space is wasted for a [`repack`](Pkg::repack) to be worth it.
+    ///
+    /// This is the case once the [`free_regions`](Pkg::free_regions) and
+    /// [`stale_path_bytes`](Pkg::stale_path_bytes) add up to more than a quarter of the
+    /// [`minimum_size`](Pkg::minimum_size). Unused space at the end of the storage isn't
+    /// counted. If most of the waste is stale paths, [`compact_paths`](Pkg::compact_paths)
+    /// reclaims it without moving any data.
+    pub fn should_repack(&self) -> bool {
+        self.state.should_repack()
+    }
+
+    /// Returns the size of everything stored after the path region.
+    ///
+    /// This includes the data of every entry along with any free regions and unused space at the
+    /// end of the storage, which is what accumulates after many removals without a
+    /// [`repack`](Pkg::repack).
+    pub fn data_region_size(&mut self) -> std::io::Result<u64> {
+        self.driver.drive_read(base::data_region_size(&self.state))
+    }
+
+    /// Checks whether a [`repack`](Pkg::repack) would succeed without corrupting any entries,
+    /// without modifying the archive.
+    ///
+    /// This simulates the moves a repack would make using only the parsed metadata, reporting
+    /// entries whose data overlaps (which makes repacking fail with
+    /// [`RepackError::OverlappingEntries`]), entries whose data extends past the end of the
+    /// storage and entries that would overwrite data that hasn't been moved yet. It's meant to
+    /// gate a real repack, for example in CI before distributing an archive.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("a".into(), Flags::default())?.write_all(b"hello")?;
+    ///
+    /// let check = pkg.dry_repack_verify()?;
+    /// assert!(check.is_ok());
+    /// pkg.repack()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn dry_repack_verify(&mut self) -> std::io::Result<RepackCheck> {
+        self.driver.drive_read(base::check_repack(&self.state))
+    }
+
+    /// Checks whether the storage continues past the end of the entry that ends last.
+    ///
+    /// Archives with trailing garbage can be rejected while parsing with
+    /// [`ParseOptions::reject_trailing_garbage`], a [`repack`](Pkg::repack) gets rid of it.
+    pub fn has_trailing_garbage(&mut self) -> std::io::Result<bool> {
+        self.driver
+            .drive_read(base::has_trailing_garbage(&self.state))
+    }
+
+    /// Returns the paths of all entries whose data extends past the end of the storage, sorted.
+    ///
+    /// This is the case for archives that were truncated after being written, reading such an
+    /// entry stops early at the end of the storage without returning an error. Parsing with
+    /// [`ParseOptions::repair_truncated`] leaves these entries out instead.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags, ParseOptions};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("first.txt".into(), Flags::default())?.write_all(b"intact")?;
+    /// pkg.insert("second.txt".into(), Flags::default())?.write_all(b"truncated")?;
+    ///
+    /// let mut storage = pkg.inner().get_ref().clone();
+    /// storage.truncate(storage.len() - 4);
+    /// let mut pkg = Pkg::parse(Cursor::new(storage.clone()))?;
+    /// assert_eq!(pkg.check_truncation()?, ["second.txt"]);
+    ///
+    /// let options = ParseOptions { repair_truncated: true, ..Default::default() };
+    /// let mut pkg = Pkg::parse_with_options(Cursor::new(storage), options)?;
+    /// assert_eq!(pkg.dropped_entries(), ["second.txt"]);
+    /// assert!(pkg.check_truncation()?.is_empty());
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn check_truncation(&mut self) -> std::io::Result<Vec<String>> {
+        self.driver.drive_read(base::truncated_entries(&self.state))
+    }
+
+    /// Returns the paths of the entries that were left out while parsing because of
+    /// [`ParseOptions::repair_truncated`], in the order they're stored in the entry list.
+    ///
+    /// This is empty unless that option was set. The paths are empty for archives parsed with
+    /// [`ParseOptions::skip_paths`].
+    pub fn dropped_entries(&self) -> &[String] {
+        self.state.dropped_entries()
+    }
+
+    /// Retrieve entry metadata, like size or index.
+    ///
+    /// # Notes
+    /// Returns [`None`] if an entry with that name does not exist.
+    pub fn metadata(&self, path: &str) -> Option<EntryInfo> {
+        self.state.index(path).and_then(|idx| self.state.info(idx))
+    }
+
+    /// Reads the modification times stored in the archive.
+    ///
+    /// Modification times are not part of the PKG format and are only present if they were
+    /// explicitly stored using [`set_modification_times`](Pkg::set_modification_times), if they
+    /// weren't then an empty map is returned.
+    ///
+    /// # Errors
+    /// - [`OpenError::Io`] if an IO error occurs or the [`MTIME_ENTRY_PATH`] entry is malformed.
+    pub fn modification_times(&mut self) -> Result<HashMap<String, SystemTime>, OpenError> {
+        if !self.contains(MTIME_ENTRY_PATH) {
+            return Ok(HashMap::new());
+        }
+
+        let mut data = vec![];
+        self.open(MTIME_ENTRY_PATH)?.read_to_end(&mut data)?;
+
+        Ok(decode_mtimes(&data)?)
+    }
+
+    /// Computes a SHA-256 hash of the logical contents of the archive.
+    ///
+    /// Entries are hashed in sorted path order, each contributing its path, uncompressed size and
+    /// uncompressed data. The hash does not depend on the on-disk layout or compression of the
+    /// entries, so two archives containing the same files will hash the same even if one of them
+    /// was [`repack`](Pkg::repack)ed or compressed differently.
+    ///
+    /// # Notes
+    /// [`MTIME_ENTRY_PATH`] is not included, so storing modification times does not change the
+    /// hash.
+    #[cfg(feature = "content_hash")]
+    #[doc(cfg(feature = "content_hash"))]
+    pub fn content_hash(&mut self) -> std::io::Result<[u8; 32]> {
+        use sha2::Digest;
+
+        let mut paths = self.path_list();
+        paths.sort();
+
+        let mut hasher = sha2::Sha256::new();
+        for path in paths {
+            let size = self.metadata(&path).unwrap().uncompressed_size;
+
+            // Paths can't contain NUL so this unambiguously separates them from the data.
+            hasher.update(path.as_bytes());
+            hasher.update([0]);
+            hasher.update(u64::from(size).to_le_bytes());
+            std::io::copy(&mut self.open(&path)?, &mut hasher)?;
+        }
+
+        Ok(hasher.finalize().into())
+    }
+
+    /// Finds groups of entries with byte-identical uncompressed contents.
+    ///
+    /// Each returned group contains at least two paths, groups and the paths inside them are
+    /// sorted.
+    ///
+    /// # Notes
+    /// This is an expensive operation, every entry that has the same uncompressed size as some
+    /// other entry is read and decompressed in full and entries that turn out to be duplicates
+    /// are read once more to rule out hash collisions.
+    pub fn find_duplicates(&mut self) -> std::io::Result<Vec<Vec<String>>> {
+        let mut by_size = HashMap::<u32, Vec<String>>::new();
+        for (path, info) in self.entries() {
+            by_size
+                .entry(info.uncompressed_size)
+                .or_default()
+                .push(path.to_string());
+        }
+
+        let mut groups = vec![];
+        for (_, paths) in by_size {
+            if paths.len() < 2 {
+                continue;
+            }
+
+            let mut by_hash = HashMap::<u64, Vec<String>>::new();
+            for path in paths {
+                by_hash
+                    .entry(self.content_hash_fnv(&path)?)
+                    .or_default()
+                    .push(path);
+            }
+
+            for (_, mut candidates) in by_hash {
+                // Repeatedly split off everything that matches the first candidate
+                while candidates.len() > 1 {
+                    let mut first = vec![];
+                    self.open(&candidates[0])?.read_to_end(&mut first)?;
+
+                    let mut group = vec![candidates.swap_remove(0)];
+                    let mut rest = vec![];
+                    for path in candidates {
+                        let mut data = Vec::with_capacity(first.len());
+                        self.open(&path)?.read_to_end(&mut data)?;
+                        if data == first {
+                            group.push(path);
+                        } else {
+                            rest.push(path);
+                        }
+                    }
+
+                    if group.len() > 1 {
+                        group.sort();
+                        groups.push(group);
+                    }
+                    candidates = rest;
+                }
+            }
+        }
+
+        groups.sort();
+        Ok(groups)
+    }
+
+    // Returns the 64-bit FNV-1a hash of the uncompressed data of the entry at `path`.
+    fn content_hash_fnv(&mut self, path: &str) -> std::io::Result<u64> {
+        let mut hasher = ContentHasher::new();
+        std::io::copy(&mut self.open(path)?, &mut hasher)?;
+        Ok(hasher.0)
+    }
+
+    /// Returns a [`Manifest`] describing every entry in the archive.
+    ///
+    /// If `content_hashes` is `true` every entry is read in full to hash its contents, which makes
+    /// [`validate_against`](Self::validate_against) compare the contents too.
+    ///
+    /// # Errors
+    /// Returns an error if an entry can't be read or an IO error occurs.
+    pub fn manifest(&mut self, content_hashes: bool) -> std::io::Result<Manifest> {
+        let mut manifest = Manifest::default();
+        for path in self.path_list() {
+            let info = self.metadata(&path).unwrap();
+            let content_hash = match content_hashes {
+                true => Some(self.content_hash_fnv(&path)?),
+                false => None,
+            };
+
+            manifest.entries.insert(
+                path,
+                ManifestEntry {
+                    size: info.uncompressed_size,
+                    compressed: info.compressed,
+                    content_hash,
+                },
+            );
+        }
+
+        Ok(manifest)
+    }
+
+    /// Checks that the archive contains exactly the entries in `manifest` with the properties
+    /// listed there.
+    ///
+    /// Every difference is reported, entries in the manifest come first in the order of their
+    /// paths followed by entries that are only in the archive, which makes the result suitable
+    /// for printing. Contents are only compared if `check_contents` is `true` and the manifest
+    /// entry has a [`content_hash`](ManifestEntry::content_hash), this reads the entry in full.
+    /// An empty result means the archive matches the manifest.
+    ///
+    /// # Errors
+    /// Returns an error if an entry can't be read or an IO error occurs.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{errors::ValidationError, sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"Hello!")?;
+    /// let manifest = pkg.manifest(true)?;
+    /// assert!(pkg.validate_against(&manifest, true)?.is_empty());
+    ///
+    /// pkg.insert("extra.txt".into(), Flags::default())?.write_all(b"Extra!")?;
+    /// assert_eq!(
+    ///     pkg.validate_against(&manifest, true)?,
+    ///     [ValidationError::Unexpected("extra.txt".into())]
+    /// );
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn validate_against(
+        &mut self,
+        manifest: &Manifest,
+        check_contents: bool,
+    ) -> std::io::Result<Vec<errors::ValidationError>> {
+        use errors::ValidationError;
+
+        let mut errors = vec![];
+        for (path, expected) in manifest.entries.iter() {
+            let Some(info) = self.metadata(path) else {
+                errors.push(ValidationError::Missing(path.clone()));
+                continue;
+            };
+
+            if info.uncompressed_size != expected.size {
+                errors.push(ValidationError::SizeMismatch {
+                    path: path.clone(),
+                    expected: expected.size,
+                    actual: info.uncompressed_size,
+                });
+            }
+            if info.compressed != expected.compressed {
+                errors.push(ValidationError::CompressionMismatch {
+                    path: path.clone(),
+                    expected: expected.compressed,
+                    actual: info.compressed,
+                });
+            }
+            if let Some(hash) = expected.content_hash.filter(|_| check_contents) {
+                if self.content_hash_fnv(path)? != hash {
+                    errors.push(ValidationError::ContentMismatch(path.clone()));
+                }
+            }
+        }
+
+        let mut unexpected = self
+            .paths()
+            .filter(|path| !manifest.entries.contains_key(*path))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        unexpected.sort();
+        errors.extend(unexpected.into_iter().map(ValidationError::Unexpected));
+
+        Ok(errors)
+    }
+
+    /// Writes every entry into a new archive created in `out` with compression chosen by `policy`.
+    ///
+    /// Entries are written one after another into the new archive, so unlike changing the
+    /// compression in place this leaves no free space behind and the result doesn't need to be
+    /// [`repack`](Pkg::repack)ed. The new archive uses the same [`DeflateFormat`] as this one and
+    /// keeps the stored [modification times](Pkg::modification_times).
+    ///
+    /// # Notes
+    /// With [`CompressionPolicy::Keep`] compressed data is copied without being decompressed, so
+    /// corrupted deflate streams are copied as they are instead of causing an error.
+    ///
+    /// # Errors
+    /// Returns an error if an entry can't be read or an IO error occurs, `out` may then contain a
+    /// partially written archive.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, CompressionPolicy, EntryCompression, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"Hello!")?;
+    ///
+    /// let policy = CompressionPolicy::All(EntryCompression::deflate_checked(9)?);
+    /// let transcoded = pkg.transcode_to(Cursor::new(vec![]), policy)?;
+    /// assert_eq!(transcoded.is_compressed("hello.txt"), Some(true));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn transcode_to<W: Read + Seek + Write>(
+        &mut self,
+        out: W,
+        policy: CompressionPolicy,
+    ) -> std::io::Result<Pkg<W>> {
+        let mut out = Pkg::create(out)?;
+        out.set_deflate_format(self.state.deflate_format());
+
+        // The modification times aren't listed by entries() but still have to be carried over
+        let mut entries = self
+            .entries()
+            .map(|(path, info)| (path.to_string(), info))
+            .chain(
+                self.metadata(MTIME_ENTRY_PATH)
+                    .map(|info| (MTIME_ENTRY_PATH.to_string(), info)),
+            )
+            .collect::<Vec<_>>();
+        // Reading entries in the order their data is stored in avoids seeking back and forth
+        entries.sort_by_key(|(_, info)| info.data_offset);
+
+        for (path, info) in entries {
+            let compression = match &policy {
+                CompressionPolicy::Keep if info.compressed => {
+                    let handle = out
+                        .driver
+                        .drive_write(
+                            out.state
+                                .insert_precompressed(path.clone(), info.uncompressed_size.into()),
+                        )
+                        .flatten()?;
+                    let mut writer = EntryWriter::new(&mut out.driver, handle);
+                    std::io::copy(&mut self.open_raw_lenient(&path)?, &mut writer)?;
+                    writer.finish()?;
+                    continue;
+                }
+                CompressionPolicy::Keep => EntryCompression::None,
+                CompressionPolicy::All(compression) => compression.clone(),
+            };
+
+            let mut writer = out.insert(path.clone(), Flags { compression })?;
+            std::io::copy(&mut self.open(&path)?, &mut writer)?;
+            writer.finish()?;
+        }
+
+        out.flush()?;
+        Ok(out)
+    }
+
+    // TODO: Add a way to access this metadata
+    // pub fn fixme_remove_this_print_size_info(&mut self) {
+    //     {
+    //         let occupied = self.entries.iter().filter(|x| x.is_some()).count();
+    //         println!(
+    //             "Entries: {} ({} occupied, {} empty)",
+    //             self.entries.len(),
+    //             occupied,
+    //             self.entries.len() - occupied
+    //         );
+    //     }
+    //     println!(
+    //         "Path region size: {}B ({}B occupied, {}B unused)",
+    //         self.path_region_size,
+    //         self.path_region_empty_offset,
+    //         self.path_region_size - self.path_region_empty_offset
+    //     );
+    //
+    //     println!("Data region:");
+    //     let data_region_size = self.storage.stream_len().unwrap() - self.data_region_offset();
+    //     let occupied_data_region = self
+    //         .entries
+    //         .iter()
+    //         .filter_map(|opt| opt.as_ref().map(|entry| entry.data_size as u64))
+    //         .sum::<u64>();
+    //     println!(
+    //         "  Size: {data_region_size}B ({}B occupied, {}B unused)",
+    //         occupied_data_region,
+    //         data_region_size - occupied_data_region
+    //     );
+    //     println!(
+    //         "  Fragmentation: {:.4}%",
+    //         ((data_region_size - occupied_data_region) as f64) / data_region_size as f64
+    //     )
+    // }
+} // Read + Seek
+
+/// A writer that allows writing a single entry into a [`Pkg`].
+///
+/// The entry is only updated in the archive once the writer is flushed, finished or dropped.
+/// Until then inserted entries don't exist yet and replaced ones keep their old data, so a
+/// writer that is leaked or dropped while panicking leaves the [`Pkg`] as it was before, apart
+/// from some unused space.
+pub struct EntryWriter<'a, S: Read + Seek + Write> {
+    driver: &'a mut SyncDriver<S>,
+    handle: ManuallyDrop<base::WriteHandle<'a>>,
+    // Whether dropping the writer updates the entry like finish does.
+    commit_on_drop: bool,
+}
+
+impl<'a, S: Read + Seek + Write> EntryWriter<'a, S> {
+    fn new(driver: &'a mut SyncDriver<S>, handle: base::WriteHandle<'a>) -> Self {
+        Self {
+            driver,
+            handle: ManuallyDrop::new(handle),
+            commit_on_drop: true,
+        }
+    }
+
+    /// Returns the uncompressed size of the entry's data written so far.
+    ///
+    /// This only reads counters kept by the writer and doesn't perform any IO, so it's cheap
+    /// enough to call after every write, for example to report progress. For uncompressed
+    /// entries this is the length of the data, which isn't affected by seeking back and includes
+    /// any data that was already there when the entry was opened with [`Pkg::append`].
+    pub fn written(&self) -> u64 {
+        self.handle.written()
+    }
+
+    /// Updates the entry's record in the archive without flushing the underlying writer.
+    ///
+    /// [`flush`](Write::flush) does the same and then also flushes the underlying writer, which
+    /// is wasteful when the storage is buffered and many entries are written before it is flushed
+    /// once at the end.
+    pub fn flush_metadata(&mut self) -> std::io::Result<()> {
+        Ok(self.driver.drive_write(self.handle.flush()).flatten()?)
+    }
+
+    /// Writes entry metadata to the underlying writer.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        let handle = unsafe { ManuallyDrop::take(&mut self.handle) };
+        let result = self
+            .driver
+            .drive_write(handle.finish())
+            .flatten()
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.driver.get_mut().flush());
+        // The handle is gone already, dropping the writer would take it again
+        std::mem::forget(self);
+
+        result
+    }
+
+    /// Makes dropping this writer discard the written data instead of updating the entry, so
+    /// that only [`finish`](Self::finish) or flushing it persists anything.
+    ///
+    /// This turns a forgotten [`finish`](Self::finish), or an early return after an error,
+    /// into a no-op instead of an entry with partially written data. Inserted entries then
+    /// don't exist and replaced ones keep their old data, unless the writer was flushed before
+    /// being dropped. The data written so far is left behind as unused space either way.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    ///
+    /// let mut writer = pkg.insert("hello.txt".into(), Flags::default())?.rollback_on_drop();
+    /// writer.write_all(b"Hel")?;
+    /// drop(writer);
+    /// assert!(!pkg.contains("hello.txt"));
+    ///
+    /// let mut writer = pkg.insert("hello.txt".into(), Flags::default())?.rollback_on_drop();
+    /// writer.write_all(b"Hello!")?;
+    /// writer.finish()?;
+    /// assert!(pkg.contains("hello.txt"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn rollback_on_drop(mut self) -> Self {
+        self.commit_on_drop = false;
+        self
+    }
+
+    /// Drops the writer without updating the entry, any data written so far is left unused.
+    fn discard(mut self) {
+        unsafe { ManuallyDrop::drop(&mut self.handle) };
+        std::mem::forget(self);
+    }
+}
+
+impl<S: Read + Seek + Write> Write for EntryWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.driver.drive_write(self.handle.write(buf))?;
+        if self.handle.overflowed() {
+            return Err(InsertError::InvalidOffset.into());
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_metadata()?;
+        self.driver.get_mut().flush()
+    }
+}
+
+/// # Notes
+/// Reading from a compressed entry writer will fail with [`ReadError::NotReadable`] converted to
+/// [`Unsupported`].
+///
+/// [`ReadError::NotReadable`]: crate::errors::ReadError::NotReadable
+/// [`Unsupported`]: std::io::ErrorKind::Unsupported
+impl<S: Read + Seek + Write> Read for EntryWriter<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.handle.inner_mut() {
+            DataWriteHandle::Raw(handle) => {
+                Ok(self.driver.drive_read(handle.read(buf)).flatten()?)
+            }
+            DataWriteHandle::Deflate(_) => Err(ReadError::NotReadable.into()),
+        }
+    }
+}
+
+/// # Notes
+/// Even though this type implements [`Seek`] [`seek`]ing will not always succeed, for example if the entry
+/// happens to be compressed then [`seek`]ing will fail with [`NotSeekable`].
+///
+/// [`seek`]: EntryReader::seek
+/// [`NotSeekable`]: std::io::ErrorKind::NotSeekable
+impl<S: Read + Seek + Write> Seek for EntryWriter<'_, S> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        match self.handle.inner_mut() {
+            DataWriteHandle::Raw(handle) => {
                 Ok(self.driver.drive_read(handle.seek(pos.into())).flatten()?)
             }
-            DataWriteHandle::Deflate(_) => Err(std::io::Error::new(
-                std::io::ErrorKind::NotSeekable,
-                "Cannot seek on compressed entry writer",
-            )),
+            DataWriteHandle::Deflate(_) => Err(SeekError::NotSeekable.into()),
         }
     }
 }
@@ -403,15 +2042,88 @@ impl<S: Read + Seek + Write> Seek for EntryWriter<'_, S> {
 impl<S: Read + Seek + Write> Drop for EntryWriter<'_, S> {
     /// Writes entry metadata to the underlying writer.
     ///
+    /// The entry is left untouched instead if [`rollback_on_drop`](Self::rollback_on_drop) was
+    /// called or if the thread is panicking, since the panic may have interrupted a write and the
+    /// storage can't be trusted to not panic again.
+    ///
     /// # Errors
     /// This function will ignore IO errors!
     /// If you need to handle them use [`finish`](Self::finish).
     fn drop(&mut self) {
         let handle = unsafe { ManuallyDrop::take(&mut self.handle) };
+        if !self.commit_on_drop || std::thread::panicking() {
+            return;
+        }
         _ = self.driver.drive_write(handle.finish());
     }
 }
 
+/// Which of the two handles used during [`Pkg::transform`] an operation comes from.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TransformSide {
+    Reader = 0,
+    Writer = 1,
+}
+
+/// Storage shared between the reader and the writer used during [`Pkg::transform`].
+///
+/// Both handles expect the storage to stay where they left it, so whenever the other side is
+/// used its position is saved and the position of the current side is restored.
+struct TransformShared<'a, S> {
+    driver: &'a mut SyncDriver<S>,
+    active: TransformSide,
+    positions: [u64; 2],
+}
+
+impl<S: Read + Seek> TransformShared<'_, S> {
+    fn activate(&mut self, side: TransformSide) -> std::io::Result<&mut SyncDriver<S>> {
+        if self.active != side {
+            let storage = self.driver.get_mut();
+            self.positions[self.active as usize] = storage.stream_position()?;
+            storage.seek(std::io::SeekFrom::Start(self.positions[side as usize]))?;
+            self.active = side;
+        }
+
+        Ok(self.driver)
+    }
+}
+
+struct TransformReader<'a, 'b, S> {
+    shared: &'b RefCell<TransformShared<'a, S>>,
+    handle: base::ReadHandle,
+}
+
+impl<S: Read + Seek> Read for TransformReader<'_, '_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut shared = self.shared.borrow_mut();
+        Ok(shared
+            .activate(TransformSide::Reader)?
+            .drive_read(self.handle.read(buf))
+            .flatten()?)
+    }
+}
+
+struct TransformWriter<'a, 'b, 'c, S> {
+    shared: &'b RefCell<TransformShared<'a, S>>,
+    handle: &'b mut base::WriteHandle<'c>,
+}
+
+impl<S: Read + Seek + Write> Write for TransformWriter<'_, '_, '_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut shared = self.shared.borrow_mut();
+        shared
+            .activate(TransformSide::Writer)?
+            .drive_write(self.handle.write(buf))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut shared = self.shared.borrow_mut();
+        let driver = shared.activate(TransformSide::Writer)?;
+        driver.drive_write(self.handle.flush()).flatten()?;
+        driver.get_mut().flush()
+    }
+}
+
 impl<S: Read + Seek + Write> Pkg<S> {
     /// Create a new archive in `storage`.
     ///
@@ -423,22 +2135,119 @@ impl<S: Read + Seek + Write> Pkg<S> {
     /// # Errors
     /// - [`CreateError::Io`] if an IO error occurs.
     pub fn create(storage: S) -> Result<Self, CreateError> {
+        Self::create_with_format(storage, Format::STANDARD)
+    }
+
+    /// Create a new archive in `storage` that uses the header and entry sizes of `format`.
+    ///
+    /// This is only useful for formats related to SIL archives, see [`Format`] for the
+    /// limitations. Such archives have to be parsed with the same [`ParseOptions::format`].
+    ///
+    /// # Errors
+    /// - [`CreateError::UnsupportedFormat`] if `format` is not
+    ///   [supported](Format::is_supported).
+    /// - [`CreateError::Io`] if an IO error occurs.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags, Format, ParseOptions};
+    /// let format = Format { header_size: 32, entry_size: 24 };
+    /// let mut pkg = Pkg::create_with_format(Cursor::new(vec![]), format)?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?.write_all(b"Hello!")?;
+    ///
+    /// let options = ParseOptions { format, ..Default::default() };
+    /// let pkg = Pkg::parse_with_options(Cursor::new(pkg.inner().get_ref()), options)?;
+    /// assert_eq!(pkg.format(), format);
+    /// assert!(pkg.contains("hello.txt"));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn create_with_format(storage: S, format: Format) -> Result<Self, CreateError> {
         let mut driver = SyncDriver::new(storage);
-        let state = driver.drive_write(PkgState::create()).flatten()?;
+        let state = driver.drive_write(PkgState::create(format)).flatten()?;
 
         Ok(Self { driver, state })
     }
 
+    /// Writes the header of the archive again from the parsed metadata.
+    ///
+    /// silpkg keeps the header up to date by itself, so this is only useful for repairing an
+    /// archive whose header was damaged by something else after it was parsed, for example by
+    /// writing to the storage directly.
+    pub fn rewrite_header(&mut self) -> std::io::Result<()> {
+        self.driver.drive_write(self.state.rewrite_header())
+    }
+
+    /// Checks whether the archive can be modified by writing its header again, which leaves the
+    /// contents of the storage unchanged.
+    ///
+    /// Storages that can't be written to, like [`File`]s opened without write access, otherwise
+    /// only fail once an operation already started and usually with an error that doesn't make
+    /// the cause obvious, for example `EBADF` on Unix. Calling this right after opening an
+    /// archive catches that before anything is left half done.
+    ///
+    /// # Errors
+    /// - [`ErrorKind::Unsupported`] if [`is_writable`](Self::is_writable) returns `false`.
+    /// - [`ErrorKind::PermissionDenied`] if the storage can't be written to, with the original
+    ///   error as the [`source`](std::error::Error::source) of its inner error. Other operations
+    ///   that modify the archive report this the same way.
+    /// - Any other IO error returned while writing to or flushing the storage.
+    ///
+    /// [`File`]: std::fs::File
+    /// [`ErrorKind::Unsupported`]: std::io::ErrorKind::Unsupported
+    /// [`ErrorKind::PermissionDenied`]: std::io::ErrorKind::PermissionDenied
+    pub fn check_writable(&mut self) -> std::io::Result<()> {
+        self.driver.check_writable()?;
+        self.rewrite_header()?;
+        self.driver.get_mut().flush().map_err(map_not_writable)
+    }
+
     /// Removes an entry from the archive.
     pub fn remove(&mut self, path: &str) -> Result<(), RemoveError> {
         self.driver.drive_write(self.state.remove(path)).flatten()
     }
 
+    /// Removes all entries for which `pred` returns `false`, like [`Vec::retain`].
+    ///
+    /// This is equivalent to calling [`remove`](Self::remove) for every such entry, except that
+    /// the entry list is rewritten in a single pass. [`MTIME_ENTRY_PATH`] is never passed to
+    /// `pred` and is always kept. Like with [`remove`](Self::remove) the space occupied by
+    /// the removed entries is only reclaimed once the archive is [`repack`](Self::repack)ed.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("data.txt".into(), Flags::default())?.write_all(b"data")?;
+    /// pkg.insert("data.txt.bak".into(), Flags::default())?.write_all(b"old data")?;
+    ///
+    /// pkg.retain(|path, _| !path.ends_with(".bak"))?;
+    /// assert!(pkg.paths().eq(["data.txt"]));
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn retain(
+        &mut self,
+        mut pred: impl FnMut(&str, &EntryInfo) -> bool,
+    ) -> std::io::Result<()> {
+        let slots = self
+            .entries()
+            .filter(|(path, info)| !pred(path, info))
+            .map(|(_, info)| info.index)
+            .collect::<Vec<_>>();
+
+        self.driver.drive_write(self.state.remove_slots(&slots))
+    }
+
     /// Renames `src` to `dst`.
     ///
+    /// Paths are compared case sensitively, so an entry can be renamed to a path that only
+    /// differs in case.
+    ///
     /// # Errors
     /// - [`RenameError::NotFound`] if `src` does not exist.
     /// - [`RenameError::AlreadyExists`] if `dst` already exists.
+    /// - [`RenameError::InvalidPath`] if `dst` is not a valid path, see [`insert`](Self::insert).
     /// - [`RenameError::Io`] if an IO error occurs.
     pub fn rename(&mut self, src: &str, dst: String) -> Result<(), RenameError> {
         self.driver
@@ -446,17 +2255,86 @@ impl<S: Read + Seek + Write> Pkg<S> {
             .flatten()
     }
 
-    /// Replaces `dst` with `src` if it doesn't exist or renames `src` to `dst` otherwise.
+    /// Renames multiple entries at once.
+    ///
+    /// All the renames are validated before any of them are performed, so if this function fails
+    /// because of [`NotFound`](RenameError::NotFound),
+    /// [`AlreadyExists`](RenameError::AlreadyExists) or
+    /// [`InvalidPath`](RenameError::InvalidPath) the archive is left unchanged.
+    /// Destinations are checked against the archive with all the renames applied, so a
+    /// destination may be the source of another rename in the same batch.
+    ///
+    /// # Errors
+    /// - [`RenameError::NotFound`] if any source does not exist or appears more than once.
+    /// - [`RenameError::AlreadyExists`] if any destination already exists and is not renamed
+    ///   in this batch, or appears more than once.
+    /// - [`RenameError::InvalidPath`] if any destination is not a valid path.
+    /// - [`RenameError::Io`] if an IO error occurs.
+    pub fn rename_many(&mut self, pairs: &[(&str, String)]) -> Result<(), RenameError> {
+        self.driver
+            .drive_write(self.state.rename_many(pairs))
+            .flatten()
+    }
+
+    /// Swaps the paths of `a` and `b`.
+    ///
+    /// This is equivalent to renaming `a` to `b` and `b` to `a` at the same time, which isn't
+    /// possible with [`rename`](Self::rename) since the destination would already exist. Both
+    /// paths are already stored in the archive so only the two entry records are rewritten.
+    ///
+    /// # Errors
+    /// - [`RenameError::NotFound`] if either `a` or `b` does not exist.
+    /// - [`RenameError::Io`] if an IO error occurs.
+    pub fn swap(&mut self, a: &str, b: &str) -> Result<(), RenameError> {
+        self.driver.drive_write(self.state.swap(a, b)).flatten()
+    }
+
+    /// Replaces `dst` with `src` if it doesn't exist or renames `src` to `dst` otherwise.
+    ///
+    /// Unlike [`rename`](Self::rename) this function will not fail if `dst` already exists.
+    pub fn replace(&mut self, src: &str, dst: String) -> Result<(), ReplaceError> {
+        self.driver
+            .drive_write(self.state.replace(src, dst))
+            .flatten()
+    }
+
+    /// Packs the paths of all entries at the start of the path region, reclaiming the
+    /// [`stale_path_bytes`](Self::stale_path_bytes) for future paths.
     ///
-    /// Unlike [`rename`](Self::rename) this function will not fail if `dst` already exists.
-    pub fn replace(&mut self, src: &str, dst: String) -> Result<(), ReplaceError> {
+    /// Unlike [`repack`](Self::repack) this doesn't move any data or shrink the archive, it only
+    /// rewrites the entry list and the path region. This does nothing if there are no stale
+    /// paths.
+    pub fn compact_paths(&mut self) -> std::io::Result<()> {
+        self.driver.drive_write(self.state.compact_paths())
+    }
+
+    /// Inserts a new entry `dst` with the same contents as `src`.
+    ///
+    /// The stored data and flags are copied as is, so compressed entries stay compressed without
+    /// being decompressed and compressed again. The two entries are independent afterwards,
+    /// modifying or removing one of them doesn't affect the other.
+    ///
+    /// # Errors
+    /// - [`CopyError::NotFound`] if `src` does not exist.
+    /// - [`CopyError::AlreadyExists`] if `dst` already exists.
+    /// - [`CopyError::InvalidPath`] if `dst` contains characters that are not allowed.
+    /// - [`CopyError::Io`] if an IO error occurs.
+    pub fn copy_entry(&mut self, src: &str, dst: String) -> Result<(), CopyError> {
         self.driver
-            .drive_write(self.state.replace(src, dst))
+            .drive_write(self.state.copy_entry(src, dst))
             .flatten()
     }
 
     /// Inserts a new entry into the archive.
     ///
+    /// Paths can contain any ASCII characters except for null bytes and must not be empty.
+    /// Separators aren't interpreted in any way, but other tools expect `/` to be used.
+    ///
+    /// # Errors
+    /// - [`InsertError::AlreadyExists`] if an entry with the same path already exists.
+    /// - [`InsertError::InvalidPath`] if the path contains characters that are not allowed.
+    /// - [`InsertError::Io`] if an IO error occurs.
+    ///
     /// # Examples
     /// ```
     /// # use std::io::{Read, Write};
@@ -479,18 +2357,437 @@ impl<S: Read + Seek + Write> Pkg<S> {
     ///
     /// # Ok::<(), Box<dyn std::error::Error>>(())
     /// ```
-    pub fn insert(&mut self, path: String, flags: Flags) -> Result<EntryWriter<S>, InsertError> {
+    pub fn insert(
+        &mut self,
+        path: String,
+        flags: Flags,
+    ) -> Result<EntryWriter<'_, S>, InsertError> {
         let handle = self
             .driver
             .drive_write(self.state.insert(path, flags))
             .flatten()?;
 
-        Ok(EntryWriter {
+        Ok(EntryWriter::new(&mut self.driver, handle))
+    }
+
+    /// Inserts a new entry with the data read from `source` until EOF.
+    ///
+    /// This is like [`insert`](Self::insert) followed by [`std::io::copy`] and
+    /// [`EntryWriter::finish`], except that if reading or writing fails the entry is rolled back
+    /// using [`EntryWriter::rollback_on_drop`] instead of being left in the archive with
+    /// truncated data.
+    ///
+    /// # Errors
+    /// - [`AlreadyExists`] if an entry with the same path already exists.
+    /// - Any error returned while reading from `source` or writing to the archive.
+    ///
+    /// [`AlreadyExists`]: std::io::ErrorKind::AlreadyExists
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::Cursor;
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// let info = pkg.insert_from("hello.txt".into(), Flags::default(), &mut &b"Hello!"[..])?;
+    /// assert_eq!(info.uncompressed_size, 6);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn insert_from(
+        &mut self,
+        path: String,
+        flags: Flags,
+        source: &mut impl Read,
+    ) -> std::io::Result<EntryInfo> {
+        let mut writer = self.insert(path.clone(), flags)?.rollback_on_drop();
+        std::io::copy(source, &mut writer)?;
+        writer.finish()?;
+
+        Ok(self.metadata(&path).unwrap())
+    }
+
+    /// Stores modification times for entries in the archive.
+    ///
+    /// The times are written into a separate [`MTIME_ENTRY_PATH`] entry which replaces any
+    /// previously stored times, archives that never call this function are not affected.
+    /// Times before the unix epoch cannot be stored and are silently skipped.
+    ///
+    /// # Errors
+    /// - [`InsertError::Io`] if an IO error occurs.
+    pub fn set_modification_times<'a>(
+        &mut self,
+        times: impl IntoIterator<Item = (&'a str, SystemTime)>,
+    ) -> Result<(), InsertError> {
+        let data = encode_mtimes(times);
+
+        if self.contains(MTIME_ENTRY_PATH) {
+            self.remove(MTIME_ENTRY_PATH).map_err(|err| match err {
+                errors::RemoveError::NotFound => unreachable!(),
+                errors::RemoveError::Io(err) => InsertError::Io(err),
+            })?;
+        }
+
+        let mut writer = self.insert(MTIME_ENTRY_PATH.to_string(), Flags::default())?;
+        writer.write_all(&data)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Inserts a new uncompressed entry with a known size into the archive.
+    ///
+    /// Unlike [`insert`](Self::insert) this function will try to place the entry's data into the
+    /// smallest free space left behind by previously removed or replaced entries that can fit
+    /// `size` bytes, only appending it to the end of the archive if there is none. This slows down
+    /// the growth of archives that are frequently modified without having to [`repack`] them.
+    ///
+    /// The returned writer will refuse to write more than `size` bytes, writing less is allowed.
+    ///
+    /// [`repack`]: Self::repack
+    pub fn insert_sized(
+        &mut self,
+        path: String,
+        size: u32,
+    ) -> Result<EntryWriter<'_, S>, InsertError> {
+        let handle = self
+            .driver
+            .drive_write(self.state.insert_sized(path, size.into()))
+            .flatten()?;
+
+        Ok(EntryWriter::new(&mut self.driver, handle))
+    }
+
+    /// Inserts a new entry whose data is written starting at `data_offset` in the archive.
+    ///
+    /// This is meant for controlling the layout of an archive, see
+    /// [`PkgState::insert_at`](crate::base::PkgState::insert_at) for details. Writing compressed
+    /// data that doesn't fit before the data of the next entry fails with
+    /// [`InsertError::InvalidOffset`](errors::InsertError::InvalidOffset) and the entry is not
+    /// added.
+    ///
+    /// # Errors
+    /// - [`InsertError::InvalidOffset`](errors::InsertError::InvalidOffset) if `data_offset` is
+    ///   outside of the data region or inside the data of another entry.
+    /// - Any of the errors returned by [`insert`](Self::insert).
+    #[cfg(feature = "unstable_base")]
+    #[doc(cfg(feature = "unstable_base"))]
+    pub fn insert_at(
+        &mut self,
+        path: String,
+        flags: Flags,
+        data_offset: u64,
+    ) -> Result<EntryWriter<'_, S>, InsertError> {
+        let handle = self
+            .driver
+            .drive_write(self.state.insert_at(path, flags, data_offset))
+            .flatten()?;
+
+        Ok(EntryWriter::new(&mut self.driver, handle))
+    }
+
+    /// Opens the existing entry `path` for writing completely new contents.
+    ///
+    /// The new data is written elsewhere in the archive and the entry keeps its old contents
+    /// until the returned writer is flushed, finished or dropped, so a crash in the middle of the
+    /// write never leaves behind a partially overwritten entry. Like any other writer it commits
+    /// whatever was written so far when it is dropped, use
+    /// [`EntryWriter::rollback_on_drop`] to keep the old contents unless
+    /// [`finish`](EntryWriter::finish) is reached. Like [`replace`](Self::replace) this leaves
+    /// behind the space previously occupied by the old data.
+    ///
+    /// # Errors
+    /// - [`ReplaceError::NotFound`] if the entry does not exist.
+    /// - [`ReplaceError::Io`] if an IO error occurs.
+    pub fn overwrite(
+        &mut self,
+        path: String,
+        flags: Flags,
+    ) -> Result<EntryWriter<'_, S>, ReplaceError> {
+        let handle = self
+            .driver
+            .drive_write(self.state.overwrite(path, flags))
+            .flatten()?;
+
+        Ok(EntryWriter::new(&mut self.driver, handle))
+    }
+
+    /// Opens the existing entry `path` for appending more data to its end.
+    ///
+    /// Only an uncompressed entry whose data is the last thing in the archive can be appended to,
+    /// since extending it in place would otherwise overwrite other data. A freshly inserted entry
+    /// satisfies this until something else is inserted after it, after a [`repack`] the entry
+    /// with the highest data offset does.
+    ///
+    /// The entry's size is updated when the returned writer is flushed or dropped.
+    ///
+    /// # Errors
+    /// - [`AppendError::NotFound`] if the entry does not exist.
+    /// - [`AppendError::NotAppendable`] if the entry is compressed or its data is not at the end
+    ///   of the archive.
+    /// - [`AppendError::Io`] if an IO error occurs.
+    ///
+    /// [`repack`]: Self::repack
+    pub fn append(&mut self, path: String) -> Result<EntryWriter<'_, S>, AppendError> {
+        let handle = self.driver.drive_write(self.state.append(path)).flatten()?;
+
+        Ok(EntryWriter::new(&mut self.driver, handle))
+    }
+
+    /// Streams the contents of `src` into a new entry `dst` through `f`.
+    ///
+    /// `f` receives a reader over the uncompressed contents of `src` and a writer into `dst`,
+    /// which will be written with the specified `flags`. Unlike reading `src` into memory and
+    /// then [`insert`](Self::insert)ing it, the data is never buffered as a whole.
+    ///
+    /// `src` is left as is, to transform an entry in place [`replace`](Self::replace) it with
+    /// `dst` afterwards. If `f` fails `dst` is removed again.
+    ///
+    /// # Errors
+    /// Returns an error if `src` can't be opened, `dst` already exists, `f` fails or an IO error
+    /// occurs.
+    ///
+    /// # Examples
+    /// ```
+    /// # use std::io::{Cursor, Read, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("lower".into(), Flags::default())?.write_all(b"shout")?;
+    ///
+    /// pkg.transform("lower", "upper".into(), Flags::default(), |reader, writer| {
+    ///     let mut data = String::new();
+    ///     reader.read_to_string(&mut data)?;
+    ///     writer.write_all(data.to_uppercase().as_bytes())
+    /// })?;
+    ///
+    /// let mut buf = String::new();
+    /// pkg.open("upper")?.read_to_string(&mut buf)?;
+    /// assert_eq!(buf, "SHOUT");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn transform(
+        &mut self,
+        src: &str,
+        dst: String,
+        flags: Flags,
+        f: impl FnOnce(&mut dyn Read, &mut dyn Write) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        self.transform_internal(src, Some(dst), flags, f)
+    }
+
+    // Transforms `src` into `dst`, or into itself if `dst` is `None`, in which case `src` is only
+    // updated if `f` succeeds.
+    fn transform_internal(
+        &mut self,
+        src: &str,
+        dst: Option<String>,
+        flags: Flags,
+        f: impl FnOnce(&mut dyn Read, &mut dyn Write) -> std::io::Result<()>,
+    ) -> std::io::Result<()> {
+        // Make sure the source can be opened before creating the destination, the handle itself
+        // can't be used since inserting may move existing data around.
+        self.driver
+            .drive_read(base::open(&self.state, src))
+            .flatten()?;
+
+        let dst_path = dst.clone();
+        let mut writer = match dst {
+            Some(dst) => self
+                .driver
+                .drive_write(self.state.insert(dst, flags))
+                .flatten()?,
+            None => self
+                .driver
+                .drive_write(self.state.overwrite(src.to_string(), flags))
+                .flatten()?,
+        };
+        let writer_position = self.driver.get_mut().stream_position()?;
+        let reader = self
+            .driver
+            .drive_read(base::open(writer.state(), src))
+            .flatten()?;
+        let reader_position = self.driver.get_mut().stream_position()?;
+
+        let shared = RefCell::new(TransformShared {
             driver: &mut self.driver,
-            handle: ManuallyDrop::new(handle),
+            active: TransformSide::Reader,
+            positions: [reader_position, writer_position],
+        });
+
+        let result = f(
+            &mut TransformReader {
+                shared: &shared,
+                handle: reader,
+            },
+            &mut TransformWriter {
+                shared: &shared,
+                handle: &mut writer,
+            },
+        );
+
+        let mut shared = shared.into_inner();
+        let result = match dst_path {
+            // Leaving the handle unflushed discards the new data
+            None if result.is_err() => result,
+            _ => result.and(
+                shared
+                    .activate(TransformSide::Writer)
+                    .and_then(|driver| Ok(driver.drive_write(writer.finish()).flatten()?)),
+            ),
+        };
+
+        if let (Err(_), Some(dst_path)) = (&result, &dst_path) {
+            _ = self.remove(dst_path);
+        }
+
+        result?;
+        self.driver.get_mut().flush()
+    }
+
+    /// Stores the entry at `path` uncompressed.
+    ///
+    /// Entries that are already uncompressed are left untouched. Like
+    /// [`compress_entries`](Self::compress_entries) this leaves behind the space previously
+    /// occupied by the compressed data, so you will probably want to [`repack`] afterwards.
+    ///
+    /// # Errors
+    /// Returns an error if the entry does not exist or an IO error occurs.
+    ///
+    /// [`repack`]: Self::repack
+    pub fn decompress(&mut self, path: &str) -> std::io::Result<()> {
+        let compressed = self
+            .is_compressed(path)
+            .ok_or_else(|| std::io::Error::from(OpenError::NotFound))?;
+        if !compressed {
+            return Ok(());
+        }
+
+        self.transform_internal(path, None, Flags::default(), |reader, writer| {
+            std::io::copy(reader, writer).map(|_| ())
+        })
+    }
+
+    /// Compresses the entries at `paths` using up to `jobs` threads.
+    ///
+    /// Entries are read and committed back into the archive by the calling thread while the
+    /// worker threads only do the compression, so at most about `2 * jobs` entries are held in
+    /// memory at once. Entries are committed in the order they appear in `paths` regardless of
+    /// which finishes compressing first, which keeps the resulting layout deterministic.
+    /// `on_done` is called with each path after it has been committed.
+    ///
+    /// Like [`insert`](Self::insert) this leaves behind the space previously occupied by the
+    /// uncompressed data, so you will probably want to [`repack`] afterwards.
+    ///
+    /// # Errors
+    /// Returns an error if any of the entries does not exist or an IO error occurs, entries
+    /// committed before the error remain compressed.
+    ///
+    /// [`repack`]: Self::repack
+    pub fn compress_entries(
+        &mut self,
+        paths: &[&str],
+        level: Compression,
+        jobs: NonZeroUsize,
+        mut on_done: impl FnMut(&str),
+    ) -> std::io::Result<()> {
+        type Compressed = std::io::Result<(Vec<u8>, u64)>;
+
+        let jobs = jobs.get();
+        let format = self.state.deflate_format();
+        let (job_tx, job_rx) = mpsc::sync_channel::<(usize, Vec<u8>)>(jobs);
+        let job_rx = Mutex::new(job_rx);
+        let (done_tx, done_rx) = mpsc::channel::<(usize, Compressed)>();
+
+        std::thread::scope(|scope| {
+            for _ in 0..jobs {
+                let job_rx = &job_rx;
+                let done_tx = done_tx.clone();
+                scope.spawn(move || loop {
+                    let Ok((index, data)) = job_rx.lock().unwrap().recv() else {
+                        break;
+                    };
+
+                    // The zlib encoder only adds the wrapper if the compressor is configured to
+                    let mut encoder = flate2::write::ZlibEncoder::new_with_compress(
+                        Vec::new(),
+                        flate2::Compress::new(level, format.zlib_header()),
+                    );
+                    let result = encoder
+                        .write_all(&data)
+                        .and_then(|_| encoder.finish())
+                        .map(|compressed| (compressed, data.len() as u64));
+
+                    if done_tx.send((index, result)).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(done_tx);
+
+            // Moving the sender in here makes sure the workers stop even if we return early.
+            let job_tx = job_tx;
+            let mut pending = BTreeMap::new();
+            let mut in_flight = 0;
+            let mut next_commit = 0;
+            let mut commit_ready = |pkg: &mut Self,
+                                    pending: &mut BTreeMap<usize, Compressed>|
+             -> std::io::Result<()> {
+                while let Some(result) = pending.remove(&next_commit) {
+                    let (compressed, unpacked_size) = result?;
+                    let path = paths[next_commit];
+
+                    let handle = pkg
+                        .driver
+                        .drive_write(
+                            pkg.state
+                                .overwrite_precompressed(path.to_string(), unpacked_size),
+                        )
+                        .flatten()?;
+                    let mut writer = EntryWriter::new(&mut pkg.driver, handle);
+                    // Don't let a failed write replace the entry's data
+                    if let Err(err) = writer.write_all(&compressed) {
+                        writer.discard();
+                        return Err(err);
+                    }
+                    writer.finish()?;
+
+                    on_done(path);
+                    next_commit += 1;
+                }
+
+                Ok(())
+            };
+
+            for (index, path) in paths.iter().enumerate() {
+                let mut data = Vec::new();
+                self.open(path)?.read_to_end(&mut data)?;
+                job_tx.send((index, data)).map_err(std::io::Error::other)?;
+                in_flight += 1;
+
+                while in_flight >= 2 * jobs {
+                    let (index, result) = done_rx.recv().map_err(std::io::Error::other)?;
+                    in_flight -= 1;
+                    pending.insert(index, result);
+                    commit_ready(self, &mut pending)?;
+                }
+            }
+            drop(job_tx);
+
+            for (index, result) in done_rx.iter() {
+                pending.insert(index, result);
+                commit_ready(self, &mut pending)?;
+            }
+
+            Ok(())
         })
     }
 
+    /// Sets how much extra space is reserved when the path region has to grow.
+    ///
+    /// This setting is not stored in the archive and defaults to
+    /// [`PathRegionGrowth::default`] for every newly created or parsed [`Pkg`].
+    pub fn set_path_region_growth(&mut self, growth: PathRegionGrowth) {
+        self.state.set_path_region_growth(growth);
+    }
+
     /// Flushes the underlying writer
     pub fn flush(&mut self) -> std::io::Result<()> {
         self.driver.get_mut().flush()
@@ -506,8 +2803,382 @@ impl<S: Read + Seek + Write + Truncate> Pkg<S> {
     ///
     /// This function is pretty expensive and also makes proceeding [`insert`]s slower.
     ///
+    /// If writing fails partway through, the archive in the storage can still be parsed. At most
+    /// one entry is left with damaged data, namely the one whose data was being moved at the time.
+    ///
+    /// This is the only modification allowed on archives that had entries dropped by
+    /// [`ParseOptions::repair_truncated`], which can be modified normally once it succeeds.
+    ///
     /// [`insert`]: Pkg::insert
     pub fn repack(&mut self) -> Result<(), RepackError> {
-        self.driver.drive_truncate(self.state.repack()).flatten()
+        // Repacking is what gets rid of the records of dropped entries, so it's allowed for them
+        let dropped = self.driver.read_only == Some(ReadOnly::DroppedEntries);
+        if dropped {
+            self.driver.read_only = None;
+        }
+
+        let result = self.driver.drive_truncate(self.state.repack()).flatten();
+        if dropped && result.is_err() {
+            self.driver.read_only = Some(ReadOnly::DroppedEntries);
+        }
+        result
+    }
+
+    /// Brings the archive into a canonical form that only depends on the paths and data of its
+    /// entries, while also making it as small as [`repack`](Self::repack) would.
+    ///
+    /// Two archives that contain the same entries with the same stored data become byte-identical
+    /// after this, regardless of the order in which the entries were inserted or what was removed
+    /// from them. This makes it a good last step before distributing an archive.
+    ///
+    /// # Notes
+    /// This is the most expensive operation on an archive, on top of a [`repack`] the whole data
+    /// region is copied twice and the storage temporarily grows to twice the size of the data.
+    /// Entries compressed with different settings store different data and therefore still
+    /// differ.
+    ///
+    /// [`repack`]: Self::repack
+    pub fn canonicalize(&mut self) -> Result<(), RepackError> {
+        self.driver
+            .drive_truncate(self.state.canonicalize())
+            .flatten()
+    }
+
+    /// Repacks the archive and then reorders its data so that entries in the same directory are
+    /// stored next to each other, which makes reading whole directories more sequential.
+    ///
+    /// Entries are grouped by the first `depth` components of their directory, so with a `depth`
+    /// of 1 everything under `a/` ends up together while `a/b/` and `a/c/` may still be
+    /// interleaved. A `depth` of [`usize::MAX`] groups every directory separately and a `depth`
+    /// of 0 is the same as a [`repack`](Self::repack). Groups are stored in path order, the
+    /// entries in each one keep the order their data was stored in.
+    ///
+    /// This is meant as a final step after writing an archive, entries inserted afterwards are
+    /// appended to the end as usual.
+    ///
+    /// # Notes
+    /// On top of a [`repack`](Self::repack) the whole data region is copied twice and the
+    /// storage temporarily grows to twice the size of the data. The entries stay intact if
+    /// writing fails after the repack is done.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// for path in ["a/1", "b/1", "a/2", "b/2"] {
+    ///     pkg.insert(path.into(), Flags::default())?.write_all(path.as_bytes())?;
+    /// }
+    ///
+    /// pkg.cluster_by_prefix(1)?;
+    /// let offset = |pkg: &Pkg<_>, path| pkg.metadata(path).unwrap().data_offset;
+    /// assert_eq!(offset(&pkg, "a/2"), offset(&pkg, "a/1") + 3);
+    /// assert_eq!(offset(&pkg, "b/1"), offset(&pkg, "a/2") + 3);
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn cluster_by_prefix(&mut self, depth: usize) -> Result<(), RepackError> {
+        self.driver
+            .drive_truncate(self.state.cluster_by_prefix(depth))
+            .flatten()
+    }
+
+    /// Reserves space for `extra_bytes` of upcoming entry data by extending the storage up front.
+    ///
+    /// This is purely a performance hint for storages like [`File`]s that may end up fragmented
+    /// when repeatedly extended by small writes. Entries inserted afterwards fill the reserved
+    /// space instead of being appended to the end of the storage, anything left unused stays in
+    /// the archive until a [`repack`](Self::repack) reclaims it.
+    ///
+    /// Storages whose [`Truncate`] implementation returns [`Unsupported`] can't preallocate, for
+    /// them this does nothing and returns `Ok(())`.
+    ///
+    /// [`File`]: std::fs::File
+    /// [`Unsupported`]: std::io::ErrorKind::Unsupported
+    pub fn preallocate(&mut self, extra_bytes: u64) -> std::io::Result<()> {
+        match self
+            .driver
+            .drive_truncate(self.state.preallocate(extra_bytes))
+        {
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Attempts to salvage an archive that [`repack`](Self::repack) refuses to process because of
+    /// [`RepackError::OverlappingEntries`].
+    ///
+    /// Whenever the data of two entries overlaps, the one with the later data offset gets its
+    /// data copied to the end of the archive, after which the archive is repacked. Entries whose
+    /// data extends past the end of the storage are relocated the same way with the missing part
+    /// filled with zeroes.
+    ///
+    /// Returns the paths of all relocated entries, their data is whatever was stored in the
+    /// overlapping region and may well be partially corrupt.
+    pub fn repair(&mut self) -> Result<Vec<String>, RepackError> {
+        self.driver.drive_truncate(self.state.repair()).flatten()
+    }
+
+    /// Removes empty entry slots from the end of the entry list and shrinks the archive accordingly.
+    ///
+    /// Unlike [`repack`](Self::repack) this function does not touch the path region or
+    /// defragment the data region, it only moves both of them up to reclaim the space freed from
+    /// the entry list. Empty slots between entries are left alone and entries keep their indices,
+    /// which makes this the cheapest way to reclaim space after removing the most recently
+    /// inserted entries.
+    pub fn shrink_entry_table(&mut self) -> std::io::Result<()> {
+        self.driver.drive_truncate(self.state.shrink_entry_table())
+    }
+
+    /// Removes an entry from the archive and immediately reclaims the space used by its data.
+    ///
+    /// Unlike [`remove`](Self::remove) this moves the data of every entry stored after the
+    /// removed one down over the freed space and shrinks the archive, which is a lot cheaper than
+    /// a full [`repack`](Self::repack) when a single large entry is removed. Space shared with
+    /// the data of other entries is left alone.
+    pub fn remove_and_reclaim(&mut self, path: &str) -> Result<(), RemoveError> {
+        self.driver
+            .drive_truncate(self.state.remove_and_reclaim(path))
+            .flatten()
     }
 } // Read + Seek + Write + Truncate
+
+impl Pkg<std::io::Cursor<Arc<[u8]>>> {
+    /// Parses a read-only [`Pkg`] from a shared in-memory buffer.
+    ///
+    /// The resulting [`Pkg`] is [`Send`], [`Sync`] and [`Clone`], and clones keep referencing
+    /// the same buffer, so one loaded archive can be read from many threads at once by giving
+    /// each of them its own clone. Uncompressed entries can also be borrowed directly from the
+    /// buffer with [`entry_slice`](Self::entry_slice).
+    ///
+    /// # Example
+    /// ```
+    /// # use std::{io::{Cursor, Read}, sync::Arc};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// # let mut storage = Cursor::new(vec![]);
+    /// # Pkg::create(&mut storage)?.insert_from("hello.txt".into(), Flags::default(), &mut &b"Hello!"[..])?;
+    /// let data: Arc<[u8]> = storage.into_inner().into();
+    /// let pkg = Pkg::parse_shared(data)?;
+    ///
+    /// std::thread::scope(|scope| {
+    ///     for _ in 0..4 {
+    ///         let mut pkg = pkg.clone();
+    ///         scope.spawn(move || {
+    ///             let mut buf = String::new();
+    ///             pkg.open("hello.txt").unwrap().read_to_string(&mut buf).unwrap();
+    ///             assert_eq!(buf, "Hello!");
+    ///         });
+    ///     }
+    /// });
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn parse_shared(data: Arc<[u8]>) -> Result<Self, ParseError> {
+        Self::parse(std::io::Cursor::new(data))
+    }
+}
+
+impl<S: Read + Seek> Pkg<OffsetStorage<S>> {
+    /// Parses a [`Pkg`] that starts at `base_offset` in the supplied reader.
+    ///
+    /// This is useful for archives embedded inside of other files, like ones appended to an
+    /// executable. Modifying the archive never touches anything before `base_offset`.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Read, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// # let mut archive = Cursor::new(vec![]);
+    /// # Pkg::create(&mut archive)?.insert_from("hello.txt".into(), Flags::default(), &mut &b"Hello!"[..])?;
+    /// let mut storage = Cursor::new(b"some executable".to_vec());
+    /// storage.get_mut().extend_from_slice(archive.get_ref());
+    ///
+    /// let mut pkg = Pkg::parse_at(storage, 15)?;
+    /// let mut buf = String::new();
+    /// pkg.open("hello.txt")?.read_to_string(&mut buf)?;
+    /// assert_eq!(buf, "Hello!");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn parse_at(storage: S, base_offset: u64) -> Result<Self, ParseError> {
+        Self::parse(OffsetStorage::new(storage, base_offset))
+    }
+}
+
+impl<T: AsRef<[u8]>> Pkg<std::io::Cursor<T>> {
+    /// Returns the data of the uncompressed entry at `path` borrowed directly from the in-memory
+    /// storage.
+    ///
+    /// Unlike [`open`](Self::open) this doesn't copy the data through any intermediate buffers,
+    /// which makes it the fastest way to read uncompressed entries of archives that are already
+    /// in memory.
+    ///
+    /// # Errors
+    /// - [`NotFound`] if the entry does not exist.
+    /// - [`Unsupported`] if the entry is compressed, compressed entries have to be read with
+    ///   [`open`](Self::open) instead.
+    /// - [`UnexpectedEof`] if the entry's data extends past the end of the storage.
+    ///
+    /// [`NotFound`]: std::io::ErrorKind::NotFound
+    /// [`Unsupported`]: std::io::ErrorKind::Unsupported
+    /// [`UnexpectedEof`]: std::io::ErrorKind::UnexpectedEof
+    ///
+    /// # Example
+    /// ```
+    /// # use std::io::{Cursor, Write};
+    /// # use silpkg::{sync::Pkg, Flags};
+    /// let mut pkg = Pkg::create(Cursor::new(vec![]))?;
+    /// pkg.insert("hello.txt".into(), Flags::default())?
+    ///     .write_all(b"Hello, world!")?;
+    ///
+    /// assert_eq!(pkg.entry_slice("hello.txt")?, b"Hello, world!");
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn entry_slice(&self, path: &str) -> std::io::Result<&[u8]> {
+        let info = self.metadata(path).ok_or(OpenError::NotFound)?;
+        if info.compressed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Compressed entries cannot be borrowed",
+            ));
+        } else if info.raw_flags != 0 {
+            return Err(OpenError::UnrecognisedEntryFlags(info.raw_flags).into());
+        }
+
+        let offset = info.data_offset as usize;
+        let size = info.compressed_size as usize;
+        self.driver
+            .storage
+            .get_ref()
+            .as_ref()
+            .get(offset..offset + size)
+            .ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+/// A read-only memory mapping of the data of a single uncompressed entry.
+///
+/// Created by [`Pkg::mmap_entry`], dereferences to the entry's data.
+#[cfg(all(feature = "mmap", unix))]
+#[doc(cfg(all(feature = "mmap", unix)))]
+pub struct EntryMmap<'a> {
+    // Start of the mapping, which begins at the page boundary before the entry's data.
+    map: *mut libc::c_void,
+    map_len: usize,
+    data_start: usize,
+    // The mapping must not outlive the archive being modified through the Pkg.
+    _pkg: std::marker::PhantomData<&'a Pkg<std::fs::File>>,
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl std::ops::Deref for EntryMmap<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.map_len == 0 {
+            return &[];
+        }
+
+        unsafe {
+            core::slice::from_raw_parts(
+                self.map.cast::<u8>().add(self.data_start),
+                self.map_len - self.data_start,
+            )
+        }
+    }
+}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Drop for EntryMmap<'_> {
+    fn drop(&mut self) {
+        if self.map_len != 0 {
+            unsafe { libc::munmap(self.map, self.map_len) };
+        }
+    }
+}
+
+// The mapping is read-only and not tied to any thread.
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Send for EntryMmap<'_> {}
+#[cfg(all(feature = "mmap", unix))]
+unsafe impl Sync for EntryMmap<'_> {}
+
+#[cfg(all(feature = "mmap", unix))]
+impl Pkg<std::fs::File> {
+    /// Memory maps the data of the uncompressed entry at `path` for zero-copy access.
+    ///
+    /// Only the pages containing the entry's data are mapped. The returned mapping borrows the
+    /// [`Pkg`] so the archive can't be modified through it while the mapping exists.
+    ///
+    /// # Safety
+    /// The caller must ensure that the archive file is neither written to nor truncated while the
+    /// returned mapping exists. This includes writes through the [`File`](std::fs::File)
+    /// returned by [`inner`](Self::inner) as well as by other handles to the file and other
+    /// processes. Writing to the file changes the data behind the returned slice and truncating
+    /// it makes accessing the slice crash the program.
+    ///
+    /// # Errors
+    /// - [`NotFound`] if the entry does not exist.
+    /// - [`Unsupported`] if the entry is compressed, compressed entries have to be read with
+    ///   [`open`](Self::open) instead.
+    /// - [`UnexpectedEof`] if the entry's data extends past the end of the file.
+    /// - Any error returned by `mmap`.
+    ///
+    /// [`NotFound`]: std::io::ErrorKind::NotFound
+    /// [`Unsupported`]: std::io::ErrorKind::Unsupported
+    /// [`UnexpectedEof`]: std::io::ErrorKind::UnexpectedEof
+    #[doc(cfg(all(feature = "mmap", unix)))]
+    pub unsafe fn mmap_entry(&self, path: &str) -> std::io::Result<EntryMmap<'_>> {
+        use std::os::fd::AsRawFd;
+
+        let info = self.metadata(path).ok_or(OpenError::NotFound)?;
+        if info.compressed {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Compressed entries cannot be memory mapped",
+            ));
+        } else if info.raw_flags != 0 {
+            return Err(OpenError::UnrecognisedEntryFlags(info.raw_flags).into());
+        }
+
+        let offset = u64::from(info.data_offset);
+        let size = u64::from(info.compressed_size);
+        if offset + size > self.driver.storage.metadata()?.len() {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        // Empty mappings are not allowed
+        if size == 0 {
+            return Ok(EntryMmap {
+                map: core::ptr::null_mut(),
+                map_len: 0,
+                data_start: 0,
+                _pkg: std::marker::PhantomData,
+            });
+        }
+
+        // The offset passed to mmap has to be aligned to the page size
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let map_offset = offset - offset % page_size;
+        let data_start = (offset - map_offset) as usize;
+        let map_len = data_start + size as usize;
+
+        let map = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                self.driver.storage.as_raw_fd(),
+                map_offset as libc::off_t,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(EntryMmap {
+            map,
+            map_len,
+            data_start,
+            _pkg: std::marker::PhantomData,
+        })
+    }
+}
@@ -1,8 +1,6 @@
 #[cfg(feature = "std")]
 use std::io::{Read, Seek, Write};
 
-use crate::base::BUFFER_SIZE;
-
 macro_rules! define_read_le_methods {
     { $($name:ident -> $ret:ty;)* } => {
         $(fn $name(&mut self) -> ::std::io::Result<$ret> {
@@ -136,39 +134,25 @@ pub trait ReadSeekWriteExt: Read + Write + Seek {
         input_offset: u64,
         count: u64,
         output_offset: u64,
+        buffer_size: usize,
     ) -> std::io::Result<()> {
-        if input_offset == output_offset {
-        } else if (input_offset..count).contains(&output_offset) {
-            assert!(output_offset != input_offset);
-
-            let buf_size = (output_offset - input_offset).min(BUFFER_SIZE);
-
-            // TODO: Optimise this case
-            if count / buf_size > 64 {
-                let mut buf = vec![0; count as usize];
-
-                self.seek(std::io::SeekFrom::Start(input_offset))?;
-                self.read_exact(&mut buf)?;
-
-                self.seek(std::io::SeekFrom::Start(output_offset))?;
-                self.write_all(&buf)?;
-
-                return Ok(());
-            }
-
-            let mut buf = vec![0; buf_size as usize];
+        if input_offset == output_offset || count == 0 {
+        } else if (input_offset..input_offset + count).contains(&output_offset) {
+            // The destination overlaps the end of the source so we have to copy backwards to avoid
+            // overwriting source data before it's read.
+            let mut buf = vec![0; count.min(buffer_size as u64) as usize];
             let mut remaining = count;
             while remaining > 0 {
                 let chunk_size = (buf.len()).min(remaining as usize);
+                remaining -= chunk_size as u64;
                 self.seek(std::io::SeekFrom::Start(input_offset + remaining))?;
                 // TODO: read instead of read_exact
                 self.read_exact(&mut buf[..chunk_size])?;
                 self.seek(std::io::SeekFrom::Start(output_offset + remaining))?;
                 self.write_all(&buf[..chunk_size])?;
-                remaining -= chunk_size as u64;
             }
         } else {
-            let mut buf = [0; BUFFER_SIZE as usize];
+            let mut buf = vec![0; count.min(buffer_size as u64) as usize];
             let mut remaining = count;
             while remaining > 0 {
                 let chunk_size = (buf.len()).min(remaining as usize);
@@ -0,0 +1,88 @@
+use std::{
+    cell::Cell,
+    io::{Cursor, Read, Seek, SeekFrom, Write},
+    rc::Rc,
+};
+
+use silpkg::Truncate;
+
+/// A storage that starts failing every write, seek and truncate after a set number of them
+/// succeeded, which simulates the process crashing in the middle of an operation.
+///
+/// Reads keep working so that the state left behind can be inspected afterwards.
+pub struct FaultyStorage {
+    inner: Cursor<Vec<u8>>,
+    trigger: FaultTrigger,
+}
+
+/// Controls when a [`FaultyStorage`] starts failing, this stays usable after the storage was
+/// moved into a [`Pkg`](silpkg::sync::Pkg).
+#[derive(Clone, Default)]
+pub struct FaultTrigger(Rc<Cell<Option<usize>>>);
+
+impl FaultTrigger {
+    /// Makes every write, seek and truncate after the next `operations` ones fail.
+    pub fn fail_after(&self, operations: usize) {
+        self.0.set(Some(operations));
+    }
+}
+
+impl FaultyStorage {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            inner: Cursor::new(data),
+            trigger: FaultTrigger::default(),
+        }
+    }
+
+    pub fn trigger(&self) -> FaultTrigger {
+        self.trigger.clone()
+    }
+
+    pub fn get_ref(&self) -> &[u8] {
+        self.inner.get_ref()
+    }
+
+    fn check(&mut self) -> std::io::Result<()> {
+        match self.trigger.0.get() {
+            Some(0) => Err(std::io::Error::other("injected fault")),
+            Some(remaining) => {
+                self.trigger.0.set(Some(remaining - 1));
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Read for FaultyStorage {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for FaultyStorage {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.check()?;
+        self.inner.seek(pos)
+    }
+}
+
+impl Write for FaultyStorage {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.check()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Truncate for FaultyStorage {
+    fn truncate(&mut self, len: u64) -> std::io::Result<()> {
+        self.check()?;
+        self.inner.get_mut().truncate(len as usize);
+        Ok(())
+    }
+}
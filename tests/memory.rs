@@ -0,0 +1,138 @@
+//! Checks that streaming entry data doesn't make allocations proportional to its size and that
+//! parsing doesn't allocate once per entry.
+//!
+//! This lives in its own test binary since it replaces the global allocator.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::{Cursor, Read, Write},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use rand::RngCore;
+use silpkg::{sync::Pkg, Compression, EntryCompression, Flags};
+
+struct TrackingAllocator;
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static LARGEST_ALLOCATION: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if TRACKING.load(Ordering::Relaxed) {
+            LARGEST_ALLOCATION.fetch_max(layout.size(), Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if TRACKING.load(Ordering::Relaxed) {
+            LARGEST_ALLOCATION.fetch_max(new_size, Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+// Returns the size of the largest allocation made by `f`.
+fn largest_allocation(f: impl FnOnce()) -> usize {
+    LARGEST_ALLOCATION.store(0, Ordering::Relaxed);
+    TRACKING.store(true, Ordering::Relaxed);
+    f();
+    TRACKING.store(false, Ordering::Relaxed);
+    LARGEST_ALLOCATION.load(Ordering::Relaxed)
+}
+
+// Returns the number of allocations made by `f` and the total number of bytes they requested.
+fn allocations(f: impl FnOnce()) -> (usize, usize) {
+    ALLOCATIONS.store(0, Ordering::Relaxed);
+    ALLOCATED_BYTES.store(0, Ordering::Relaxed);
+    TRACKING.store(true, Ordering::Relaxed);
+    f();
+    TRACKING.store(false, Ordering::Relaxed);
+    (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+const DATA_SIZE: usize = 8 * 1024 * 1024;
+const LIMIT: usize = 64 * 1024;
+
+#[test]
+fn deflate_streaming_allocations() {
+    // Reserving the storage up front keeps its growth out of the measurements
+    let mut pkg = Pkg::create(Cursor::new(Vec::with_capacity(4 * DATA_SIZE))).unwrap();
+    let flags = Flags {
+        compression: EntryCompression::Deflate(Compression::new(6)),
+    };
+
+    // Random data expands when compressed, zeroes expand a lot when decompressed
+    let mut random = vec![0; DATA_SIZE];
+    rand::thread_rng().fill_bytes(&mut random);
+    let zeroes = vec![0; DATA_SIZE];
+
+    for (name, data) in [("random", &random), ("zeroes", &zeroes)] {
+        let mut writer = pkg.insert(name.to_string(), flags.clone()).unwrap();
+        let largest = largest_allocation(|| writer.write_all(data).unwrap());
+        assert!(largest <= LIMIT, "writing {name} allocated {largest} bytes");
+        writer.finish().unwrap();
+    }
+
+    let mut buffer = vec![0; DATA_SIZE];
+    for (name, data) in [("random", &random), ("zeroes", &zeroes)] {
+        let mut reader = pkg.open(name).unwrap();
+        let largest = largest_allocation(|| reader.read_exact(&mut buffer).unwrap());
+        assert!(largest <= LIMIT, "reading {name} allocated {largest} bytes");
+        assert!(buffer == *data);
+    }
+}
+
+const PARSE_ENTRY_COUNT: usize = 100_000;
+
+#[test]
+fn parse_allocations() {
+    let paths = (0..PARSE_ENTRY_COUNT)
+        .map(|i| format!("data/entry-{i}.txt"))
+        .collect::<Vec<_>>();
+    let path_region_size = paths.iter().map(|p| p.len() + 1).sum::<usize>();
+    let data_offset = 16 + 20 * PARSE_ENTRY_COUNT + path_region_size;
+
+    let mut archive = b"PKG\n".to_vec();
+    archive.extend(16u16.to_be_bytes());
+    archive.extend(20u16.to_be_bytes());
+    archive.extend((PARSE_ENTRY_COUNT as u32).to_be_bytes());
+    archive.extend((path_region_size as u32).to_be_bytes());
+    let mut path_offset = 0;
+    for (i, path) in paths.iter().enumerate() {
+        archive.extend(0u32.to_be_bytes());
+        archive.extend((path_offset as u32).to_be_bytes());
+        archive.extend(((data_offset + i) as u32).to_be_bytes());
+        archive.extend(1u32.to_be_bytes());
+        archive.extend(1u32.to_be_bytes());
+        path_offset += path.len() + 1;
+    }
+    for path in &paths {
+        archive.extend(path.as_bytes());
+        archive.push(0);
+    }
+    archive.resize(data_offset + PARSE_ENTRY_COUNT, b'x');
+
+    let mut pkg = None;
+    let (count, bytes) = allocations(|| pkg = Some(Pkg::parse(Cursor::new(&archive)).unwrap()));
+    println!("parsing {PARSE_ENTRY_COUNT} entries: {bytes} bytes in {count} allocations");
+    assert!(count < 100, "parsing allocated {count} times");
+    assert_eq!(pkg.unwrap().paths().count(), PARSE_ENTRY_COUNT);
+}
@@ -1,13 +1,14 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     io::{Read, Seek, Write},
 };
 
-use silpkg::{sync::Pkg, Compression, Flags};
+use silpkg::{sync::Pkg, Compression, Flags, ParseOptions};
 
 use test_log::test;
 
 mod data;
+mod fault;
 
 fn add<'a, S: Read + Seek + Write>(
     pkg: &mut Pkg<S>,
@@ -42,7 +43,7 @@ fn extract<'a, S: Read + Seek + Write>(
 }
 
 fn list<'a, S: Read + Seek + Write>(pkg: &Pkg<S>, paths: impl Iterator<Item = &'a str>) {
-    let mut list_paths = pkg.paths().map(String::as_str).collect::<HashSet<_>>();
+    let mut list_paths = pkg.paths().collect::<HashSet<_>>();
 
     for name in paths {
         assert!(list_paths.remove(name));
@@ -51,6 +52,34 @@ fn list<'a, S: Read + Seek + Write>(pkg: &Pkg<S>, paths: impl Iterator<Item = &'
     assert_eq!(list_paths.len(), 0);
 }
 
+/// Builds an archive from raw `(path, data_offset, data_size)` entries without any data.
+fn raw_archive(entries: &[(&str, u32, u32)]) -> Vec<u8> {
+    let path_region_size = entries.iter().map(|(p, _, _)| p.len() + 1).sum::<usize>();
+
+    let mut out = b"PKG\n".to_vec();
+    out.extend(16u16.to_be_bytes());
+    out.extend(20u16.to_be_bytes());
+    out.extend((entries.len() as u32).to_be_bytes());
+    out.extend((path_region_size as u32).to_be_bytes());
+
+    let mut path_offset = 0;
+    for (path, data_offset, data_size) in entries {
+        out.extend(0u32.to_be_bytes());
+        out.extend((path_offset as u32).to_be_bytes());
+        out.extend(data_offset.to_be_bytes());
+        out.extend(data_size.to_be_bytes());
+        out.extend(data_size.to_be_bytes());
+        path_offset += path.len() + 1;
+    }
+
+    for (path, _, _) in entries {
+        out.extend(path.as_bytes());
+        out.push(0);
+    }
+
+    out
+}
+
 // TODO: Tests for remove
 // fn remove<'a, S: Read + Seek + Write>(pkg: &mut Pkg<S>, names: impl Iterator<Item = &'a str>) {
 //     for name in names {
@@ -120,6 +149,83 @@ fn add_parse_extract() {
     list(&pkg, data.iter().map(|(n, _)| n.as_str()));
 }
 
+#[test]
+fn add_past_preallocated_entries() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    // One more entry than is preallocated so that the entry list has to grow while the file
+    // still ends right after the little data that was written
+    let data: Vec<(String, Vec<u8>)> = (0..65)
+        .map(|i| (format!("entry{i}"), vec![i as u8]))
+        .collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+}
+
+#[test]
+fn add_past_preallocated_entries_empty() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    // Entries without any data don't extend the file past the path region
+    let mut data: Vec<(String, Vec<u8>)> = (0..65).map(|i| (format!("empty{i}"), vec![])).collect();
+    data.push(("last".to_string(), b"data after the path region".to_vec()));
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn add_long_paths_past_preallocated_entries() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    // Long paths make the path region larger than a single copy buffer, so moving it to make
+    // space for more entries copies between overlapping ranges
+    let data: Vec<(String, Vec<u8>)> = (0..70)
+        .map(|i| {
+            (
+                format!("{}/{i}", "long".repeat(50)),
+                format!("{i}").into_bytes(),
+            )
+        })
+        .collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
 #[test]
 fn add_repack_parse_extract_extract() {
     let mut storage = std::io::Cursor::new(vec![]);
@@ -302,3 +408,4155 @@ fn everything() {
         data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
     );
 }
+
+#[test]
+fn add_clone_extract() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        silpkg::Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::best()),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let mut clone = pkg.clone();
+    drop(pkg);
+
+    extract(
+        &mut clone,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+
+    list(&clone, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn add_find() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    assert!(pkg.entries().all(|(_, info)| !info.compressed));
+
+    let mut found =
+        pkg.find(|path, info| path.starts_with("random/") && info.uncompressed_size > 1024);
+    found.sort();
+
+    let mut expected = data
+        .iter()
+        .filter(|(n, d)| n.starts_with("random/") && d.len() > 1024)
+        .map(|(n, _)| n.as_str())
+        .collect::<Vec<_>>();
+    expected.sort();
+
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn set_parse_modification_times() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    assert!(pkg.modification_times().unwrap().is_empty());
+
+    let times = data
+        .iter()
+        .enumerate()
+        .map(|(i, (n, _))| {
+            (
+                n.as_str(),
+                std::time::SystemTime::UNIX_EPOCH
+                    + std::time::Duration::new(1_700_000_000 + i as u64, i as u32),
+            )
+        })
+        .collect::<Vec<_>>();
+    pkg.set_modification_times(times.iter().copied()).unwrap();
+    // Setting them again should replace the previous entry
+    pkg.set_modification_times(times.iter().copied()).unwrap();
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    let read = pkg.modification_times().unwrap();
+    assert_eq!(read.len(), times.len());
+    for (path, time) in times {
+        assert_eq!(read[path], time);
+    }
+
+    // The sidecar entry should not show up as one of the archive's files
+    assert!(pkg.contains(silpkg::sync::MTIME_ENTRY_PATH));
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+}
+
+#[test]
+fn modification_times_hidden_from_listings() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        [("hello.txt".to_string(), b"hello".as_slice())].into_iter(),
+    );
+    pkg.set_modification_times([("hello.txt", std::time::SystemTime::UNIX_EPOCH)])
+        .unwrap();
+
+    assert!(pkg.children("").eq(["hello.txt"]));
+    assert_eq!(pkg.dir_sizes()[""], (5, 5));
+    let tree = pkg.tree();
+    assert_eq!(tree.files.len(), 1);
+    assert!(tree.dirs.is_empty());
+    drop(pkg);
+
+    // The modification times are written last, so cutting off the end only truncates them
+    let mut storage = storage.into_inner();
+    storage.pop();
+    let mut pkg = Pkg::parse(std::io::Cursor::new(storage)).unwrap();
+    assert!(pkg.check_truncation().unwrap().is_empty());
+}
+
+#[test]
+fn add_rename_many_parse_extract() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let mut data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    // Invalid batches must not modify the archive
+    assert!(matches!(
+        pkg.rename_many(&[("hello.txt", "a".to_string()), ("missing", "b".to_string())]),
+        Err(silpkg::sync::RenameError::NotFound)
+    ));
+    assert!(matches!(
+        pkg.rename_many(&[("hello.txt", "a".to_string()), ("fox.txt", "a".to_string())]),
+        Err(silpkg::sync::RenameError::AlreadyExists)
+    ));
+    assert!(matches!(
+        pkg.rename_many(&[("hello.txt", "fox.txt".to_string())]),
+        Err(silpkg::sync::RenameError::AlreadyExists)
+    ));
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+
+    // Swap the first two entries and rename all the others
+    let mut pairs = vec![
+        (data[0].0.clone(), data[1].0.clone()),
+        (data[1].0.clone(), data[0].0.clone()),
+    ];
+    pairs.extend(
+        data[2..]
+            .iter()
+            .map(|(n, _)| (n.clone(), format!("{n}-renamed"))),
+    );
+    pkg.rename_many(
+        &pairs
+            .iter()
+            .map(|(src, dst)| (src.as_str(), dst.clone()))
+            .collect::<Vec<_>>(),
+    )
+    .unwrap();
+
+    let (first, second) = (data[0].0.clone(), data[1].0.clone());
+    data[0].0 = second;
+    data[1].0 = first;
+    for (name, _) in data[2..].iter_mut() {
+        *name = format!("{name}-renamed")
+    }
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn add_swap_parse_extract() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let mut data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        silpkg::Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::best()),
+        },
+        data.iter()
+            .take(3)
+            .map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter()
+            .skip(3)
+            .map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    assert!(matches!(
+        pkg.swap("hello.txt", "missing"),
+        Err(silpkg::sync::RenameError::NotFound)
+    ));
+
+    // Swap a compressed entry with an uncompressed one
+    let (a, b) = (data[0].0.clone(), data[5].0.clone());
+    let (a_index, b_index) = (
+        pkg.metadata(&a).unwrap().index,
+        pkg.metadata(&b).unwrap().index,
+    );
+    pkg.swap(&a, &b).unwrap();
+    // The entries keep their data and exchange their paths
+    assert_eq!(pkg.metadata(&a).unwrap().index, b_index);
+    assert_eq!(pkg.metadata(&b).unwrap().index, a_index);
+    let (first, second) = (data[0].1.clone(), data[5].1.clone());
+    data[0].1 = second;
+    data[5].1 = first;
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    drop(pkg);
+
+    let options = ParseOptions {
+        verify_hashes: true,
+        ..Default::default()
+    };
+    let mut pkg = Pkg::parse_with_options(&mut storage, options).unwrap();
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn add_remove_open_index() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let removed = pkg.metadata("hello.txt").unwrap().index;
+    pkg.remove("hello.txt").unwrap();
+    assert!(matches!(
+        pkg.open_index(removed),
+        Err(silpkg::sync::OpenError::NotFound)
+    ));
+    assert!(matches!(
+        pkg.open_index(usize::MAX),
+        Err(silpkg::sync::OpenError::NotFound)
+    ));
+
+    for (name, expected) in data.iter().skip(1) {
+        let index = pkg.metadata(name).unwrap().index;
+        let mut out = vec![];
+        pkg.open_index(index)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(&out, expected);
+    }
+}
+
+#[test]
+fn add_remove_insert_sized_parse_extract() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let mut data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let removed = data.remove(data.len() / 2);
+    pkg.remove(&removed.0).unwrap();
+    pkg.flush().unwrap();
+    drop(pkg);
+    let len_before = storage.get_ref().len();
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    let new = (
+        "reused.bin".to_string(),
+        removed.1[..removed.1.len() / 2].to_vec(),
+    );
+    pkg.insert_sized(new.0.clone(), new.1.len() as u32)
+        .unwrap()
+        .write_all(&new.1)
+        .unwrap();
+    data.push(new);
+
+    // Writing past the declared size must fail instead of clobbering other entries
+    let mut writer = pkg.insert_sized("too-big.bin".to_string(), 4).unwrap();
+    assert!(writer.write_all(b"12345").is_err());
+    drop(writer);
+    data.push(("too-big.bin".to_string(), b"1234".to_vec()));
+
+    pkg.flush().unwrap();
+    drop(pkg);
+    assert_eq!(storage.get_ref().len(), len_before);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn add_remove_free_regions_parse_repack() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    assert!(pkg.free_regions().is_empty());
+
+    // Removing the last entry does not leave a gap between entries
+    let (last, _) = data.last().unwrap();
+    pkg.remove(last).unwrap();
+    assert!(pkg.free_regions().is_empty());
+
+    let (first, first_data) = &data[0];
+    let (middle, middle_data) = &data[data.len() / 2];
+    pkg.remove(first).unwrap();
+    pkg.remove(middle).unwrap();
+    let regions = pkg.free_regions().to_vec();
+    assert_eq!(regions.len(), 2);
+    assert_eq!(regions[0].1, first_data.len() as u64);
+    assert_eq!(regions[1].1, middle_data.len() as u64);
+
+    pkg.flush().unwrap();
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    assert_eq!(pkg.free_regions(), regions);
+
+    pkg.repack().unwrap();
+    assert!(pkg.free_regions().is_empty());
+}
+
+#[test]
+fn free_regions_match_reparse() {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0x5EED);
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let mut contents = std::collections::BTreeMap::<String, Vec<u8>>::new();
+
+    for step in 0..400 {
+        let existing = contents.keys().nth(rng.gen_range(0..contents.len().max(1)));
+        let existing = existing.cloned().filter(|_| rng.gen_bool(0.9));
+        // Long paths make the path region grow and move data out of the way
+        let new_path = match rng.gen_bool(0.05) {
+            true => format!("{}/{step}", "long".repeat(100)),
+            false => format!("entry{step}"),
+        };
+        let data = vec![step as u8; rng.gen_range(0..300)];
+
+        match (rng.gen_range(0..8), existing) {
+            (0, _) | (1..=6, None) => {
+                let mut writer = pkg.insert(new_path.clone(), Flags::default()).unwrap();
+                writer.write_all(&data).unwrap();
+                writer.finish().unwrap();
+                contents.insert(new_path, data);
+            }
+            (1, _) => {
+                let mut writer = pkg
+                    .insert_sized(new_path.clone(), data.len() as u32)
+                    .unwrap();
+                writer.write_all(&data).unwrap();
+                writer.finish().unwrap();
+                contents.insert(new_path, data);
+            }
+            (2, Some(path)) => {
+                pkg.remove(&path).unwrap();
+                contents.remove(&path);
+            }
+            (3, Some(path)) => {
+                pkg.remove_and_reclaim(&path).unwrap();
+                contents.remove(&path);
+            }
+            (4, Some(path)) => {
+                let dst = match contents.keys().nth(rng.gen_range(0..contents.len())) {
+                    Some(dst) if *dst != path => dst.clone(),
+                    _ => new_path,
+                };
+                pkg.replace(&path, dst.clone()).unwrap();
+                let data = contents.remove(&path).unwrap();
+                contents.insert(dst, data);
+            }
+            (5, Some(path)) => {
+                let mut writer = pkg.overwrite(path.clone(), Flags::default()).unwrap();
+                writer.write_all(&data).unwrap();
+                writer.finish().unwrap();
+                contents.insert(path, data);
+            }
+            (6, Some(path)) => {
+                pkg.copy_entry(&path, new_path.clone()).unwrap();
+                contents.insert(new_path, contents[&path].clone());
+            }
+            (_, _) => pkg.shrink_entry_table().unwrap(),
+        }
+
+        let mut reparsed = Pkg::parse(std::io::Cursor::new(pkg.inner().get_ref().clone())).unwrap();
+        assert_eq!(
+            pkg.free_regions(),
+            reparsed.free_regions(),
+            "after step {step}"
+        );
+        assert_eq!(
+            pkg.has_trailing_garbage().unwrap(),
+            reparsed.has_trailing_garbage().unwrap(),
+            "after step {step}"
+        );
+    }
+
+    extract(
+        &mut pkg,
+        contents.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, contents.keys().map(String::as_str));
+}
+
+#[test]
+fn add_remove_shrink_entry_table_parse_extract() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let mut data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    // Entries are inserted into the first free slot so these occupy the last slots
+    for (name, _) in data.drain(data.len() - 4..) {
+        pkg.remove(&name).unwrap();
+    }
+    pkg.flush().unwrap();
+    let len_before = pkg.inner().get_ref().len();
+
+    pkg.shrink_entry_table().unwrap();
+    pkg.flush().unwrap();
+    drop(pkg);
+    assert!(storage.get_ref().len() < len_before);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+
+    // The archive should still be writable after shrinking
+    add(
+        &mut pkg,
+        Flags::default(),
+        std::iter::once(("new.txt".to_string(), b"new".as_slice())),
+    );
+    data.push(("new.txt".to_string(), b"new".to_vec()));
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+}
+
+#[test]
+fn add_compressed_open_raw_lenient() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        silpkg::Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::best()),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    for (name, expected) in data.iter() {
+        let info = pkg.metadata(name).unwrap();
+        assert!(info.compressed);
+        let mut raw = vec![];
+        pkg.open_raw_lenient(name)
+            .unwrap()
+            .read_to_end(&mut raw)
+            .unwrap();
+        assert_eq!(raw.len() as u32, info.compressed_size);
+
+        let mut decompressed = vec![];
+        flate2::read::ZlibDecoder::new(raw.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(&decompressed, expected);
+    }
+}
+
+#[cfg(feature = "io_stats")]
+#[test]
+fn add_repack_io_stats() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let stats = pkg.io_stats().clone();
+    assert_eq!(stats.truncates, 0);
+    assert!(stats.bytes_written >= data.iter().map(|(_, d)| d.len() as u64).sum::<u64>());
+
+    pkg.reset_io_stats();
+    pkg.repack().unwrap();
+    assert_eq!(pkg.io_stats().truncates, 1);
+}
+
+#[test]
+fn add_many_parse_extract() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    // Enough entries for the path region to grow well past a single copy buffer
+    let data: Vec<(String, Vec<u8>)> = (0..2000)
+        .map(|i| {
+            (
+                format!("many/entry-number-{i}.txt"),
+                format!("{i}").into_bytes(),
+            )
+        })
+        .collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn repack_overlapping_near_u32_max() {
+    // 0xFFFFFF00 + 0x200 overflows a u32 and would wrap around to 0x100
+    let storage = raw_archive(&[("a", 0xFFFF_FF00, 0x200), ("b", 0xFFFF_FF80, 0x10)]);
+    let mut pkg = Pkg::parse(std::io::Cursor::new(storage)).unwrap();
+
+    assert!(matches!(
+        pkg.repack(),
+        Err(silpkg::sync::RepackError::OverlappingEntries)
+    ));
+
+    // Entries are sorted before the overlap is found, lookups must still find them afterwards
+    let storage = raw_archive(&[("b", 0xFFFF_FF80, 0x10), ("a", 0xFFFF_FF00, 0x200)]);
+    let mut pkg = Pkg::parse(std::io::Cursor::new(storage)).unwrap();
+
+    assert!(matches!(
+        pkg.repack(),
+        Err(silpkg::sync::RepackError::OverlappingEntries)
+    ));
+    assert_eq!(pkg.metadata("a").unwrap().data_offset, 0xFFFF_FF00);
+    assert_eq!(pkg.metadata("b").unwrap().data_offset, 0xFFFF_FF80);
+}
+
+#[test]
+fn parse_verify_hashes() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let options = ParseOptions {
+        verify_hashes: true,
+        ..Default::default()
+    };
+    Pkg::parse_with_options(&mut storage, options.clone()).unwrap();
+
+    // raw_archive stores zero as every hash
+    let storage = raw_archive(&[("a", 100, 0)]);
+    Pkg::parse(std::io::Cursor::new(&storage)).unwrap();
+    assert!(matches!(
+        Pkg::parse_with_options(std::io::Cursor::new(&storage), options),
+        Err(silpkg::sync::ParseError::HashMismatch { path, stored: 0, .. }) if path == "a"
+    ));
+}
+
+#[test]
+fn add_compress_entries_parallel_repack() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let paths = data.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+    let mut done = vec![];
+    pkg.compress_entries(
+        &paths,
+        Compression::new(6),
+        std::num::NonZeroUsize::new(3).unwrap(),
+        |path| done.push(path.to_string()),
+    )
+    .unwrap();
+    assert_eq!(done, paths);
+
+    pkg.repack().unwrap();
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    for (name, data) in data.iter() {
+        let info = pkg.metadata(name).unwrap();
+        assert!(info.compressed);
+        assert_eq!(info.raw_flags, 1 << 24);
+        assert_eq!(info.uncompressed_size as usize, data.len());
+    }
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn deflate_checked_levels() {
+    for level in 0..=9 {
+        assert!(matches!(
+            silpkg::EntryCompression::deflate_checked(level),
+            Ok(silpkg::EntryCompression::Deflate(compression)) if compression.level() == level
+        ));
+    }
+
+    assert!(matches!(
+        silpkg::EntryCompression::deflate_checked(10),
+        Err(silpkg::errors::InvalidLevel(10))
+    ));
+}
+
+#[test]
+fn add_entry_info_layout() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    drop(pkg);
+
+    let pkg = Pkg::parse(std::io::Cursor::new(storage.get_ref())).unwrap();
+    for (name, data) in data::BASIC_EXAMPLE_FILES {
+        let info = pkg.metadata(name).unwrap();
+        let offset = info.data_offset as usize;
+
+        assert_eq!(info.raw_flags, 0);
+        assert_eq!(
+            &storage.get_ref()[offset..offset + info.compressed_size as usize],
+            *data
+        );
+    }
+}
+
+#[test]
+fn parse_preserve_unknown_flags_repack() {
+    let mut storage = raw_archive(&[("a", 60, 5), ("b", 65, 5)]);
+    storage.extend(b"helloworld");
+    // Set an unknown flag bit on the first entry
+    storage[16 + 4] = 0x02;
+
+    assert!(matches!(
+        Pkg::parse(std::io::Cursor::new(storage.clone())),
+        Err(silpkg::sync::ParseError::UnrecognisedEntryFlags(
+            0x0200_0000
+        ))
+    ));
+
+    let options = ParseOptions {
+        preserve_unknown_flags: true,
+        ..Default::default()
+    };
+    let mut storage = std::io::Cursor::new(storage);
+    let mut pkg = Pkg::parse_with_options(&mut storage, options.clone()).unwrap();
+
+    assert_eq!(pkg.metadata("a").unwrap().raw_flags, 0x0200_0000);
+    assert!(matches!(
+        pkg.open("a"),
+        Err(silpkg::sync::OpenError::UnrecognisedEntryFlags(0x0200_0000))
+    ));
+    extract(&mut pkg, [("b", b"world".as_slice())].into_iter());
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        [("c".to_string(), b"new".as_slice())].into_iter(),
+    );
+    pkg.copy_entry("a", "a copy".to_string()).unwrap();
+    pkg.repack().unwrap();
+
+    let mut pkg = Pkg::parse_with_options(&mut storage, options).unwrap();
+    assert_eq!(pkg.metadata("a").unwrap().raw_flags, 0x0200_0000);
+    assert_eq!(pkg.metadata("a copy").unwrap().raw_flags, 0x0200_0000);
+    let mut raw = vec![];
+    pkg.open_raw_lenient("a")
+        .unwrap()
+        .read_to_end(&mut raw)
+        .unwrap();
+    assert_eq!(raw, b"hello");
+    extract(
+        &mut pkg,
+        [("b", b"world".as_slice()), ("c", b"new".as_slice())].into_iter(),
+    );
+}
+
+#[cfg(feature = "content_hash")]
+#[test]
+fn content_hash_ignores_layout() {
+    let data = data::BASIC_EXAMPLE_FILES
+        .iter()
+        .map(|(n, d)| (n.to_string(), *d))
+        .collect::<Vec<_>>();
+
+    let mut first = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(&mut first, Flags::default(), data.iter().cloned());
+
+    // Same contents inserted in reverse, compressed and then repacked
+    let mut second = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut second,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(9)),
+        },
+        data.iter().rev().cloned(),
+    );
+    second.repack().unwrap();
+
+    let hash = first.content_hash().unwrap();
+    assert_eq!(hash, second.content_hash().unwrap());
+
+    second.remove("hello.txt").unwrap();
+    assert_ne!(hash, second.content_hash().unwrap());
+}
+
+#[test]
+fn add_transform_recompress() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let flags = Flags {
+        compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+    };
+    for (name, _) in data.iter() {
+        let tmp = format!("{name}.tmp");
+        pkg.transform(name, tmp.clone(), flags.clone(), |reader, writer| {
+            std::io::copy(reader, writer).map(|_| ())
+        })
+        .unwrap();
+        pkg.replace(&tmp, name.clone()).unwrap();
+    }
+
+    assert!(pkg
+        .transform("hello.txt", "failed".into(), Flags::default(), |_, _| {
+            Err(std::io::Error::other("failed"))
+        })
+        .is_err());
+    assert!(!pkg.contains("failed"));
+    assert!(pkg
+        .transform("missing", "failed".into(), Flags::default(), |_, _| Ok(()))
+        .is_err());
+    assert!(!pkg.contains("failed"));
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    assert!(pkg.entries().all(|(_, info)| info.compressed));
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn add_remove_entry_slots() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let removed = pkg.metadata("fox.txt").unwrap().index;
+    pkg.remove("fox.txt").unwrap();
+
+    let slots = pkg.entry_slots().collect::<Vec<_>>();
+    assert!(slots.iter().enumerate().all(|(i, (index, _))| i == *index));
+    assert!(slots[removed].1.is_none());
+    assert_eq!(
+        slots.iter().filter(|(_, info)| info.is_some()).count(),
+        data::BASIC_EXAMPLE_FILES.len() - 1
+    );
+    for (index, info) in slots.iter() {
+        if let Some(info) = info {
+            assert_eq!(info.index, *index);
+        }
+    }
+}
+
+#[test]
+fn compressed_handles_error_kinds() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let flags = Flags {
+        compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+    };
+
+    let mut writer = pkg.insert("hello.txt".into(), flags).unwrap();
+    writer.write_all(b"hello").unwrap();
+    assert_eq!(
+        writer.read(&mut [0; 4]).unwrap_err().kind(),
+        std::io::ErrorKind::Unsupported
+    );
+    assert_eq!(
+        writer.seek(std::io::SeekFrom::Start(0)).unwrap_err().kind(),
+        std::io::ErrorKind::NotSeekable
+    );
+    writer.finish().unwrap();
+
+    let mut reader = pkg.open("hello.txt").unwrap();
+    assert_eq!(
+        reader.seek(std::io::SeekFrom::Start(0)).unwrap_err().kind(),
+        std::io::ErrorKind::NotSeekable
+    );
+}
+
+#[test]
+fn seek_out_of_bounds() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        [
+            ("first".to_string(), &b"0123456789"[..]),
+            ("second".to_string(), &b"abcdefghij"[..]),
+        ]
+        .into_iter(),
+    );
+
+    let mut reader = pkg.open("first").unwrap();
+    reader.seek(std::io::SeekFrom::Start(4)).unwrap();
+    for pos in [
+        std::io::SeekFrom::Current(-5),
+        std::io::SeekFrom::End(-11),
+        std::io::SeekFrom::Start(11),
+        std::io::SeekFrom::Start(u64::MAX),
+        std::io::SeekFrom::Current(7),
+        std::io::SeekFrom::End(1),
+    ] {
+        assert_eq!(
+            reader.seek(pos).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    // Failed seeks leave the position alone and nothing past the end is ever read
+    let mut out = vec![];
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(out, b"456789");
+    assert_eq!(reader.seek(std::io::SeekFrom::End(0)).unwrap(), 10);
+    assert_eq!(reader.read(&mut [0; 4]).unwrap(), 0);
+    drop(reader);
+
+    let mut writer = pkg.insert("third".into(), Flags::default()).unwrap();
+    writer.write_all(b"xyz").unwrap();
+    assert_eq!(
+        writer.seek(std::io::SeekFrom::Start(4)).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+    writer.write_all(b"w").unwrap();
+    writer.finish().unwrap();
+
+    extract(
+        &mut pkg,
+        [
+            ("first", &b"0123456789"[..]),
+            ("second", &b"abcdefghij"[..]),
+            ("third", &b"xyzw"[..]),
+        ]
+        .into_iter(),
+    );
+}
+
+#[test]
+fn add_many_path_region_growth() {
+    for growth in [
+        silpkg::PathRegionGrowth::Fixed(0),
+        silpkg::PathRegionGrowth::Proportional(50),
+    ] {
+        let mut storage = std::io::Cursor::new(vec![]);
+        let mut pkg = Pkg::create(&mut storage).unwrap();
+        pkg.set_path_region_growth(growth);
+
+        let data: Vec<(String, Vec<u8>)> = (0..500)
+            .map(|i| (format!("growth/{i}.txt"), format!("{i}").into_bytes()))
+            .collect();
+
+        add(
+            &mut pkg,
+            Flags::default(),
+            data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+        );
+
+        let mut pkg = Pkg::parse(&mut storage).unwrap();
+        extract(
+            &mut pkg,
+            data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+        );
+        list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+    }
+}
+
+#[test]
+fn add_find_duplicates() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let compressed = Flags {
+        compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+    };
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    add(
+        &mut pkg,
+        compressed,
+        [
+            (
+                "copies/hello.txt".to_string(),
+                data::BASIC_EXAMPLE_FILES[0].1,
+            ),
+            (
+                "copies/lorem.txt".to_string(),
+                data::BASIC_EXAMPLE_FILES[2].1,
+            ),
+        ]
+        .into_iter(),
+    );
+    add(
+        &mut pkg,
+        Flags::default(),
+        [
+            (
+                "copies/hello2.txt".to_string(),
+                data::BASIC_EXAMPLE_FILES[0].1,
+            ),
+            // Same size as hello.txt but different contents
+            (
+                "not-hello.txt".to_string(),
+                b"A very happy little fill".as_slice(),
+            ),
+        ]
+        .into_iter(),
+    );
+
+    assert_eq!(
+        pkg.find_duplicates().unwrap(),
+        vec![
+            vec!["copies/hello.txt", "copies/hello2.txt", "hello.txt"],
+            vec!["copies/lorem.txt", "lorem/lorem512.txt"],
+        ]
+    );
+}
+
+#[test]
+fn buffer_size_add_extract_repack() {
+    for buffer_size in [7, 256 * 1024] {
+        let mut storage = std::io::Cursor::new(vec![]);
+        let mut pkg = Pkg::create(&mut storage).unwrap();
+        pkg.set_buffer_size(std::num::NonZeroUsize::new(buffer_size).unwrap());
+
+        let data = data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d))
+            .collect::<Vec<_>>();
+        add(
+            &mut pkg,
+            Flags {
+                compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+            },
+            data.iter().map(|(n, d)| (format!("deflate/{n}"), *d)),
+        );
+        add(&mut pkg, Flags::default(), data.iter().cloned());
+        pkg.remove("hello.txt").unwrap();
+        pkg.repack().unwrap();
+
+        let expected = data
+            .iter()
+            .skip(1)
+            .cloned()
+            .chain(data.iter().map(|(n, d)| (format!("deflate/{n}"), *d)))
+            .collect::<Vec<_>>();
+
+        let mut pkg = Pkg::parse(&mut storage).unwrap();
+        pkg.set_buffer_size(std::num::NonZeroUsize::new(buffer_size).unwrap());
+        extract(&mut pkg, expected.iter().map(|(n, d)| (n.as_str(), *d)));
+        list(&pkg, expected.iter().map(|(n, _)| n.as_str()));
+    }
+}
+
+#[test]
+fn parse_extended_layout() {
+    // A header with 4 extra bytes and entries with 4 extra bytes each
+    let mut storage = b"PKG\n".to_vec();
+    storage.extend(20u16.to_be_bytes());
+    storage.extend(24u16.to_be_bytes());
+    storage.extend(2u32.to_be_bytes());
+    storage.extend(4u32.to_be_bytes());
+    storage.extend([0xAA; 4]);
+    for (path_offset, data_offset) in [(0u32, 72u32), (2, 77)] {
+        storage.extend(0u32.to_be_bytes());
+        storage.extend(path_offset.to_be_bytes());
+        storage.extend(data_offset.to_be_bytes());
+        storage.extend(5u32.to_be_bytes());
+        storage.extend(5u32.to_be_bytes());
+        storage.extend([0xBB; 4]);
+    }
+    storage.extend(b"a\0b\0");
+    storage.extend(b"helloworld");
+
+    assert!(matches!(
+        Pkg::parse(std::io::Cursor::new(storage.clone())),
+        Err(silpkg::sync::ParseError::MismatchedHeaderSize {
+            size: 20,
+            expected: 16
+        })
+    ));
+
+    let options = ParseOptions {
+        allow_extended_layout: true,
+        ..Default::default()
+    };
+    let mut pkg = Pkg::parse_with_options(std::io::Cursor::new(storage), options).unwrap();
+    list(&pkg, ["a", "b"].into_iter());
+    extract(
+        &mut pkg,
+        [("a", b"hello".as_slice()), ("b", b"world".as_slice())].into_iter(),
+    );
+
+    assert_eq!(
+        pkg.rename("a", "c".into()).unwrap_err().to_string(),
+        "Archives with an extended layout cannot be modified"
+    );
+    assert!(pkg.repack().is_err());
+    list(&pkg, ["a", "b"].into_iter());
+}
+
+#[test]
+fn custom_format() {
+    let format = silpkg::Format {
+        header_size: 24,
+        entry_size: 28,
+    };
+    let mut pkg = Pkg::create_with_format(std::io::Cursor::new(vec![]), format).unwrap();
+    assert_eq!(pkg.format(), format);
+
+    // Enough entries to grow the entry list
+    let data = data::combined_data().collect::<Vec<_>>();
+    let mut expected = data
+        .iter()
+        .flat_map(|(n, d)| (0..10).map(move |i| (format!("{i}/{n}"), d.as_slice())))
+        .collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags::default(),
+        expected.iter().map(|(n, d)| (n.clone(), *d)),
+    );
+    let (removed, _) = expected.remove(0);
+    pkg.remove(&removed).unwrap();
+    let (renamed, renamed_data) = expected.remove(0);
+    pkg.rename(&renamed, "renamed".into()).unwrap();
+    expected.push(("renamed".into(), renamed_data));
+    pkg.repack().unwrap();
+
+    let storage = pkg.inner().get_ref().clone();
+    assert!(matches!(
+        Pkg::parse(std::io::Cursor::new(storage.clone())),
+        Err(silpkg::sync::ParseError::MismatchedHeaderSize {
+            size: 24,
+            expected: 16
+        })
+    ));
+
+    // Only archives parsed with their own format can be modified
+    let options = ParseOptions {
+        allow_extended_layout: true,
+        ..Default::default()
+    };
+    let pkg = Pkg::parse_with_options(std::io::Cursor::new(storage.clone()), options).unwrap();
+    assert_eq!(pkg.format(), format);
+    assert!(!pkg.is_writable());
+
+    let options = ParseOptions {
+        format,
+        ..Default::default()
+    };
+    let mut pkg = Pkg::parse_with_options(std::io::Cursor::new(storage), options).unwrap();
+    assert!(pkg.is_writable());
+    extract(&mut pkg, expected.iter().map(|(n, d)| (n.as_str(), *d)));
+
+    assert!(matches!(
+        Pkg::create_with_format(
+            std::io::Cursor::new(vec![]),
+            silpkg::Format {
+                header_size: 16,
+                entry_size: 12,
+            }
+        ),
+        Err(silpkg::sync::CreateError::UnsupportedFormat(_))
+    ));
+}
+
+#[test]
+fn add_flush_metadata() {
+    struct CountFlushes {
+        inner: std::io::Cursor<Vec<u8>>,
+        flushes: usize,
+    }
+
+    impl Read for CountFlushes {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for CountFlushes {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl Write for CountFlushes {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    let mut pkg = Pkg::create(CountFlushes {
+        inner: std::io::Cursor::new(vec![]),
+        flushes: 0,
+    })
+    .unwrap();
+
+    let mut writer = pkg.insert("hello.txt".into(), Flags::default()).unwrap();
+    writer.write_all(b"hello").unwrap();
+    writer.flush_metadata().unwrap();
+    // Skip the metadata update done on drop to make sure flush_metadata already did it
+    std::mem::forget(writer);
+    assert_eq!(pkg.inner().flushes, 0);
+
+    let mut copy = Pkg::parse(std::io::Cursor::new(pkg.inner().inner.get_ref().clone())).unwrap();
+    extract(&mut copy, [("hello.txt", b"hello".as_slice())].into_iter());
+
+    let mut writer = pkg.insert("fox.txt".into(), Flags::default()).unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+    assert_eq!(pkg.inner().flushes, 1);
+}
+
+#[test]
+fn add_remove_minimum_size_repack() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    for (name, _) in data.iter().step_by(3) {
+        pkg.remove(name).unwrap();
+    }
+
+    let minimum_size = pkg.minimum_size();
+    assert!(minimum_size < pkg.inner().get_ref().len() as u64);
+
+    pkg.repack().unwrap();
+    assert_eq!(minimum_size, storage.get_ref().len() as u64);
+}
+
+#[test]
+fn add_compressed_stored_paths() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let (stored, compressed) = data::BASIC_EXAMPLE_FILES.split_at(2);
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        stored.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        compressed.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let mut stored_paths = pkg.stored_paths().collect::<Vec<_>>();
+    stored_paths.sort();
+    let mut expected = stored.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(stored_paths, expected);
+
+    let mut compressed_paths = pkg.compressed_paths().collect::<Vec<_>>();
+    compressed_paths.sort();
+    let mut expected = compressed.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(compressed_paths, expected);
+}
+
+#[test]
+fn add_decompress_repack() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    for (name, _) in data.iter() {
+        pkg.decompress(name).unwrap();
+    }
+    // Decompressing an uncompressed entry does nothing
+    pkg.decompress("hello.txt").unwrap();
+    assert!(pkg.decompress("missing").is_err());
+    pkg.repack().unwrap();
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    assert_eq!(pkg.compressed_paths().count(), 0);
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn add_interleaved_partial_reads() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter()
+            .step_by(2)
+            .map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        data.iter()
+            .skip(1)
+            .step_by(2)
+            .map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    // Partially read every entry, leaving the storage at some arbitrary position each time
+    for (name, expected) in data.iter() {
+        let compressed = pkg.metadata(name).unwrap().compressed;
+        let mut reader = pkg.open(name).unwrap();
+        let mut buf = vec![0; expected.len() / 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, &expected[..buf.len()]);
+        if !compressed {
+            reader.seek(std::io::SeekFrom::End(-1)).unwrap();
+        }
+        reader.close();
+    }
+
+    // Reading in reverse order after all that should still yield the correct data
+    for (name, expected) in data.iter().rev() {
+        let mut out = vec![];
+        pkg.open(name).unwrap().read_to_end(&mut out).unwrap();
+        assert_eq!(&out, expected);
+    }
+}
+
+#[test]
+fn append() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+
+    pkg.insert(
+        "compressed".into(),
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+    )
+    .unwrap()
+    .write_all(b"compressed")
+    .unwrap();
+    pkg.insert("first".into(), Flags::default())
+        .unwrap()
+        .write_all(b"first")
+        .unwrap();
+    pkg.insert("last".into(), Flags::default())
+        .unwrap()
+        .write_all(b"hello")
+        .unwrap();
+
+    assert!(matches!(
+        pkg.append("missing".into()),
+        Err(silpkg::sync::AppendError::NotFound)
+    ));
+    assert!(matches!(
+        pkg.append("first".into()),
+        Err(silpkg::sync::AppendError::NotAppendable)
+    ));
+    assert!(matches!(
+        pkg.append("compressed".into()),
+        Err(silpkg::sync::AppendError::NotAppendable)
+    ));
+
+    pkg.append("last".into())
+        .unwrap()
+        .write_all(b" world")
+        .unwrap();
+    pkg.append("last".into()).unwrap().write_all(b"!").unwrap();
+
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    let metadata = pkg.metadata("last").unwrap();
+    assert_eq!(metadata.compressed_size, 12);
+    assert_eq!(metadata.uncompressed_size, 12);
+    extract(
+        &mut pkg,
+        [
+            ("first", b"first".as_slice()),
+            ("last", b"hello world!".as_slice()),
+            ("compressed", b"compressed".as_slice()),
+        ]
+        .into_iter(),
+    );
+}
+
+#[test]
+fn repair_overlapping_entries() {
+    // The data region starts right after 3 entries and the "a\0b\0c\0" path region
+    let mut storage = raw_archive(&[("a", 82, 10), ("b", 87, 12), ("c", 97, 2)]);
+    storage.extend(b"0123456789ABCDE");
+    let mut storage = std::io::Cursor::new(storage);
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+
+    assert!(matches!(
+        pkg.repack(),
+        Err(silpkg::sync::RepackError::OverlappingEntries)
+    ));
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    let mut relocated = pkg.repair().unwrap();
+    relocated.sort();
+    assert_eq!(relocated, ["b", "c"]);
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    pkg.repack().unwrap();
+    extract(
+        &mut pkg,
+        [
+            ("a", b"0123456789".as_slice()),
+            ("b", b"56789ABCDE\0\0".as_slice()),
+            ("c", b"\0\0".as_slice()),
+        ]
+        .into_iter(),
+    );
+}
+
+#[test]
+fn preallocate_unsupported() {
+    // A storage that can't be resized, like a fixed size memory mapping
+    struct FixedSize(std::io::Cursor<Vec<u8>>);
+
+    impl Read for FixedSize {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Seek for FixedSize {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    impl Write for FixedSize {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl silpkg::Truncate for FixedSize {
+        fn truncate(&mut self, _len: u64) -> std::io::Result<()> {
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    let mut pkg = Pkg::create(FixedSize(std::io::Cursor::new(vec![]))).unwrap();
+    let len = pkg.inner().0.get_ref().len();
+    pkg.preallocate(1024).unwrap();
+    assert_eq!(pkg.inner().0.get_ref().len(), len);
+
+    let data = data::combined_data().collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+}
+
+#[test]
+fn add_preallocated() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+    let total = data.iter().map(|(_, d)| d.len() as u64).sum::<u64>();
+
+    pkg.preallocate(total).unwrap();
+    drop(pkg);
+    let preallocated_len = storage.get_ref().len();
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    pkg.preallocate(total).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    let minimum_size = pkg.minimum_size();
+    drop(pkg);
+
+    // The data should have been written into the space reserved by the second preallocate, which
+    // comes after the unused reservation of the first one
+    assert_eq!(
+        storage.get_ref().len() as u64,
+        preallocated_len as u64 + total
+    );
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    pkg.repack().unwrap();
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    drop(pkg);
+    assert_eq!(storage.get_ref().len() as u64, minimum_size);
+}
+
+#[test]
+fn overwrite_interrupted() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+
+    pkg.insert("a".into(), Flags::default())
+        .unwrap()
+        .write_all(b"old")
+        .unwrap();
+
+    assert!(matches!(
+        pkg.overwrite("missing".into(), Flags::default()),
+        Err(silpkg::sync::ReplaceError::NotFound)
+    ));
+
+    // Simulate a crash in the middle of writing the new data
+    let mut writer = pkg.overwrite("a".into(), Flags::default()).unwrap();
+    writer.write_all(b"new data").unwrap();
+    std::mem::forget(writer);
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    list(&pkg, ["a"].into_iter());
+    extract(&mut pkg, [("a", b"old".as_slice())].into_iter());
+
+    // Dropping the writer early only keeps the old contents when rolling back on drop
+    let mut writer = pkg
+        .overwrite("a".into(), Flags::default())
+        .unwrap()
+        .rollback_on_drop();
+    writer.write_all(b"new").unwrap();
+    drop(writer);
+    extract(&mut pkg, [("a", b"old".as_slice())].into_iter());
+
+    pkg.overwrite(
+        "a".into(),
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+    )
+    .unwrap()
+    .write_all(b"new data")
+    .unwrap();
+    pkg.decompress("a").unwrap();
+    list(&pkg, ["a"].into_iter());
+    extract(&mut pkg, [("a", b"new data".as_slice())].into_iter());
+    assert!(!pkg.metadata("a").unwrap().compressed);
+}
+
+#[test]
+fn tree() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d))
+            .chain([
+                ("/lorem//lorem0.txt".to_string(), b"".as_slice()),
+                ("deep/er/file".to_string(), b"".as_slice()),
+            ]),
+    );
+
+    let tree = pkg.tree();
+    assert_eq!(tree.file_count(), data::BASIC_EXAMPLE_FILES.len() + 2);
+    assert_eq!(
+        tree.files
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>(),
+        ["fox.txt", "hello.txt"]
+    );
+    assert_eq!(tree.dirs.keys().collect::<Vec<_>>(), ["deep", "lorem"]);
+
+    let lorem = tree.get("lorem/").unwrap();
+    assert!(lorem.dirs.is_empty());
+    assert_eq!(
+        lorem
+            .files
+            .iter()
+            .map(|f| (f.name.as_str(), f.path.as_str()))
+            .collect::<Vec<_>>(),
+        [
+            ("lorem0.txt", "/lorem//lorem0.txt"),
+            ("lorem1024.txt", "lorem/lorem1024.txt"),
+            ("lorem16384.txt", "lorem/lorem16384.txt"),
+            ("lorem4096.txt", "lorem/lorem4096.txt"),
+            ("lorem512.txt", "lorem/lorem512.txt"),
+        ]
+    );
+    assert_eq!(
+        lorem.files[4].info.uncompressed_size,
+        pkg.metadata("lorem/lorem512.txt")
+            .unwrap()
+            .uncompressed_size
+    );
+
+    assert_eq!(tree.get("deep/er").unwrap().files[0].path, "deep/er/file");
+    assert!(tree.get("deep/missing").is_none());
+}
+
+#[test]
+fn dir_sizes() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    assert_eq!(
+        pkg.dir_sizes().into_iter().collect::<Vec<_>>(),
+        [(String::new(), (0, 0))]
+    );
+
+    let (stored, compressed) = data::BASIC_EXAMPLE_FILES.split_at(3);
+    add(
+        &mut pkg,
+        Flags::default(),
+        stored.iter().map(|(n, d)| (n.to_string(), *d)).chain([
+            ("/lorem//lorem0.txt".to_string(), b"0".as_slice()),
+            ("empty/".to_string(), b"".as_slice()),
+        ]),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+        compressed.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let sum = |prefix: &str| {
+        pkg.entries()
+            .filter(|(path, _)| path.trim_start_matches('/').starts_with(prefix))
+            .fold((0, 0), |(c, u), (_, info)| {
+                (
+                    c + u64::from(info.compressed_size),
+                    u + u64::from(info.uncompressed_size),
+                )
+            })
+    };
+    let sizes = pkg.dir_sizes();
+    assert_eq!(sizes.keys().collect::<Vec<_>>(), ["", "empty", "lorem"]);
+    assert_eq!(
+        sizes[""],
+        (pkg.total_compressed_size(), pkg.total_uncompressed_size())
+    );
+    assert_eq!(sizes["lorem"], sum("lorem/"));
+    assert!(sizes["lorem"].0 < sizes["lorem"].1);
+    assert_eq!(sizes["empty"], (0, 0));
+}
+
+#[test]
+fn children() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d))
+            .chain([("lorem/ipsum/dolor.txt".to_string(), b"".as_slice())]),
+    );
+
+    assert_eq!(
+        pkg.children("").collect::<Vec<_>>(),
+        ["fox.txt", "hello.txt", "lorem/"]
+    );
+    for prefix in ["lorem", "lorem/"] {
+        assert_eq!(
+            pkg.children(prefix).collect::<Vec<_>>(),
+            [
+                "ipsum/",
+                "lorem1024.txt",
+                "lorem16384.txt",
+                "lorem4096.txt",
+                "lorem512.txt"
+            ]
+        );
+    }
+    assert_eq!(
+        pkg.children("lorem/ipsum").collect::<Vec<_>>(),
+        ["dolor.txt"]
+    );
+    assert_eq!(pkg.children("hello.txt").count(), 0);
+    assert_eq!(pkg.children("missing").count(), 0);
+}
+
+#[test]
+fn data_region_size() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    assert_eq!(pkg.data_region_size().unwrap(), 0);
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    let total = data::BASIC_EXAMPLE_FILES
+        .iter()
+        .map(|(_, d)| d.len() as u64)
+        .sum::<u64>();
+    assert_eq!(pkg.data_region_size().unwrap(), total);
+
+    // Removing entries doesn't shrink the data region until the archive is repacked
+    pkg.remove("hello.txt").unwrap();
+    assert_eq!(pkg.data_region_size().unwrap(), total);
+    pkg.repack().unwrap();
+    assert_eq!(
+        pkg.data_region_size().unwrap(),
+        total - data::BASIC_EXAMPLE_FILES[0].1.len() as u64
+    );
+}
+
+#[test]
+fn parse_reject_trailing_garbage() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    assert!(!pkg.has_trailing_garbage().unwrap());
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    assert!(!pkg.has_trailing_garbage().unwrap());
+    drop(pkg);
+
+    let options = ParseOptions {
+        reject_trailing_garbage: true,
+        ..Default::default()
+    };
+    Pkg::parse_with_options(&mut storage, options.clone()).unwrap();
+
+    storage.get_mut().extend(b"garbage");
+    assert!(Pkg::parse(&mut storage)
+        .unwrap()
+        .has_trailing_garbage()
+        .unwrap());
+    assert!(matches!(
+        Pkg::parse_with_options(&mut storage, options.clone()),
+        Err(silpkg::sync::ParseError::TrailingGarbage { size: 7 })
+    ));
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    pkg.repack().unwrap();
+    assert!(!pkg.has_trailing_garbage().unwrap());
+    drop(pkg);
+    Pkg::parse_with_options(&mut storage, options).unwrap();
+}
+
+#[test]
+fn integrity_ok() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let (name, data) = data::BASIC_EXAMPLE_FILES[2];
+
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        [(name.to_string(), data)].into_iter(),
+    );
+    add(
+        &mut pkg,
+        Flags::default(),
+        [("raw".to_string(), data)].into_iter(),
+    );
+
+    let mut reader = pkg.open(name).unwrap();
+    reader.read_exact(&mut [0; 16]).unwrap();
+    assert_eq!(reader.integrity_ok(), None);
+    reader.read_to_end(&mut vec![]).unwrap();
+    assert_eq!(reader.integrity_ok(), Some(true));
+
+    let mut reader = pkg.open("raw").unwrap();
+    reader.read_to_end(&mut vec![]).unwrap();
+    assert_eq!(reader.integrity_ok(), None);
+
+    // Corrupt the last byte of the Adler-32 checksum
+    let info = pkg.metadata(name).unwrap();
+    drop(pkg);
+    storage.get_mut()[(info.data_offset + info.compressed_size - 1) as usize] ^= 0xFF;
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    let mut reader = pkg.open(name).unwrap();
+    let mut out = vec![];
+    assert_eq!(
+        reader.read_to_end(&mut out).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    assert_eq!(reader.integrity_ok(), Some(false));
+    // The error sticks instead of turning into an early end of the data
+    assert_eq!(
+        reader.read(&mut [0; 16]).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    drop(reader);
+
+    // Decompressing must not replace the entry with the truncated data
+    assert!(pkg.decompress(name).is_err());
+    assert!(pkg.is_compressed(name).unwrap());
+}
+
+#[test]
+fn add_raw_deflate() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let files = &data::BASIC_EXAMPLE_FILES[..3];
+    let flags = Flags {
+        compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+    };
+
+    pkg.insert("zlib".into(), flags.clone())
+        .unwrap()
+        .write_all(files[0].1)
+        .unwrap();
+    pkg.set_deflate_format(silpkg::DeflateFormat::Raw);
+    add(
+        &mut pkg,
+        flags,
+        files[..2].iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+    add(
+        &mut pkg,
+        Flags::default(),
+        files[2..].iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+    pkg.compress_entries(
+        &[files[2].0],
+        Compression::new(6),
+        std::num::NonZeroUsize::new(1).unwrap(),
+        |_| {},
+    )
+    .unwrap();
+
+    // Only the zlib wrapper should differ
+    assert_eq!(
+        pkg.metadata("zlib").unwrap().compressed_size,
+        pkg.metadata(files[0].0).unwrap().compressed_size + 6
+    );
+    drop(pkg);
+
+    let options = ParseOptions {
+        deflate_format: silpkg::DeflateFormat::Raw,
+        ..Default::default()
+    };
+    let mut pkg = Pkg::parse_with_options(&mut storage, options).unwrap();
+    extract(&mut pkg, files.iter().copied());
+    let mut reader = pkg.open(files[2].0).unwrap();
+    reader.read_to_end(&mut vec![]).unwrap();
+    assert_eq!(reader.integrity_ok(), None);
+    drop(reader);
+
+    // Reading raw deflate data as zlib fails
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    let mut reader = pkg.open(files[0].0).unwrap();
+    assert_eq!(
+        reader.read_to_end(&mut vec![]).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    assert_eq!(reader.integrity_ok(), Some(false));
+}
+
+#[test]
+fn add_written() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data = data::BASIC_EXAMPLE_FILES[5].1;
+
+    for compression in [
+        silpkg::EntryCompression::None,
+        silpkg::EntryCompression::Deflate(Compression::new(6)),
+    ] {
+        let path = format!("{compression:?}");
+        let mut writer = pkg.insert(path.clone(), Flags { compression }).unwrap();
+        assert_eq!(writer.written(), 0);
+        for (i, chunk) in data.chunks(1000).enumerate() {
+            writer.write_all(chunk).unwrap();
+            assert_eq!(writer.written(), (data.len().min((i + 1) * 1000)) as u64);
+        }
+        writer.finish().unwrap();
+
+        extract(&mut pkg, [(path.as_str(), data)].into_iter());
+    }
+}
+
+#[test]
+fn dry_repack_verify() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    pkg.remove(data::BASIC_EXAMPLE_FILES[0].0).unwrap();
+
+    let check = pkg.dry_repack_verify().unwrap();
+    assert!(check.is_ok());
+    assert!(check.bytes_moved > 0);
+    pkg.repack().unwrap();
+    assert_eq!(pkg.dry_repack_verify().unwrap().bytes_moved, 0);
+
+    let mut storage = raw_archive(&[("a", 82, 10), ("b", 87, 12), ("c", 97, 2)]);
+    storage.extend(b"0123456789ABCDE");
+    let mut pkg = Pkg::parse(std::io::Cursor::new(storage)).unwrap();
+
+    let check = pkg.dry_repack_verify().unwrap();
+    assert!(!check.is_ok());
+    assert_eq!(
+        check.overlapping,
+        [("a".into(), "b".into()), ("b".into(), "c".into())]
+    );
+    assert_eq!(check.past_end, ["b", "c"]);
+    assert_eq!(check.unsafe_moves, ["b"]);
+}
+
+#[cfg(all(feature = "mmap", unix))]
+#[test]
+fn mmap_entry() {
+    let path = std::env::temp_dir().join(format!("silpkg-mmap-{}.pkg", std::process::id()));
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    let mut pkg = Pkg::create(file).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d))
+            .chain([("empty".to_string(), b"".as_slice())]),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        [("compressed".to_string(), b"compressed".as_slice())].into_iter(),
+    );
+
+    // SAFETY: The file was already removed so nothing else can modify it and the archive is not
+    // modified through the Pkg while any of the mappings exist.
+    unsafe {
+        for (name, data) in data::BASIC_EXAMPLE_FILES {
+            assert_eq!(&*pkg.mmap_entry(name).unwrap(), *data);
+        }
+        assert!(pkg.mmap_entry("empty").unwrap().is_empty());
+        assert_eq!(
+            pkg.mmap_entry("compressed").err().unwrap().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+        assert_eq!(
+            pkg.mmap_entry("missing").err().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+}
+
+#[test]
+fn retain() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let kept = |path: &str, size: u32| !path.starts_with("lorem/") && size.is_multiple_of(2);
+    pkg.retain(|path, info| kept(path, info.uncompressed_size))
+        .unwrap();
+
+    let remaining = data
+        .iter()
+        .filter(|(n, d)| kept(n, d.len() as u32))
+        .collect::<Vec<_>>();
+    assert!(remaining.len() < data.len());
+    list(&pkg, remaining.iter().map(|(n, _)| n.as_str()));
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    list(&pkg, remaining.iter().map(|(n, _)| n.as_str()));
+    extract(
+        &mut pkg,
+        remaining.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+}
+
+#[test]
+fn observer() {
+    let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let sink = events.clone();
+    pkg.set_observer(move |event| sink.lock().unwrap().push(event));
+
+    // Enough entries to run out of the preallocated entry slots
+    let data = (0..70)
+        .map(|i| (format!("file{i}.txt"), i.to_string().into_bytes()))
+        .collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let events = events.lock().unwrap();
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, silpkg::PkgEvent::EntriesReserved { .. })));
+    let written = events
+        .iter()
+        .filter_map(|event| match event {
+            silpkg::PkgEvent::EntryWritten { path, .. } => Some(path.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(
+        written,
+        data.iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<HashSet<_>>()
+    );
+}
+
+#[test]
+fn insert_would_grow() {
+    use silpkg::{GrowthKind, PkgEvent};
+
+    let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let sink = events.clone();
+    pkg.set_observer(move |event| sink.lock().unwrap().push(event));
+
+    let mut kinds = HashSet::new();
+    for i in 0..100 {
+        // Occasionally use long paths to run out of path region space
+        let path = match i % 10 {
+            0 => format!("{i}/{}", "x".repeat(300)),
+            _ => format!("{i}.txt"),
+        };
+
+        let kind = pkg.insert_would_grow(&path);
+        kinds.insert(kind);
+        events.lock().unwrap().clear();
+        pkg.insert(path.clone(), Flags::default())
+            .unwrap()
+            .finish()
+            .unwrap();
+        assert_eq!(pkg.insert_would_grow(&path), GrowthKind::None);
+
+        let events = events.lock().unwrap();
+        let reserved_entries = events
+            .iter()
+            .any(|event| matches!(event, PkgEvent::EntriesReserved { .. }));
+        let moved = events
+            .iter()
+            .any(|event| matches!(event, PkgEvent::DataRegionMoved { .. }));
+        assert_eq!(
+            reserved_entries,
+            matches!(
+                kind,
+                GrowthKind::EntryList | GrowthKind::EntryListAndPathRegion
+            )
+        );
+        assert_eq!(moved, kind.grows(), "{path}: {kind:?}");
+    }
+
+    assert!(kinds.contains(&GrowthKind::None));
+    assert!(kinds.contains(&GrowthKind::EntryList));
+    assert!(kinds.contains(&GrowthKind::PathRegion));
+}
+
+#[test]
+fn entry_slice() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    pkg.insert(
+        "compressed".into(),
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+    )
+    .unwrap()
+    .finish()
+    .unwrap();
+
+    for (path, data) in data::BASIC_EXAMPLE_FILES {
+        assert_eq!(pkg.entry_slice(path).unwrap(), *data);
+    }
+    assert_eq!(
+        pkg.entry_slice("compressed").unwrap_err().kind(),
+        std::io::ErrorKind::Unsupported
+    );
+    assert_eq!(
+        pkg.entry_slice("missing").unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn estimate_compressed_size() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+
+    for (path, data) in data::combined_data() {
+        for compression in [
+            silpkg::EntryCompression::None,
+            silpkg::EntryCompression::Deflate(Compression::fast()),
+            silpkg::EntryCompression::Deflate(Compression::best()),
+        ] {
+            let estimate = compression.estimate(&data);
+            let path = format!("{path}.{compression:?}");
+            pkg.insert(path.clone(), Flags { compression })
+                .unwrap()
+                .write_all(&data)
+                .unwrap();
+            assert_eq!(
+                estimate,
+                pkg.metadata(&path).unwrap().compressed_size as u64,
+                "{path}"
+            );
+        }
+    }
+}
+
+#[test]
+fn parse_headers_only() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    let hashes = pkg
+        .entries()
+        .map(|(path, info)| (path.to_string(), info.path_hash))
+        .collect::<Vec<_>>();
+    drop(pkg);
+
+    let mut pkg = Pkg::parse_headers_only(&mut storage).unwrap();
+    assert_eq!(pkg.paths().count(), 0);
+    assert_eq!(pkg.entries().count(), data::BASIC_EXAMPLE_FILES.len());
+    assert!(pkg.entries().all(|(path, _)| path.is_empty()));
+
+    let missing_hash = (0..)
+        .find(|hash| hashes.iter().all(|(_, h)| h != hash))
+        .unwrap();
+    for (path, hash) in hashes {
+        let data = data::BASIC_EXAMPLE_FILES
+            .iter()
+            .find(|(p, _)| *p == path)
+            .unwrap()
+            .1;
+        let mut out = vec![];
+        pkg.open_by_hash(hash)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, data);
+    }
+    assert!(matches!(
+        pkg.open_by_hash(missing_hash),
+        Err(silpkg::sync::OpenError::NotFound)
+    ));
+
+    assert_eq!(
+        pkg.insert("new".into(), Flags::default())
+            .err()
+            .unwrap()
+            .to_string(),
+        "Archives parsed without paths cannot be modified"
+    );
+}
+
+#[test]
+fn peek() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    let entry_count = pkg.entry_slots().count() as u32;
+    drop(pkg);
+
+    storage.set_position(7);
+    let info = silpkg::peek(&mut storage).unwrap();
+    assert_eq!(storage.position(), 7);
+    assert!(info.is_supported());
+    assert_eq!((info.header_size, info.entry_size), (16, 20));
+    assert_eq!(info.entry_count, entry_count);
+
+    let mut storage = std::io::Cursor::new(raw_archive(&[("a", 0, 0)]));
+    storage.get_mut()[0] = b'X';
+    let info = silpkg::peek(&mut storage).unwrap();
+    assert!(!info.magic_ok);
+    assert_eq!(info.entry_count, 1);
+    assert_eq!(info.path_region_size, 2);
+
+    let mut storage = std::io::Cursor::new(b"PKG\n".to_vec());
+    assert!(matches!(
+        silpkg::peek(&mut storage),
+        Err(silpkg::sync::ParseError::Io(_))
+    ));
+    assert_eq!(storage.position(), 0);
+}
+
+#[test]
+fn insert_from() {
+    struct FailingReader(usize);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.checked_sub(buf.len().min(10)) {
+                Some(left) => {
+                    let count = self.0 - left;
+                    self.0 = left;
+                    buf[..count].fill(b'a');
+                    Ok(count)
+                }
+                None => Err(std::io::Error::other("source failed")),
+            }
+        }
+    }
+
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    for (path, data) in data::BASIC_EXAMPLE_FILES {
+        let info = pkg
+            .insert_from(path.to_string(), Flags::default(), &mut &data[..])
+            .unwrap();
+        assert_eq!(info.uncompressed_size as usize, data.len());
+    }
+
+    assert_eq!(
+        pkg.insert_from("broken".into(), Flags::default(), &mut FailingReader(25))
+            .unwrap_err()
+            .to_string(),
+        "source failed"
+    );
+    assert_eq!(
+        pkg.insert_from("hello.txt".into(), Flags::default(), &mut &b""[..])
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::AlreadyExists
+    );
+
+    list(
+        &pkg,
+        data::BASIC_EXAMPLE_FILES.iter().map(|(path, _)| *path),
+    );
+    extract(&mut pkg, data::BASIC_EXAMPLE_FILES.iter().copied());
+
+    // The source's error should be returned even if the archive can't be written to anymore
+    struct FaultingReader(fault::FaultTrigger);
+
+    impl Read for FaultingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.fail_after(0);
+            Err(std::io::Error::other("source failed"))
+        }
+    }
+
+    let storage = fault::FaultyStorage::new(pkg.inner().get_ref().clone());
+    let trigger = storage.trigger();
+    let mut pkg = Pkg::parse(storage).unwrap();
+    assert_eq!(
+        pkg.insert_from(
+            "broken".into(),
+            Flags::default(),
+            &mut FaultingReader(trigger)
+        )
+        .unwrap_err()
+        .to_string(),
+        "source failed"
+    );
+
+    let pkg = Pkg::parse(std::io::Cursor::new(pkg.inner().get_ref().to_vec())).unwrap();
+    list(
+        &pkg,
+        data::BASIC_EXAMPLE_FILES.iter().map(|(path, _)| *path),
+    );
+}
+
+#[test]
+fn index_of() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    assert_eq!(pkg.index_of("missing"), None);
+    for (path, data) in data::BASIC_EXAMPLE_FILES {
+        let index = pkg.index_of(path).unwrap();
+        assert_eq!(pkg.metadata(path).unwrap().index, index);
+
+        let mut out = vec![];
+        pkg.open_index(index)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, *data);
+    }
+}
+
+#[test]
+fn entry_records() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    pkg.remove("fox.txt").unwrap();
+    drop(pkg);
+
+    let pkg = Pkg::parse(&mut storage).unwrap();
+    let records = pkg.entry_records().collect::<Vec<_>>();
+    assert_eq!(records.len(), pkg.entry_slots().count());
+    assert_eq!(
+        records
+            .iter()
+            .filter(|(_, record)| record.is_some())
+            .count(),
+        data::BASIC_EXAMPLE_FILES.len() - 1
+    );
+
+    let bytes = pkg.inner().get_ref();
+    for (position, (i, record)) in records.into_iter().enumerate() {
+        assert_eq!(i, position);
+        let raw = &bytes[16 + i * 20..16 + (i + 1) * 20];
+        let field = |n: usize| u32::from_be_bytes(raw[n * 4..n * 4 + 4].try_into().unwrap());
+        match record {
+            Some(record) => {
+                assert_eq!(record.path_hash, field(0));
+                assert_eq!(record.path_offset | record.flags, field(1));
+                assert_eq!(record.flags, 1 << 24);
+                assert_eq!(record.data_offset, field(2));
+                assert_eq!(record.data_size, field(3));
+                assert_eq!(record.unpacked_size, field(4));
+                assert_eq!(pkg.metadata(record.path).unwrap().index, i);
+            }
+            None => assert_eq!(field(2), 0),
+        }
+    }
+}
+
+#[test]
+fn parse_shared() {
+    fn assert_send_sync_clone<T: Send + Sync + Clone>(_: &T) {}
+
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    drop(pkg);
+
+    let pkg = Pkg::parse_shared(storage.into_inner().into()).unwrap();
+    assert_send_sync_clone(&pkg);
+
+    std::thread::scope(|scope| {
+        for chunk in data.chunks(data.len() / 4) {
+            let mut pkg = pkg.clone();
+            scope.spawn(move || {
+                for (path, data) in chunk {
+                    let mut out = vec![];
+                    pkg.open(path).unwrap().read_to_end(&mut out).unwrap();
+                    assert_eq!(&out, data);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn add_empty() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    pkg.insert("empty".into(), Flags::default())
+        .unwrap()
+        .finish()
+        .unwrap();
+    pkg.insert(
+        "empty_compressed".into(),
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+    )
+    .unwrap()
+    .finish()
+    .unwrap();
+    pkg.insert_sized("empty_sized".into(), 0)
+        .unwrap()
+        .finish()
+        .unwrap();
+    pkg.remove("fox.txt").unwrap();
+
+    let empty = [
+        ("empty", b"".as_slice()),
+        ("empty_compressed", b"".as_slice()),
+        ("empty_sized", b"".as_slice()),
+    ];
+    let mut expected = data::BASIC_EXAMPLE_FILES
+        .iter()
+        .copied()
+        .filter(|(path, _)| *path != "fox.txt")
+        .chain(empty)
+        .collect::<Vec<_>>();
+    expected.sort();
+
+    extract(&mut pkg, expected.iter().copied());
+    assert_eq!(pkg.metadata("empty").unwrap().compressed_size, 0);
+    assert_ne!(pkg.metadata("empty").unwrap().data_offset, 0);
+
+    pkg.repack().unwrap();
+    extract(&mut pkg, expected.iter().copied());
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    list(&pkg, expected.iter().map(|(path, _)| *path));
+    extract(&mut pkg, expected.iter().copied());
+    let mut reader = pkg.open("empty").unwrap();
+    assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+}
+
+#[test]
+fn add_invalid_path() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+
+    for path in ["", "nul\0byte", "héllo.txt"] {
+        assert!(matches!(
+            pkg.insert(path.into(), Flags::default()),
+            Err(silpkg::sync::InsertError::InvalidPath)
+        ));
+    }
+    assert_eq!(
+        pkg.insert_from("".into(), Flags::default(), &mut &b""[..])
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::InvalidInput
+    );
+    assert_eq!(pkg.entries().count(), 0);
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        [("back\\slash".to_string(), b"ok".as_slice())].into_iter(),
+    );
+    extract(&mut pkg, [("back\\slash", b"ok".as_slice())].into_iter());
+
+    for path in ["", "nul\0byte", "héllo.txt"] {
+        assert!(matches!(
+            pkg.rename("back\\slash", path.into()),
+            Err(silpkg::sync::RenameError::InvalidPath)
+        ));
+        assert!(matches!(
+            pkg.replace("back\\slash", path.into()),
+            Err(silpkg::sync::ReplaceError::InvalidPath)
+        ));
+    }
+    list(&pkg, ["back\\slash"].into_iter());
+}
+
+#[test]
+fn rename_case_only() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        [
+            ("Foo.txt".to_string(), b"foo".as_slice()),
+            ("Bar.txt".to_string(), b"bar".as_slice()),
+        ]
+        .into_iter(),
+    );
+
+    let hash = pkg.metadata("Foo.txt").unwrap().path_hash;
+    pkg.rename("Foo.txt", "foo.txt".into()).unwrap();
+    pkg.rename("Bar.txt", "baz.txt".into()).unwrap();
+    assert_eq!(pkg.metadata("foo.txt").unwrap().path_hash, hash);
+    list(&pkg, ["foo.txt", "baz.txt"].into_iter());
+    extract(
+        &mut pkg,
+        [
+            ("foo.txt", b"foo".as_slice()),
+            ("baz.txt", b"bar".as_slice()),
+        ]
+        .into_iter(),
+    );
+    drop(pkg);
+
+    let options = ParseOptions {
+        verify_hashes: true,
+        ..Default::default()
+    };
+    let mut pkg = Pkg::parse_with_options(&mut storage, options).unwrap();
+    list(&pkg, ["foo.txt", "baz.txt"].into_iter());
+    extract(
+        &mut pkg,
+        [
+            ("foo.txt", b"foo".as_slice()),
+            ("baz.txt", b"bar".as_slice()),
+        ]
+        .into_iter(),
+    );
+}
+
+#[test]
+fn directory_markers() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        [
+            ("empty/".to_string(), b"".as_slice()),
+            ("lorem/".to_string(), b"".as_slice()),
+            ("lorem/ipsum/".to_string(), b"".as_slice()),
+            ("lorem/dolor.txt".to_string(), b"sit".as_slice()),
+            ("not_a_marker/".to_string(), b"data".as_slice()),
+        ]
+        .into_iter(),
+    );
+
+    assert!(pkg.is_directory_marker("empty/"));
+    assert!(pkg.is_directory_marker("lorem/ipsum/"));
+    assert!(!pkg.is_directory_marker("lorem/dolor.txt"));
+    assert!(!pkg.is_directory_marker("not_a_marker/"));
+    assert!(!pkg.is_directory_marker("missing/"));
+
+    let tree = pkg.tree();
+    assert!(tree.get("empty").unwrap().files.is_empty());
+    assert!(tree.get("lorem/ipsum").unwrap().files.is_empty());
+    assert_eq!(
+        tree.get("lorem")
+            .unwrap()
+            .files
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>(),
+        ["lorem/dolor.txt"]
+    );
+    assert_eq!(tree.file_count(), 2);
+
+    assert!(pkg.children("").eq(["empty/", "lorem/", "not_a_marker/"]));
+    assert!(pkg.children("lorem").eq(["dolor.txt", "ipsum/"]));
+    assert_eq!(pkg.children("empty").count(), 0);
+}
+
+#[test]
+fn rewrite_header() {
+    // Storage that can still be modified from the outside while a Pkg is using it
+    #[derive(Clone)]
+    struct Shared(std::rc::Rc<std::cell::RefCell<std::io::Cursor<Vec<u8>>>>);
+
+    impl Read for Shared {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().read(buf)
+        }
+    }
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Shared {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.0.borrow_mut().seek(pos)
+        }
+    }
+
+    let storage = Shared(Default::default());
+    let mut pkg = Pkg::create(storage.clone()).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    let header = storage.0.borrow().get_ref()[..16].to_vec();
+
+    storage.0.borrow_mut().get_mut()[..16].fill(0xFF);
+    assert!(Pkg::parse(std::io::Cursor::new(storage.0.borrow().get_ref().clone())).is_err());
+
+    pkg.rewrite_header().unwrap();
+    assert_eq!(storage.0.borrow().get_ref()[..16], header);
+
+    let mut pkg = Pkg::parse(std::io::Cursor::new(storage.0.borrow().get_ref().clone())).unwrap();
+    extract(&mut pkg, data::BASIC_EXAMPLE_FILES.iter().copied());
+}
+
+#[test]
+fn extract_entry() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    for (path, data) in data::BASIC_EXAMPLE_FILES {
+        let mut out = vec![];
+        assert_eq!(
+            pkg.extract_entry(path, &mut out).unwrap(),
+            data.len() as u64
+        );
+        assert_eq!(out, *data);
+    }
+
+    assert!(matches!(
+        pkg.extract_entry("missing", &mut vec![]),
+        Err(silpkg::sync::OpenError::NotFound)
+    ));
+    assert!(matches!(
+        pkg.extract_entry("hello.txt", &mut &mut [0u8; 4][..]),
+        Err(silpkg::sync::OpenError::Io(_))
+    ));
+}
+
+#[test]
+fn read_range() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter()
+            .map(|(n, d)| (format!("stored/{n}"), d.as_slice())),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+        data.iter()
+            .map(|(n, d)| (format!("compressed/{n}"), d.as_slice())),
+    );
+
+    for (name, data) in &data {
+        let len = data.len() as u64;
+        for range in [
+            0..len,
+            0..1,
+            len / 3..len / 2,
+            len / 2..len + 10,
+            len + 1..len + 5,
+        ] {
+            let start = range.start.min(len) as usize;
+            let end = range.end.min(len) as usize;
+            for path in [format!("stored/{name}"), format!("compressed/{name}")] {
+                let mut out = vec![];
+                let written = pkg.read_range(&path, range.clone(), &mut out).unwrap();
+                assert_eq!(written, (end - start) as u64);
+                assert_eq!(out, data[start..end]);
+            }
+        }
+    }
+
+    assert!(matches!(
+        pkg.read_range("missing", 0..1, &mut vec![]),
+        Err(silpkg::sync::OpenError::NotFound)
+    ));
+}
+
+#[test]
+fn is_compressed() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let (stored, compressed) = data::BASIC_EXAMPLE_FILES.split_at(2);
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        stored.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        compressed.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    for (name, _) in stored {
+        assert_eq!(pkg.is_compressed(name), Some(false));
+    }
+    for (name, _) in compressed {
+        assert_eq!(pkg.is_compressed(name), Some(true));
+    }
+    assert_eq!(pkg.is_compressed("missing"), None);
+
+    let (name, _) = compressed[0];
+    pkg.decompress(name).unwrap();
+    assert_eq!(pkg.is_compressed(name), Some(false));
+}
+
+#[test]
+fn path_list() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let mut paths = pkg.path_list();
+    paths.sort();
+    let mut expected = data::BASIC_EXAMPLE_FILES
+        .iter()
+        .map(|(n, _)| n.to_string())
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(paths, expected);
+
+    // The list doesn't borrow the archive so entries can be removed while iterating
+    for path in pkg.path_list() {
+        pkg.remove(&path).unwrap();
+    }
+    assert!(pkg.path_list().is_empty());
+}
+
+#[test]
+fn rename_repeatedly() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let mut data: Vec<(String, Vec<u8>)> = data::combined_data().collect();
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    // Enough renames for the old paths to be dropped from memory a couple of times
+    for round in 0..50 {
+        for (i, (name, _)) in data.iter_mut().enumerate() {
+            let renamed = format!("renamed/{round}/{i}");
+            rename(&mut pkg, [(name.as_str(), renamed.clone())].into_iter());
+            *name = renamed;
+        }
+        list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+    }
+
+    pkg.remove(&data.pop().unwrap().0).unwrap();
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    pkg.repack().unwrap();
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn stale_path_bytes() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    assert_eq!(pkg.stale_path_bytes(), 0);
+
+    let (first, _) = data::BASIC_EXAMPLE_FILES[0];
+    let (second, _) = data::BASIC_EXAMPLE_FILES[1];
+    pkg.rename(first, "first".to_string()).unwrap();
+    assert_eq!(pkg.stale_path_bytes(), first.len() as u32 + 1);
+    pkg.remove(second).unwrap();
+    let stale = (first.len() + second.len()) as u32 + 2;
+    assert_eq!(pkg.stale_path_bytes(), stale);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    assert_eq!(pkg.stale_path_bytes(), stale);
+    pkg.repack().unwrap();
+    assert_eq!(pkg.stale_path_bytes(), 0);
+}
+
+#[test]
+fn compact_paths_should_repack() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let mut data = (0..16)
+        .map(|i| (format!("original-entry-name-{i}"), vec![i as u8; 4]))
+        .collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    assert!(!pkg.should_repack());
+
+    // Renaming to long paths leaves the short ones behind, which is a lot compared to the data
+    for (i, (path, _)) in data.iter_mut().enumerate() {
+        let renamed = format!("{}/{i}", "renamed".repeat(4));
+        pkg.rename(path, renamed.clone()).unwrap();
+        *path = renamed;
+    }
+    let stale = pkg.stale_path_bytes();
+    assert!(stale > 0);
+    assert!(pkg.should_repack());
+
+    let len = pkg.inner().get_ref().len();
+    pkg.compact_paths().unwrap();
+    assert_eq!(pkg.stale_path_bytes(), 0);
+    assert!(!pkg.should_repack());
+    // Nothing is moved, the path region only gets room for new paths
+    assert_eq!(pkg.inner().get_ref().len(), len);
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    assert_eq!(pkg.stale_path_bytes(), 0);
+    let path = "a".repeat(stale as usize / 2);
+    assert_eq!(pkg.insert_would_grow(&path), silpkg::GrowthKind::None);
+    add(
+        &mut pkg,
+        Flags::default(),
+        [(path.clone(), b"new".as_slice())].into_iter(),
+    );
+    data.push((path, b"new".to_vec()));
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+}
+
+#[test]
+fn open_under() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let (name, expected) = data::BASIC_EXAMPLE_FILES[2];
+    let (prefix, subpath) = name.split_once('/').unwrap();
+    for (prefix, subpath) in [
+        (prefix.to_string(), subpath.to_string()),
+        (format!("{prefix}/"), subpath.to_string()),
+        (format!("{prefix}/"), format!("/{subpath}")),
+        (String::new(), name.to_string()),
+    ] {
+        let mut data = vec![];
+        pkg.open_under(&prefix, &subpath)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, expected);
+    }
+
+    assert!(matches!(
+        pkg.open_under(prefix, "missing"),
+        Err(silpkg::sync::OpenError::NotFound)
+    ));
+}
+
+#[test]
+fn copy_entry() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let (stored, compressed) = data::BASIC_EXAMPLE_FILES.split_at(2);
+    add(
+        &mut pkg,
+        Flags::default(),
+        stored.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        compressed.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let (stored_name, stored_data) = stored[0];
+    let (compressed_name, compressed_data) = compressed[0];
+    pkg.copy_entry(stored_name, "stored copy".to_string())
+        .unwrap();
+    pkg.copy_entry(compressed_name, "compressed copy".to_string())
+        .unwrap();
+    assert_eq!(pkg.is_compressed("compressed copy"), Some(true));
+    assert_eq!(
+        pkg.metadata("compressed copy").unwrap().compressed_size,
+        pkg.metadata(compressed_name).unwrap().compressed_size
+    );
+
+    assert!(matches!(
+        pkg.copy_entry("missing", "a".to_string()),
+        Err(silpkg::sync::CopyError::NotFound)
+    ));
+    assert!(matches!(
+        pkg.copy_entry(stored_name, compressed_name.to_string()),
+        Err(silpkg::sync::CopyError::AlreadyExists)
+    ));
+    assert!(matches!(
+        pkg.copy_entry(stored_name, "".to_string()),
+        Err(silpkg::sync::CopyError::InvalidPath)
+    ));
+
+    // Modifying the copy leaves the original untouched and the other way around
+    let mut writer = pkg
+        .overwrite("stored copy".to_string(), Flags::default())
+        .unwrap();
+    writer.write_all(b"changed").unwrap();
+    writer.finish().unwrap();
+    pkg.remove(compressed_name).unwrap();
+    pkg.repack().unwrap();
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    extract(
+        &mut pkg,
+        [
+            (stored_name, stored_data),
+            ("stored copy", &b"changed"[..]),
+            ("compressed copy", compressed_data),
+        ]
+        .into_iter(),
+    );
+}
+
+#[test]
+fn transcode_to() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+    let (stored, compressed) = data.split_at(data.len() / 2);
+    add(
+        &mut pkg,
+        Flags::default(),
+        stored.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        compressed
+            .iter()
+            .map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    let time = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    pkg.set_modification_times([(data[0].0.as_str(), time)])
+        .unwrap();
+
+    for policy in [
+        silpkg::CompressionPolicy::Keep,
+        silpkg::CompressionPolicy::All(silpkg::EntryCompression::None),
+        silpkg::CompressionPolicy::All(silpkg::EntryCompression::Deflate(Compression::new(9))),
+    ] {
+        let mut storage = std::io::Cursor::new(vec![]);
+        let out = pkg.transcode_to(&mut storage, policy.clone()).unwrap();
+        for (name, _) in data.iter() {
+            let original = pkg.metadata(name).unwrap();
+            let info = out.metadata(name).unwrap();
+            match &policy {
+                silpkg::CompressionPolicy::Keep => {
+                    assert_eq!(info.compressed, original.compressed);
+                    assert_eq!(info.compressed_size, original.compressed_size);
+                }
+                silpkg::CompressionPolicy::All(compression) => {
+                    assert_eq!(info.compressed, !compression.is_none());
+                }
+            }
+        }
+        // Entries are written back to back so there is nothing to reclaim
+        assert!(out.free_regions().is_empty());
+        drop(out);
+
+        let mut out = Pkg::parse(&mut storage).unwrap();
+        extract(
+            &mut out,
+            data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+        );
+        list(&out, data.iter().map(|(n, _)| n.as_str()));
+        assert_eq!(out.modification_times().unwrap()[&data[0].0], time);
+    }
+}
+
+#[test]
+fn read_entry_owned() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let workers = data
+        .iter()
+        .map(|(name, expected)| {
+            let mut entry = pkg.read_entry_owned(name).unwrap();
+            assert_eq!(entry.as_slice(), expected.as_slice());
+            let expected = expected.clone();
+            std::thread::spawn(move || {
+                let mut buf = vec![];
+                entry.read_to_end(&mut buf).unwrap();
+                assert_eq!(buf, expected);
+                entry.rewind().unwrap();
+                assert_eq!(entry.into_vec(), expected);
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    assert_eq!(
+        pkg.read_entry_owned("missing").unwrap_err().kind(),
+        std::io::ErrorKind::NotFound
+    );
+}
+
+#[test]
+fn path_region_churn() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let mut live = BTreeMap::<String, Vec<u8>>::new();
+    let mut in_place_renames = 0;
+
+    for round in 0..8 {
+        for i in 0..20 {
+            let name = format!("round{round}/{}", "x".repeat(i));
+            let data = format!("{round} {i}").into_bytes();
+            pkg.insert(name.clone(), Flags::default())
+                .unwrap()
+                .write_all(&data)
+                .unwrap();
+            live.insert(name, data);
+        }
+
+        // Remove every third entry, then rename the last inserted one to a shorter and then a
+        // longer path, both of which overwrite it in place at the end of the path region
+        for name in live.keys().step_by(3).cloned().collect::<Vec<_>>() {
+            pkg.remove(&name).unwrap();
+            live.remove(&name);
+        }
+        let mut src = format!("round{round}/{}", "x".repeat(19));
+        if live.contains_key(&src) {
+            for dst in [format!("short{round}"), format!("longer/renamed/{round}")] {
+                let stale = pkg.stale_path_bytes();
+                pkg.rename(&src, dst.clone()).unwrap();
+                assert_eq!(pkg.stale_path_bytes(), stale);
+
+                let data = live.remove(&src).unwrap();
+                live.insert(dst.clone(), data);
+                src = dst;
+                in_place_renames += 1;
+            }
+        }
+
+        if round == 4 {
+            pkg.repack().unwrap();
+        }
+
+        let mut reparsed = Pkg::parse_with_options(
+            std::io::Cursor::new(pkg.inner().get_ref().clone()),
+            ParseOptions {
+                verify_hashes: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        extract(
+            &mut reparsed,
+            live.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+        );
+        list(&reparsed, live.keys().map(String::as_str));
+        assert_eq!(reparsed.stale_path_bytes(), pkg.stale_path_bytes());
+    }
+
+    assert!(in_place_renames > 0);
+
+    // Inserting into a reparsed archive must not overwrite any existing paths
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    pkg.insert("after/reparse".to_string(), Flags::default())
+        .unwrap()
+        .write_all(b"new")
+        .unwrap();
+    live.insert("after/reparse".to_string(), b"new".to_vec());
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    extract(
+        &mut pkg,
+        live.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, live.keys().map(String::as_str));
+}
+
+#[test]
+fn validate_against_manifest() {
+    use silpkg::errors::ValidationError;
+
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let (stored, compressed) = data::BASIC_EXAMPLE_FILES.split_at(2);
+    add(
+        &mut pkg,
+        Flags::default(),
+        stored.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        compressed.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let manifest = pkg.manifest(true).unwrap();
+    assert_eq!(manifest.entries.len(), data::BASIC_EXAMPLE_FILES.len());
+    assert!(pkg.validate_against(&manifest, true).unwrap().is_empty());
+    let without_hashes = pkg.manifest(false).unwrap();
+    assert!(without_hashes
+        .entries
+        .values()
+        .all(|e| e.content_hash.is_none()));
+
+    let (hello, hello_data) = stored[0];
+    let (fox, _) = stored[1];
+    let (lorem, _) = compressed[0];
+    assert_eq!(
+        without_hashes.entries[hello],
+        silpkg::ManifestEntry::new(hello_data.len() as u32, false)
+    );
+    // Same size but different contents
+    let mut changed = hello_data.to_vec();
+    changed[0] ^= 1;
+    pkg.remove(fox).unwrap();
+    pkg.overwrite(hello.to_string(), Flags::default())
+        .unwrap()
+        .write_all(&changed)
+        .unwrap();
+    pkg.decompress(lorem).unwrap();
+    pkg.insert("extra".to_string(), Flags::default())
+        .unwrap()
+        .write_all(b"extra")
+        .unwrap();
+
+    let mut expected = vec![
+        ValidationError::ContentMismatch(hello.to_string()),
+        ValidationError::Missing(fox.to_string()),
+        ValidationError::CompressionMismatch {
+            path: lorem.to_string(),
+            expected: true,
+            actual: false,
+        },
+        ValidationError::Unexpected("extra".to_string()),
+    ];
+    // Manifest entries are reported in path order
+    expected[..3].sort_by_key(|error| match error {
+        ValidationError::ContentMismatch(path) | ValidationError::Missing(path) => path.clone(),
+        ValidationError::CompressionMismatch { path, .. } => path.clone(),
+        _ => unreachable!(),
+    });
+    assert_eq!(pkg.validate_against(&manifest, true).unwrap(), expected);
+
+    // Contents aren't compared without the flag or without hashes in the manifest
+    expected.retain(|error| !matches!(error, ValidationError::ContentMismatch(_)));
+    assert_eq!(pkg.validate_against(&manifest, false).unwrap(), expected);
+    assert_eq!(
+        pkg.validate_against(&without_hashes, true).unwrap(),
+        expected
+    );
+
+    pkg.overwrite(lorem.to_string(), Flags::default())
+        .unwrap()
+        .write_all(b"short")
+        .unwrap();
+    assert!(pkg.validate_against(&manifest, false).unwrap().contains(
+        &ValidationError::SizeMismatch {
+            path: lorem.to_string(),
+            expected: manifest.entries[lorem].size,
+            actual: 5,
+        }
+    ));
+}
+
+#[test]
+fn open_with_info() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    for (name, expected) in data.iter() {
+        let metadata = pkg.metadata(name).unwrap();
+        let (mut reader, info) = pkg.open_with_info(name).unwrap();
+        let mut buf = Vec::with_capacity(info.uncompressed_size as usize);
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, *expected);
+        assert_eq!(info.index, metadata.index);
+        assert_eq!(info.data_offset, metadata.data_offset);
+        assert_eq!(info.uncompressed_size, expected.len() as u32);
+    }
+
+    assert!(matches!(
+        pkg.open_with_info("missing"),
+        Err(silpkg::sync::OpenError::NotFound)
+    ));
+}
+
+#[cfg(feature = "unstable_base")]
+#[test]
+fn insert_at() {
+    use silpkg::sync::InsertError;
+
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        [
+            ("first".to_string(), &[1; 64][..]),
+            ("second".to_string(), &[2; 64][..]),
+        ]
+        .into_iter(),
+    );
+    let first = pkg.metadata("first").unwrap().data_offset as u64;
+    let second = pkg.metadata("second").unwrap().data_offset as u64;
+    pkg.remove("first").unwrap();
+
+    assert!(matches!(
+        pkg.insert_at("inside".to_string(), Flags::default(), second + 8),
+        Err(InsertError::InvalidOffset)
+    ));
+    assert!(matches!(
+        pkg.insert_at("header".to_string(), Flags::default(), 0),
+        Err(InsertError::InvalidOffset)
+    ));
+    assert!(!pkg.contains("inside") && !pkg.contains("header"));
+
+    // Compressed data that doesn't fit into the gap is rejected without adding the entry
+    let noise = (0..4096u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect::<Vec<_>>();
+    let mut writer = pkg
+        .insert_at(
+            "squeezed".to_string(),
+            Flags {
+                compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+            },
+            first + 48,
+        )
+        .unwrap();
+    let error = writer
+        .write_all(&noise)
+        .err()
+        .or_else(|| writer.finish().err())
+        .unwrap();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(!pkg.contains("squeezed"));
+    extract(&mut pkg, [("second", &[2; 64][..])].into_iter());
+
+    // Data placed in the gap must not spill into the data of the next entry
+    let mut writer = pkg
+        .insert_at("placed".to_string(), Flags::default(), first + 16)
+        .unwrap();
+    assert!(writer.write_all(&[3; 64]).is_err());
+    drop(writer);
+    assert_eq!(
+        pkg.metadata("placed").unwrap().data_offset as u64,
+        first + 16
+    );
+    pkg.flush().unwrap();
+    drop(pkg);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    extract(
+        &mut pkg,
+        [("placed", &[3; 48][..]), ("second", &[2; 64][..])].into_iter(),
+    );
+}
+
+#[test]
+fn entries_after() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let snapshot = pkg
+        .entries()
+        .map(|(_, info)| info.index)
+        .collect::<HashSet<_>>();
+    assert!(pkg.entries_after(&snapshot).is_empty());
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        [
+            ("new/b.txt".to_string(), &b"b"[..]),
+            ("new/a.txt".to_string(), &b"a"[..]),
+        ]
+        .into_iter(),
+    );
+    assert_eq!(pkg.entries_after(&snapshot), ["new/b.txt", "new/a.txt"]);
+    assert_eq!(pkg.entries_after(&HashSet::new()).len(), snapshot.len() + 2);
+}
+
+#[test]
+fn panic_during_insert() {
+    struct PanickingWrites {
+        inner: std::io::Cursor<Vec<u8>>,
+        panic: std::rc::Rc<std::cell::Cell<bool>>,
+    }
+
+    impl Read for PanickingWrites {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for PanickingWrites {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl Write for PanickingWrites {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            assert!(!self.panic.get(), "write panicked");
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    let panic = std::rc::Rc::new(std::cell::Cell::new(false));
+    let mut pkg = Pkg::create(PanickingWrites {
+        inner: std::io::Cursor::new(vec![]),
+        panic: panic.clone(),
+    })
+    .unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let (replaced, original) = data::BASIC_EXAMPLE_FILES[0];
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut writer = pkg.insert("new.txt".into(), Flags::default()).unwrap();
+        writer.write_all(b"partial").unwrap();
+        panic!("interrupted");
+    }));
+    assert!(result.is_err());
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut writer = pkg
+            .overwrite(replaced.to_string(), Flags::default())
+            .unwrap();
+        panic.set(true);
+        _ = writer.write_all(b"replacement");
+    }));
+    assert!(result.is_err());
+    panic.set(false);
+
+    // Neither writer should have left anything behind
+    assert!(!pkg.contains("new.txt"));
+    assert!(pkg.open("new.txt").is_err());
+    extract(&mut pkg, [(replaced, original)].into_iter());
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        [("new.txt".to_string(), &b"complete"[..])].into_iter(),
+    );
+    pkg.flush().unwrap();
+    let mut pkg = Pkg::parse(pkg.inner().inner.clone()).unwrap();
+    extract(
+        &mut pkg,
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .copied()
+            .chain([("new.txt", &b"complete"[..])]),
+    );
+}
+
+#[test]
+fn rollback_on_drop() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let mut writer = pkg
+        .insert("new.txt".into(), Flags::default())
+        .unwrap()
+        .rollback_on_drop();
+    writer.write_all(b"partial").unwrap();
+    drop(writer);
+    assert!(!pkg.contains("new.txt"));
+
+    let (replaced, original) = data::BASIC_EXAMPLE_FILES[0];
+    let mut writer = pkg
+        .overwrite(replaced.to_string(), Flags::default())
+        .unwrap()
+        .rollback_on_drop();
+    writer.write_all(b"replacement").unwrap();
+    drop(writer);
+    extract(&mut pkg, [(replaced, original)].into_iter());
+
+    let mut writer = pkg
+        .insert("new.txt".into(), Flags::default())
+        .unwrap()
+        .rollback_on_drop();
+    writer.write_all(b"complete").unwrap();
+    writer.finish().unwrap();
+
+    pkg.flush().unwrap();
+    let mut pkg = Pkg::parse(std::io::Cursor::new(pkg.inner().get_ref().clone())).unwrap();
+    extract(
+        &mut pkg,
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .copied()
+            .chain([("new.txt", &b"complete"[..])]),
+    );
+}
+
+#[test]
+fn remove_and_reclaim() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let mut data = data::combined_data().collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        data.iter()
+            .step_by(2)
+            .map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter()
+            .skip(1)
+            .step_by(2)
+            .map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    // Remove the largest entry from somewhere in the middle of the data region
+    let (name, _) = data.iter().max_by_key(|(_, d)| d.len()).unwrap().clone();
+    let size = pkg.metadata(&name).unwrap().compressed_size as u64;
+    let len_before = pkg.inner().get_ref().len() as u64;
+    pkg.remove_and_reclaim(&name).unwrap();
+    data.retain(|(n, _)| *n != name);
+
+    assert_eq!(pkg.inner().get_ref().len() as u64, len_before - size);
+    assert!(pkg.free_regions().is_empty());
+    assert!(matches!(
+        pkg.remove_and_reclaim(&name),
+        Err(silpkg::sync::RemoveError::NotFound)
+    ));
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+
+    let mut pkg = Pkg::parse(pkg.inner().clone()).unwrap();
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn remove_and_reclaim_near_offset_limit() {
+    let mut storage = raw_archive(&[("a", 60, 5), ("b", 0xffff_fff0, 0x20)]);
+    storage.extend(b"hello");
+    // The storage is extended to the start of the removed entry, a file keeps that sparse
+    let path = std::env::temp_dir().join(format!("silpkg-reclaim-{}.pkg", std::process::id()));
+    std::fs::write(&path, &storage).unwrap();
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    // The end of "b" doesn't fit into 32 bits
+    let mut pkg = Pkg::parse(file).unwrap();
+    pkg.remove_and_reclaim("b").unwrap();
+    assert!(!pkg.contains("b"));
+    extract(&mut pkg, [("a", b"hello".as_slice())].into_iter());
+}
+
+#[test]
+fn parse_at() {
+    let mut archive = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut archive).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    drop(pkg);
+
+    let prefix = b"\x7fELF not really an executable".repeat(10);
+    let mut storage = prefix.clone();
+    storage.extend_from_slice(archive.get_ref());
+
+    let mut pkg = Pkg::parse_at(std::io::Cursor::new(storage), prefix.len() as u64).unwrap();
+    extract(&mut pkg, data::BASIC_EXAMPLE_FILES.iter().copied());
+    assert!(Pkg::parse(std::io::Cursor::new(pkg.inner().get_ref().get_ref())).is_err());
+
+    add(
+        &mut pkg,
+        Flags::default(),
+        [("new.txt".to_string(), &b"new"[..])].into_iter(),
+    );
+    pkg.remove(data::BASIC_EXAMPLE_FILES[0].0).unwrap();
+    pkg.flush().unwrap();
+
+    let storage = pkg.inner().get_ref().get_ref().clone();
+    assert_eq!(&storage[..prefix.len()], prefix);
+    let mut pkg = Pkg::parse_at(std::io::Cursor::new(storage), prefix.len() as u64).unwrap();
+    extract(
+        &mut pkg,
+        data::BASIC_EXAMPLE_FILES[1..]
+            .iter()
+            .copied()
+            .chain([("new.txt", &b"new"[..])]),
+    );
+}
+
+#[test]
+fn offset_storage_roundtrip() {
+    use silpkg::OffsetStorage;
+
+    let prefix = vec![0xAA; 1000];
+    let mut storage = OffsetStorage::new(std::io::Cursor::new(prefix.clone()), 1000);
+    assert_eq!(storage.seek(std::io::SeekFrom::End(0)).unwrap(), 0);
+    for pos in [std::io::SeekFrom::End(-1), std::io::SeekFrom::Current(-1)] {
+        assert_eq!(
+            storage.seek(pos).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    let data = data::combined_data().collect::<Vec<_>>();
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    for (name, _) in data.iter().step_by(2) {
+        pkg.remove(name).unwrap();
+    }
+    pkg.repack().unwrap();
+    drop(pkg);
+
+    let remaining = data.iter().skip(1).step_by(2).collect::<Vec<_>>();
+    let inner_len = storage.get_ref().get_ref().len() as u64;
+    assert_eq!(storage.get_ref().get_ref()[..1000], prefix);
+    assert_eq!(
+        storage.seek(std::io::SeekFrom::End(0)).unwrap(),
+        inner_len - 1000
+    );
+    // Failed seeks leave the position unchanged
+    storage.seek(std::io::SeekFrom::Start(10)).unwrap();
+    assert!(storage
+        .seek(std::io::SeekFrom::End(-(inner_len as i64)))
+        .is_err());
+    assert_eq!(storage.stream_position().unwrap(), 10);
+
+    let mut pkg = Pkg::parse(&mut storage).unwrap();
+    extract(
+        &mut pkg,
+        remaining.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, remaining.iter().map(|(n, _)| n.as_str()));
+
+    let mut pkg = Pkg::parse_at(storage.into_inner(), 1000).unwrap();
+    extract(
+        &mut pkg,
+        remaining.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+}
+
+#[test]
+fn scan_entries() {
+    let mut storage = std::io::Cursor::new(vec![]);
+    let mut pkg = Pkg::create(&mut storage).unwrap();
+    let data = data::combined_data().collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+    for (name, _) in data.iter().step_by(3) {
+        pkg.remove(name).unwrap();
+    }
+    let mut expected = pkg
+        .entries()
+        .map(|(_, info)| (info.index, info))
+        .collect::<BTreeMap<_, _>>();
+    let slots = pkg.entry_slots().count() as u32;
+    drop(pkg);
+
+    let scan = Pkg::scan_entries(&mut storage, ParseOptions::default()).unwrap();
+    assert_eq!(scan.entry_count(), slots);
+    for info in scan {
+        let info = info.unwrap();
+        let known = expected.remove(&info.index).unwrap();
+        assert_eq!(info.path_hash, known.path_hash);
+        assert_eq!(info.data_offset, known.data_offset);
+        assert_eq!(info.compressed_size, known.compressed_size);
+        assert_eq!(info.uncompressed_size, known.uncompressed_size);
+        assert_eq!(info.compressed, known.compressed);
+    }
+    assert!(expected.is_empty());
+
+    // Stopping early only reads as much of the entry list as needed
+    let mut scan = Pkg::scan_entries(&mut storage, ParseOptions::default()).unwrap();
+    let first = scan.next().unwrap().unwrap();
+    assert_eq!(scan.position(), first.index as u32 + 1);
+
+    let mut garbage = std::io::Cursor::new(b"definitely not a pkg archive".to_vec());
+    assert!(matches!(
+        Pkg::scan_entries(&mut garbage, ParseOptions::default()),
+        Err(silpkg::sync::ParseError::MismatchedMagic)
+    ));
+}
+
+#[test]
+fn shrink_entry_table_keeps_interior_slots() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+
+    let (interior, _) = data::BASIC_EXAMPLE_FILES[1];
+    pkg.remove(interior).unwrap();
+    let indices = pkg
+        .entries()
+        .map(|(path, info)| (path.to_string(), info.index))
+        .collect::<BTreeMap<_, _>>();
+    let first_empty = pkg.entry_slots().position(|(_, info)| info.is_none());
+
+    pkg.shrink_entry_table().unwrap();
+    let slots = pkg.entry_slots().collect::<Vec<_>>();
+    assert_eq!(slots.len(), indices.values().max().unwrap() + 1);
+    assert_eq!(
+        slots.iter().position(|(_, info)| info.is_none()),
+        first_empty
+    );
+    for (path, info) in pkg.entries() {
+        assert_eq!(indices[path], info.index);
+    }
+
+    let mut pkg = Pkg::parse(pkg.inner().clone()).unwrap();
+    extract(
+        &mut pkg,
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .copied()
+            .filter(|(n, _)| *n != interior),
+    );
+}
+
+#[test]
+fn check_writable() {
+    let path = std::env::temp_dir().join(format!("silpkg-writable-{}.pkg", std::process::id()));
+    let file = std::fs::File::create(&path).unwrap();
+    let mut pkg = Pkg::create(file).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    assert!(pkg.is_writable());
+    drop(pkg);
+    let contents = std::fs::read(&path).unwrap();
+
+    let file = std::fs::File::open(&path).unwrap();
+    let mut pkg = Pkg::parse(file).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(pkg.is_writable());
+    assert_eq!(
+        pkg.check_writable().unwrap_err().kind(),
+        std::io::ErrorKind::PermissionDenied
+    );
+    extract(&mut pkg, data::BASIC_EXAMPLE_FILES.iter().copied());
+    // Operations that run into the read-only handle without checking first fail the same way
+    let Err(silpkg::sync::RepackError::Io(err)) = pkg.repack() else {
+        panic!("repacking a read-only file succeeded");
+    };
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+    let mut pkg = Pkg::parse(std::io::Cursor::new(contents.clone())).unwrap();
+    pkg.check_writable().unwrap();
+    assert_eq!(*pkg.inner().get_ref(), contents);
+
+    // Other write failures are passed through as they are
+    let storage = fault::FaultyStorage::new(contents.clone());
+    let trigger = storage.trigger();
+    let mut pkg = Pkg::parse(storage).unwrap();
+    trigger.fail_after(0);
+    assert_eq!(
+        pkg.check_writable().unwrap_err().kind(),
+        std::io::ErrorKind::Other
+    );
+
+    let mut pkg = Pkg::parse_headers_only(std::io::Cursor::new(contents)).unwrap();
+    assert!(!pkg.is_writable());
+    assert_eq!(
+        pkg.check_writable().unwrap_err().kind(),
+        std::io::ErrorKind::Unsupported
+    );
+}
+
+#[test]
+fn canonicalize() {
+    let data = data::combined_data().collect::<Vec<_>>();
+
+    let mut first = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut first,
+        Flags::default(),
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    // Same entries inserted in a different order with some garbage inserted and removed inbetween
+    let mut second = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    for (i, (name, contents)) in data.iter().rev().enumerate() {
+        if i % 3 == 0 {
+            add(
+                &mut second,
+                Flags::default(),
+                [(format!("garbage/{i}"), &b"garbage"[..])].into_iter(),
+            );
+        }
+        add(
+            &mut second,
+            Flags::default(),
+            [(name.to_string(), contents.as_slice())].into_iter(),
+        );
+    }
+    for path in second.path_list() {
+        if path.starts_with("garbage/") {
+            second.remove(&path).unwrap();
+        }
+    }
+    second.preallocate(1000).unwrap();
+
+    assert_ne!(first.inner().get_ref(), second.inner().get_ref());
+    first.canonicalize().unwrap();
+    second.canonicalize().unwrap();
+    assert!(first.inner().get_ref() == second.inner().get_ref());
+    assert_eq!(first.minimum_size(), first.inner().get_ref().len() as u64);
+
+    let mut pkg = Pkg::parse(first.inner().clone()).unwrap();
+    extract(
+        &mut pkg,
+        data.iter().map(|(n, d)| (n.as_str(), d.as_slice())),
+    );
+    list(&pkg, data.iter().map(|(n, _)| n.as_str()));
+}
+
+#[test]
+fn cluster_by_prefix() {
+    let files = [
+        ("root.txt", &b"root"[..]),
+        ("a/b/1.txt", b"ab1"),
+        ("c/1.txt", b"c1"),
+        ("a/1.txt", b"a1"),
+        ("a/c/1.txt", b"ac1"),
+        ("c/2.txt", b"c2"),
+        ("a/b/2.txt", b"ab2"),
+    ];
+    let create = || {
+        let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+        add(
+            &mut pkg,
+            Flags::default(),
+            files.iter().map(|(n, d)| (n.to_string(), *d)),
+        );
+        pkg.remove("c/2.txt").unwrap();
+        pkg
+    };
+    let data_order = |pkg: &Pkg<_>| {
+        let mut entries = pkg
+            .entries()
+            .map(|(path, info)| (info.data_offset, path.to_string()))
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect::<Vec<_>>()
+    };
+
+    let mut pkg = create();
+    pkg.cluster_by_prefix(1).unwrap();
+    assert_eq!(
+        data_order(&pkg),
+        [
+            "root.txt",
+            "a/b/1.txt",
+            "a/1.txt",
+            "a/c/1.txt",
+            "a/b/2.txt",
+            "c/1.txt"
+        ]
+    );
+    assert_eq!(pkg.minimum_size(), pkg.inner().get_ref().len() as u64);
+
+    let mut pkg = create();
+    pkg.cluster_by_prefix(usize::MAX).unwrap();
+    assert_eq!(
+        data_order(&pkg),
+        [
+            "root.txt",
+            "a/1.txt",
+            "a/b/1.txt",
+            "a/b/2.txt",
+            "a/c/1.txt",
+            "c/1.txt"
+        ]
+    );
+
+    let mut pkg = Pkg::parse(std::io::Cursor::new(pkg.inner().get_ref().clone())).unwrap();
+    assert_eq!(pkg.paths().count(), files.len() - 1);
+    extract(
+        &mut pkg,
+        files.into_iter().filter(|(path, _)| *path != "c/2.txt"),
+    );
+}
+
+#[test]
+fn total_sizes() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    assert_eq!(pkg.total_uncompressed_size(), 0);
+    assert_eq!(pkg.total_compressed_size(), 0);
+
+    let data = data::combined_data().collect::<Vec<_>>();
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::new(6)),
+        },
+        data.iter().map(|(n, d)| (n.to_string(), d.as_slice())),
+    );
+
+    let uncompressed = data.iter().map(|(_, d)| d.len() as u64).sum::<u64>();
+    assert_eq!(pkg.total_uncompressed_size(), uncompressed);
+    assert_eq!(
+        pkg.total_compressed_size(),
+        pkg.entries()
+            .map(|(_, info)| info.compressed_size as u64)
+            .sum::<u64>()
+    );
+    assert!(pkg.total_compressed_size() < uncompressed);
+}
+
+#[test]
+fn crash_safety() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    let (stored, compressed) = data::BASIC_EXAMPLE_FILES.split_at(3);
+    add(
+        &mut pkg,
+        Flags::default(),
+        stored.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+    add(
+        &mut pkg,
+        Flags {
+            compression: silpkg::EntryCompression::Deflate(Compression::default()),
+        },
+        compressed.iter().map(|(n, d)| (n.to_string(), *d)),
+    );
+    pkg.remove("fox.txt").unwrap();
+    pkg.flush().unwrap();
+    let base = pkg.inner().get_ref().clone();
+
+    // Long enough to require growing the path region
+    let long_path = "long/".repeat(500);
+    let (target, _) = data::BASIC_EXAMPLE_FILES[2];
+    type Operation<'a> = Box<dyn Fn(&mut Pkg<fault::FaultyStorage>) -> bool + 'a>;
+    let operations: Vec<(&str, Operation)> = vec![
+        (
+            "insert",
+            Box::new(|pkg| {
+                pkg.insert_from("new.txt".into(), Flags::default(), &mut &b"new data"[..])
+                    .is_ok()
+            }),
+        ),
+        (
+            "insert long path",
+            Box::new(|pkg| {
+                pkg.insert_from(long_path.clone(), Flags::default(), &mut &b"new data"[..])
+                    .is_ok()
+            }),
+        ),
+        (
+            "rename",
+            Box::new(|pkg| pkg.rename(target, long_path.clone()).is_ok()),
+        ),
+        ("remove", Box::new(|pkg| pkg.remove(target).is_ok())),
+        ("repack", Box::new(|pkg| pkg.repack().is_ok())),
+        ("cluster", Box::new(|pkg| pkg.cluster_by_prefix(1).is_ok())),
+    ];
+
+    for (name, operation) in operations {
+        let allowed_damage = matches!(name, "repack" | "cluster") as usize;
+        for n in 0.. {
+            let storage = fault::FaultyStorage::new(base.clone());
+            let trigger = storage.trigger();
+            let mut pkg = Pkg::parse(storage).unwrap();
+            trigger.fail_after(n);
+            if operation(&mut pkg) {
+                break;
+            }
+
+            let mut pkg = Pkg::parse(std::io::Cursor::new(pkg.inner().get_ref().to_vec()))
+                .unwrap_or_else(|err| panic!("{name} failing after {n} operations: {err}"));
+
+            // Repacking moves the data of every entry, only the one being moved when the failure
+            // happened may be damaged. Clustering starts with a repack.
+            let damaged = data::BASIC_EXAMPLE_FILES
+                .iter()
+                .filter(|(path, _)| *path != "fox.txt" && *path != target)
+                .filter(|(path, data)| {
+                    let mut out = vec![];
+                    pkg.open(path).unwrap().read_to_end(&mut out).is_err() || out != *data
+                })
+                .count();
+            assert!(
+                damaged <= allowed_damage,
+                "{name} failing after {n} operations damaged {damaged} entries"
+            );
+        }
+    }
+}
+
+#[test]
+fn repair_truncated() {
+    let mut pkg = Pkg::create(std::io::Cursor::new(vec![])).unwrap();
+    add(
+        &mut pkg,
+        Flags::default(),
+        data::BASIC_EXAMPLE_FILES
+            .iter()
+            .map(|(n, d)| (n.to_string(), *d)),
+    );
+    let (last, _) = data::BASIC_EXAMPLE_FILES[data::BASIC_EXAMPLE_FILES.len() - 1];
+    let (second_to_last, _) = data::BASIC_EXAMPLE_FILES[data::BASIC_EXAMPLE_FILES.len() - 2];
+    // Cut into the data of the second to last entry
+    let cut = pkg.metadata(second_to_last).unwrap().data_offset as usize + 10;
+    let mut storage = pkg.inner().get_ref().clone();
+    storage.truncate(cut);
+    let intact = &data::BASIC_EXAMPLE_FILES[..data::BASIC_EXAMPLE_FILES.len() - 2];
+
+    let mut pkg = Pkg::parse(std::io::Cursor::new(storage.clone())).unwrap();
+    assert!(pkg.dropped_entries().is_empty());
+    let mut truncated = vec![last.to_string(), second_to_last.to_string()];
+    truncated.sort();
+    assert_eq!(pkg.check_truncation().unwrap(), truncated);
+    // Reading stops at the end of the storage
+    let (path, data) = intact[0];
+    assert_eq!(pkg.read_entry_owned(path).unwrap().as_slice(), data);
+    assert!(
+        pkg.read_entry_owned(second_to_last)
+            .unwrap()
+            .as_slice()
+            .len()
+            == 10
+    );
+
+    let options = ParseOptions {
+        repair_truncated: true,
+        ..Default::default()
+    };
+    let mut pkg = Pkg::parse_with_options(std::io::Cursor::new(storage), options).unwrap();
+    let mut dropped = pkg.dropped_entries().to_vec();
+    dropped.sort();
+    assert_eq!(dropped, truncated);
+    assert!(pkg.check_truncation().unwrap().is_empty());
+    list(&pkg, intact.iter().map(|(n, _)| *n));
+    extract(&mut pkg, intact.iter().copied());
+
+    // The dropped records are still in the storage, new data could end up inside of them
+    assert!(!pkg.is_writable());
+    assert_eq!(
+        pkg.insert("new".into(), Flags::default())
+            .err()
+            .unwrap()
+            .to_string(),
+        "Archives with dropped truncated entries have to be repacked before they can be modified"
+    );
+
+    pkg.repack().unwrap();
+    assert!(pkg.is_writable());
+    let new: &[u8] = &[b'n'; 4096];
+    pkg.insert_from("new".into(), Flags::default(), &mut &new[..])
+        .unwrap();
+
+    let mut pkg = Pkg::parse(std::io::Cursor::new(pkg.inner().get_ref().clone())).unwrap();
+    let mut expected = intact.to_vec();
+    expected.push(("new", new));
+    list(&pkg, expected.iter().map(|(n, _)| *n));
+    extract(&mut pkg, expected.iter().copied());
+    pkg.repack().unwrap();
+}